@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
 use itertools::Itertools;
 use log::*;
@@ -37,13 +37,23 @@ pub async fn site_create(user: &mut User, use_master_seed: bool) -> Result<(), E
 
 pub async fn rebuild_db(site: &mut Site, db_manager: &mut DbManager) -> Result<(), Error> {
     let has_schema = db_manager.has_schema(site.address());
-    let address = site.address();
+    let address = &site.address().to_string();
     if has_schema.0 {
         let schema = db_manager.load_schema(address).unwrap();
         db_manager.insert_schema(address, schema);
         db_manager.connect_db(address)?;
         db_manager.create_tables(address);
-        db_manager.load_data(address).await;
+        // Data of users, whose content.json is rejected, such as for forged cert, is left out
+        site.load_content().await?;
+        let rejected = site
+            .verify_site(true)
+            .await?
+            .failed()
+            .filter(|(inner_path, _)| *inner_path != "content.json")
+            .filter_map(|(inner_path, _)| inner_path.strip_suffix("content.json"))
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        db_manager.load_data(address, &rejected).await;
     }
     Ok(())
 }
@@ -157,7 +167,9 @@ pub async fn site_need_file(site: &mut Site, inner_path: String) -> Result<(), E
     if !download {
         info!("Inner Path Not Exists in content.json");
     } else {
-        let result = site.need_file(inner_path.clone(), None, None).await;
+        let pool = site.peer_pool();
+        let result = site.need_file(inner_path.clone(), None, &pool).await;
+        site.update_peer_stats(pool.peers());
         if let Err(e) = &result {
             error!("Error : {e:?}");
         } else {
@@ -208,18 +220,33 @@ pub async fn fetch_changes(site: &mut Site) -> Result<(), Error> {
     site.load_content().await?;
     let modified = &site.content(None).unwrap().modified;
     info!("{modified:?}");
-    let changes = site.fetch_changes(1421043090).await?;
+    let pool = site.peer_pool();
+    let changes = site.fetch_changes(1421043090, &pool).await;
+    site.update_peer_stats(pool.peers());
+    let changes = changes?;
     info!("{changes:#?}");
     Ok(())
 }
 
 pub async fn check_site_integrity(site: &mut Site) -> Result<(), Error> {
     site.load_content().await?;
-    let res = site.verify_files(false).await?;
-    if res {
-        info!("Site {} verified", site.address());
+    let report = site.verify_site(false).await?;
+    for (inner_path, status) in report.failed() {
+        warn!("{inner_path} : {status:?}");
+    }
+    if report.is_valid() {
+        info!(
+            "Site {} verified, {} files",
+            site.address(),
+            report.files.len()
+        );
     } else {
-        warn!("Site {} verification failed", site.address());
+        warn!(
+            "Site {} verification failed, {} of {} files",
+            site.address(),
+            report.failed().count(),
+            report.files.len()
+        );
     }
     Ok(())
 }
@@ -1,12 +1,23 @@
-use std::{collections::HashMap, fs::File, io::Read, time::Duration};
+use std::{
+    collections::HashMap,
+    io::SeekFrom,
+    net::{IpAddr, SocketAddr},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use itertools::Itertools;
+use actix::{Addr, Context, Handler, MessageResult};
 use log::*;
 use serde_bytes::ByteBuf;
 use std::time::Instant;
 use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
     net::{TcpListener, TcpStream},
-    sync::mpsc::{channel, Receiver, Sender},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        oneshot,
+    },
 };
 
 use decentnet_protocol::{
@@ -20,78 +31,274 @@ use zeronet_protocol::ZeroConnection;
 use zerucontent::Content;
 
 use crate::{
-    core::{error::Error, io::SiteIO, peer::Peer},
+    core::{
+        error::Error,
+        hashfield::Hashfield,
+        io::SiteIO,
+        peer::{pack_peers, Peer},
+        site::Site,
+    },
+    discovery::set_fileserver_port,
     environment::ENV,
-    net::Protocol,
+    io::utils::current_unix_epoch,
+    net::{is_own_handshake, tls, tor, Protocol},
+    plugins::{
+        site_server::handlers::sites::OptionalFileNeedRequest,
+        tracker::{Tracker, TRACKER_CLEANUP_INTERVAL},
+    },
     SitesController,
 };
 
+/// Max simultaneous connections accepted from a single ip.
+const MAX_CONNECTIONS_PER_IP: usize = 16;
+/// Max peers returned per hash id in findHashIds response.
+const MAX_HASH_ID_PEERS: usize = 20;
+/// Peers an accepted update is re-published to.
+const UPDATE_PUBLISH_LIMIT: usize = 3;
+/// Pending requests buffered between connections and the request dispatcher.
+const REQUEST_QUEUE_SIZE: usize = 512;
+/// Bytes sent per getFile response when requester doesn't ask for less.
+const FILE_BUFF: usize = 512 * 1024;
+/// Inbound connection is closed if peer sends nothing for this long.
+const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Request received from a peer connection, `respond_to` is used to send
+/// response back to the connection which received the request.
+pub struct PeerRequest {
+    pub peer_addr: SocketAddr,
+    /// Fileserver port announced by peer in its handshake.
+    pub fileserver_port: Option<u16>,
+    /// Peer of connection, if it accepts connections.
+    pub peer: Option<Peer>,
+    pub request: ZeroNetRequest,
+    respond_to: oneshot::Sender<ResponseType>,
+}
+
+/// Body shared by requests concerning a site.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SiteRequest {
+    site: String,
+}
+
+/// Runs closure on sites controller actor, so requests share sites with rest of node
+/// while only holding them for as long as closure runs, without any I/O.
+struct WithSites<R>(Box<dyn FnOnce(&mut SitesController) -> R + Send>);
+
+impl<R: 'static> actix::Message for WithSites<R> {
+    type Result = R;
+}
+
+impl<R: 'static> Handler<WithSites<R>> for SitesController {
+    type Result = MessageResult<WithSites<R>>;
+
+    fn handle(&mut self, msg: WithSites<R>, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult((msg.0)(self))
+    }
+}
+
+async fn with_sites<R, F>(sites_controller: &Addr<SitesController>, f: F) -> Result<R, Error>
+where
+    R: Send + 'static,
+    F: FnOnce(&mut SitesController) -> R + Send + 'static,
+{
+    Ok(sites_controller.send(WithSites(Box::new(f))).await?)
+}
+
 pub struct ConnectionController {
     listener: TcpListener,
-    sites_controller: SitesController,
-    conn_len: usize,
-    connections: HashMap<String, usize>,
+    sites_controller: Addr<SitesController>,
+    connections: Arc<std::sync::Mutex<HashMap<IpAddr, usize>>>,
+    /// Bootstrapper serving `announce` requests, enabled with `--tracker`.
+    tracker: Option<Arc<std::sync::Mutex<Tracker>>>,
 }
 
 impl ConnectionController {
-    pub async fn new(sites_controller: SitesController) -> Result<Self, Error> {
+    /// Binds fileserver listener, so its port is announced from the moment sites start.
+    pub fn bind() -> Result<std::net::TcpListener, Error> {
         let ser_addr = format!("{}:{}", ENV.fileserver_ip, ENV.fileserver_port);
+        let listener = std::net::TcpListener::bind(&ser_addr)?;
+        listener.set_nonblocking(true)?;
         info!("Listening on {ser_addr}");
-        let listener = TcpListener::bind(ser_addr).await?;
+        set_fileserver_port(listener.local_addr()?.port());
+        Ok(listener)
+    }
+
+    pub fn new(
+        listener: std::net::TcpListener,
+        sites_controller: Addr<SitesController>,
+    ) -> Result<Self, Error> {
+        let listener = TcpListener::from_std(listener)?;
+        let tracker = if ENV.tracker {
+            info!("Serving announce requests as tracker");
+            let tracker = Tracker::open(&ENV.data_path.join("tracker.db"))?;
+            Some(Arc::new(std::sync::Mutex::new(tracker)))
+        } else {
+            None
+        };
         Ok(Self {
             listener,
             sites_controller,
-            conn_len: 0,
-            connections: HashMap::new(),
+            connections: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            tracker,
         })
     }
 
+    /// Runs fileserver on its own thread and runtime, so blocking reads of peer
+    /// connections don't stall actors and web interface.
+    pub fn spawn(
+        listener: std::net::TcpListener,
+        sites_controller: Addr<SitesController>,
+    ) -> Result<std::thread::JoinHandle<()>, Error> {
+        let handle = std::thread::Builder::new()
+            .name("fileserver".into())
+            .spawn(move || {
+                let runtime = match tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(err) => {
+                        error!("Failed to start fileserver runtime, Error : {err:?}");
+                        return;
+                    }
+                };
+                runtime.block_on(async move {
+                    let res = match Self::new(listener, sites_controller) {
+                        Ok(mut con) => con.run().await,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = res {
+                        error!("Fileserver stopped, Error : {err:?}");
+                    }
+                });
+            })?;
+        Ok(handle)
+    }
+
     pub async fn run(&mut self) -> Result<(), Error> {
+        let (req_tx, req_rx) = channel(REQUEST_QUEUE_SIZE);
+        tokio::spawn(Self::dispatch(self.sites_controller.clone(), req_rx));
+        if let Some(tracker) = self.tracker.clone() {
+            tokio::spawn(Self::cleanup_tracker_periodically(tracker));
+        }
+        tokio::spawn(tor::run_onion());
         loop {
-            let incoming = self.listener.accept().await;
-            if let Ok(stream) = incoming {
-                self.conn_len += 1;
-                if let Ok(peer_addr) = stream.0.peer_addr() {
-                    let peer_addr = peer_addr.ip().to_string();
-                    if self.connections.contains_key(&peer_addr) {
-                        self.connections
-                            .insert(peer_addr.clone(), self.connections[&peer_addr] + 1);
-                        tokio::time::sleep(Duration::from_secs(10)).await;
-                    } else {
-                        self.connections.insert(peer_addr, 1);
-                    }
-                    let (req_tx, mut req_rx) = channel(64);
-                    let (res_tx, mut res_rx) = channel(64);
-                    tokio::spawn(async move {
-                        let _ = Self::handle_connection(stream.0, req_tx, &mut res_rx).await;
+            let (stream, peer_addr) = match self.listener.accept().await {
+                Ok(incoming) => incoming,
+                Err(err) => {
+                    error!("Error Accepting Connection : {err}");
+                    continue;
+                }
+            };
+            if !Self::register_connection(&self.connections, peer_addr.ip()) {
+                debug!("Too many connections from {}, dropping", peer_addr.ip());
+                continue;
+            }
+            let req_tx = req_tx.clone();
+            let connections = self.connections.clone();
+            let tracker = self.tracker.clone();
+            let runtime = tokio::runtime::Handle::current();
+            // Reads of peer connection block, so each connection gets a thread of its own
+            // instead of holding up a runtime worker while peer is slow or idle.
+            tokio::task::spawn_blocking(move || {
+                let res =
+                    runtime.block_on(Self::handle_connection(stream, peer_addr, req_tx, tracker));
+                if let Err(err) = res {
+                    debug!("Connection to {peer_addr} closed with error : {err:?}");
+                }
+                Self::unregister_connection(&connections, peer_addr.ip());
+            });
+        }
+    }
+
+    async fn cleanup_tracker_periodically(tracker: Arc<std::sync::Mutex<Tracker>>) {
+        let mut interval = tokio::time::interval(TRACKER_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match tracker.lock().unwrap().cleanup() {
+                Ok(removed) => debug!("Removed {removed} expired peers from tracker"),
+                Err(err) => error!("Tracker cleanup failed, Error : {err:?}"),
+            }
+        }
+    }
+
+    fn register_connection(
+        connections: &std::sync::Mutex<HashMap<IpAddr, usize>>,
+        ip: IpAddr,
+    ) -> bool {
+        let mut connections = connections.lock().unwrap();
+        let count = connections.entry(ip).or_insert(0);
+        if *count >= MAX_CONNECTIONS_PER_IP {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    fn unregister_connection(connections: &std::sync::Mutex<HashMap<IpAddr, usize>>, ip: IpAddr) {
+        let mut connections = connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&ip);
+            }
+        }
+    }
+
+    /// Receives requests from all connections and handles each of them in its own task,
+    /// so a slow request from one peer doesn't hold up others.
+    async fn dispatch(sites_controller: Addr<SitesController>, mut req_rx: Receiver<PeerRequest>) {
+        while let Some(peer_req) = req_rx.recv().await {
+            let sites_controller = sites_controller.clone();
+            tokio::spawn(async move {
+                let PeerRequest {
+                    peer_addr,
+                    fileserver_port,
+                    peer,
+                    request,
+                    respond_to,
+                } = peer_req;
+                if let Some(peer) = peer.clone() {
+                    let req = request.clone();
+                    let res = with_sites(&sites_controller, move |sites_controller| {
+                        Self::register_peer(sites_controller, peer, &req)
                     });
-                    loop {
-                        let msg = req_rx.recv().await;
-                        if let Some(req) = msg {
-                            let res = self.handle_request(req).await;
-                            let _ = res_tx.send(res).await;
-                        } else {
-                            info!("Connection closed");
-                            break;
-                        }
+                    if let Err(err) = res.await {
+                        error!("Failed to register peer {peer_addr}, Error : {err:?}");
                     }
-                } else {
-                    error!("Error : {}", stream.0.peer_addr().unwrap_err());
                 }
-            };
+                let res = Self::handle_request(
+                    &sites_controller,
+                    peer_addr,
+                    fileserver_port,
+                    peer,
+                    request,
+                )
+                .await;
+                if respond_to.send(res).is_err() {
+                    debug!("Connection to {peer_addr} closed before response was sent");
+                }
+            });
         }
     }
 
     async fn handle_connection(
         stream: TcpStream,
-        req_tx: Sender<ZeroNetRequest>,
-        res_rx: &mut Receiver<ResponseType>,
+        peer_addr: SocketAddr,
+        req_tx: Sender<PeerRequest>,
+        tracker: Option<Arc<std::sync::Mutex<Tracker>>>,
     ) -> Result<(), Error> {
-        let peer_addr = stream.peer_addr()?;
         let stream = stream.into_std()?;
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(CONNECTION_IDLE_TIMEOUT))?;
+        // Kept to upgrade connection to tls, once crypt is agreed on in handshake
+        let socket = stream.try_clone()?;
         let reader = Box::new(stream.try_clone()?);
         let writer = Box::new(stream);
         let mut connection = ZeroConnection::new(reader, writer)?;
+        let mut fileserver_port = None;
+        let mut peer = None;
+        let mut encrypted = false;
 
         loop {
             let request = connection.recv().await;
@@ -99,13 +306,34 @@ impl ConnectionController {
                 let mut protocol = Protocol::new(&mut connection);
                 match request.cmd.as_str() {
                     "handshake" => {
-                        let res = protocol.handshake(request.req_id).await;
+                        let mut crypt = None;
+                        if let Ok(handshake) = request.body::<Handshake>() {
+                            if is_own_handshake(&handshake) {
+                                debug!("Dropping connection from {peer_addr} to ourselves");
+                                break;
+                            }
+                            fileserver_port =
+                                Some(handshake.fileserver_port).filter(|port| *port != 0);
+                            peer = Peer::inbound(peer_addr, &handshake);
+                            if !encrypted {
+                                crypt =
+                                    tls::select_crypt(&handshake.crypt_supported, peer_addr.ip());
+                            }
+                        }
+                        let res = protocol
+                            .handshake_with_crypt(request.req_id, crypt.clone())
+                            .await;
                         if res.is_err() {
                             error!(
                                 "Error Sending Response: \nTo : {} : {:#?}",
                                 peer_addr,
                                 res.unwrap_err()
                             );
+                        } else if crypt.is_some() {
+                            connection = tls::accept(socket.try_clone()?).await?;
+                            // Tls handshake runs on tokio, which leaves socket nonblocking
+                            socket.set_nonblocking(false)?;
+                            encrypted = true;
                         }
                     }
                     "ping" => {
@@ -118,6 +346,20 @@ impl ConnectionController {
                             );
                         }
                     }
+                    "announce" => {
+                        let res = match &tracker {
+                            Some(tracker) => Self::handle_announce(tracker, peer_addr, &request),
+                            None => ResponseType::UnknownCmd,
+                        };
+                        let res = protocol.0.respond(request.req_id, res).await;
+                        if res.is_err() {
+                            error!(
+                                "Error Sending Response: \nTo : {} : {:#?}",
+                                peer_addr,
+                                res.unwrap_err()
+                            );
+                        }
+                    }
                     _cmd => {
                         debug!(
                             "\nFrom : {} : {} : {}",
@@ -128,11 +370,6 @@ impl ConnectionController {
                         let time = Instant::now();
 
                         //Optimisation For Unknown Sites, send direct Error Response instead for channel roundtrip
-                        #[derive(serde::Serialize, serde::Deserialize)]
-                        struct SiteRequest {
-                            site: String,
-                        }
-
                         if request.body::<SiteRequest>().is_err() {
                             let res = Self::unknown_site_response();
                             let site: &str = &request
@@ -147,14 +384,22 @@ impl ConnectionController {
                             debug!("{} Req {} took : {:?}", &request.cmd, &request.req_id, took);
                             continue;
                         }
-                        let res = req_tx.send(request.clone()).await;
-                        if res.is_err() {
+                        let (res_tx, res_rx) = oneshot::channel();
+                        let peer_req = PeerRequest {
+                            peer_addr,
+                            fileserver_port,
+                            peer: peer.clone(),
+                            request: request.clone(),
+                            respond_to: res_tx,
+                        };
+                        if req_tx.send(peer_req).await.is_err() {
                             error!("Channel closed");
+                            break;
                         }
-                        let res = res_rx.recv().await;
+                        let res = res_rx.await;
                         let took = time.elapsed();
                         debug!("{} Req {} took : {:?}", &request.cmd, &request.req_id, took);
-                        if let Some(res) = res {
+                        if let Ok(res) = res {
                             let result = protocol.0.respond(request.req_id, res.clone()).await;
                             if result.is_err() {
                                 error!(
@@ -169,7 +414,7 @@ impl ConnectionController {
                     }
                 }
             } else {
-                error!("."); //("{}", request.unwrap_err());
+                debug!("Connection closed by {peer_addr}");
                 break;
             }
         }
@@ -177,16 +422,38 @@ impl ConnectionController {
         Ok(())
     }
 
-    async fn handle_request(&mut self, req: ZeroNetRequest) -> ResponseType {
+    async fn handle_request(
+        sites_controller: &Addr<SitesController>,
+        peer_addr: SocketAddr,
+        fileserver_port: Option<u16>,
+        peer: Option<Peer>,
+        req: ZeroNetRequest,
+    ) -> ResponseType {
         match req.cmd.as_str() {
-            "pex" => self.handle_pex(req),
-            "getHashfield" => self.get_hashfield(req),
-            "getFile" => self.handle_get_file(req, false),
-            "streamFile" => self.handle_get_file(req, true),
-            "update" => self.handle_update(req),
+            "update" => {
+                Self::handle_update(sites_controller, peer_addr, fileserver_port, req).await
+            }
+            "getFile" => Self::handle_get_file(sites_controller, req, false).await,
+            "streamFile" => Self::handle_get_file(sites_controller, req, true).await,
+            "listModified" => Self::handle_list_modified(sites_controller, req).await,
             _ => {
-                warn!("Unknown cmd {}", req.cmd);
-                ResponseType::UnknownCmd
+                let res = with_sites(sites_controller, move |sites_controller| {
+                    match req.cmd.as_str() {
+                        "pex" => Self::handle_pex(sites_controller, req),
+                        "getHashfield" => Self::get_hashfield(sites_controller, req),
+                        "setHashfield" => Self::set_hashfield(sites_controller, peer, req),
+                        "findHashIds" => Self::handle_find_hash_ids(sites_controller, req),
+                        _ => {
+                            warn!("Unknown cmd {}", req.cmd);
+                            ResponseType::UnknownCmd
+                        }
+                    }
+                });
+                res.await.unwrap_or_else(|err| {
+                    ResponseType::Err(ErrorResponse {
+                        error: format!("{err:?}"),
+                    })
+                })
             }
         }
     }
@@ -200,12 +467,30 @@ impl ConnectionController {
         ResponseType::Err(res)
     }
 
-    fn handle_pex(&mut self, req: ZeroNetRequest) -> ResponseType {
+    /// Adds peer of inbound connection to served site it made `req` about.
+    fn register_peer(sites_controller: &mut SitesController, peer: Peer, req: &ZeroNetRequest) {
+        let Ok(SiteRequest { site }) = req.body::<SiteRequest>() else {
+            return;
+        };
+        if let Some(site) = sites_controller.sites.get_mut(&site)
+            && site.storage.settings.serving
+            && !site.peers.contains_key(&peer.address().to_string())
+        {
+            debug!(
+                "Added inbound peer {} to {}",
+                peer.address(),
+                site.address()
+            );
+            site.add_peer(peer);
+        }
+    }
+
+    fn handle_pex(sites_controller: &mut SitesController, req: ZeroNetRequest) -> ResponseType {
         if let Ok(res) = req.body::<Pex>() {
             let site = &res.site;
             let need = res.need;
-            if self.sites_controller.sites.contains_key(site) {
-                let site = self.sites_controller.sites.get_mut(site).unwrap();
+            if sites_controller.sites.contains_key(site) {
+                let site = sites_controller.sites.get_mut(site).unwrap();
                 let mut peers = res.peers;
                 if let Some(peers_onion) = res.peers_onion {
                     peers.extend(peers_onion);
@@ -229,39 +514,13 @@ impl ConnectionController {
                     site.add_peer(peer);
                 }
                 //TODO! Only send connectable peers instead of all peers
-                let mut retrieved = 0;
                 let peers = site
                     .peers
                     .iter()
-                    .filter_map(|(key, peer)| {
-                        if keys.contains(key) {
-                            None
-                        } else if retrieved < need {
-                            retrieved += 1;
-                            let packed = peer.clone().address().pack();
-                            match peer.address() {
-                                PeerAddr::IPV4(_, _) => Some(("ipv4", packed)),
-                                PeerAddr::IPV6(_, _) => Some(("ipv6", packed)),
-                                PeerAddr::OnionV2(_, _) | PeerAddr::OnionV3(_, _) => {
-                                    Some(("onion", packed))
-                                }
-                            }
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>();
-                let mut ip_v4 = vec![];
-                let mut ip_v6 = vec![];
-                let mut onion = vec![];
-                for (key, packed) in peers {
-                    match key {
-                        "ipv4" => ip_v4.push(ByteBuf::from(packed)),
-                        "ipv6" => ip_v6.push(ByteBuf::from(packed)),
-                        "onion" => onion.push(ByteBuf::from(packed)),
-                        _ => unimplemented!(),
-                    }
-                }
+                    .filter(|(key, _)| !keys.contains(key))
+                    .take(need)
+                    .map(|(_, peer)| peer);
+                let (ip_v4, ip_v6, onion) = pack_peers(peers);
                 ResponseType::Pex(builders::response::pex(ip_v4, ip_v6, onion))
             } else {
                 Self::unknown_site_response()
@@ -272,11 +531,52 @@ impl ConnectionController {
         }
     }
 
-    fn get_hashfield(&mut self, req: ZeroNetRequest) -> ResponseType {
+    /// Registers requester for announced hashes and returns their other peers.
+    /// Announced onions are ignored, as their onion_signs can't be verified yet.
+    fn handle_announce(
+        tracker: &std::sync::Mutex<Tracker>,
+        peer_addr: SocketAddr,
+        req: &ZeroNetRequest,
+    ) -> ResponseType {
+        let Ok(announce) = req.body::<Announce>() else {
+            error!("Invalid Announce Request {req:?}");
+            return ResponseType::InvalidRequest;
+        };
+        // Unverified onions may not be owned by announcer, so only its ip is registered.
+        if !announce.onions.is_empty() {
+            debug!("Ignoring onions announced by {peer_addr}");
+        }
+        let mut add = vec![];
+        if announce.port > 0
+            && let Ok(addr) =
+                PeerAddr::parse(SocketAddr::new(peer_addr.ip(), announce.port).to_string())
+            && announce.add.iter().any(|t| t == Tracker::addr_type(&addr))
+        {
+            add.push(addr);
+        }
+        let res = tracker.lock().unwrap().announce(
+            &announce.hashes,
+            &add,
+            &announce.need_types,
+            announce.need_num,
+        );
+        match res {
+            Ok(peers) => ResponseType::Announce(AnnounceResponse { peers }),
+            Err(err) => {
+                error!("Announce from {peer_addr} failed, Error : {err:?}");
+                ResponseType::Err(ErrorResponse {
+                    error: "Announce failed".into(),
+                })
+            }
+        }
+    }
+
+    fn get_hashfield(sites_controller: &mut SitesController, req: ZeroNetRequest) -> ResponseType {
         if let Ok(res) = req.body::<GetHashfield>() {
             let site = &res.site;
-            if self.sites_controller.sites.contains_key(site) {
-                unimplemented!();
+            if let Some(site) = sites_controller.sites.get(site) {
+                let hashfield_raw = ByteBuf::from(site.hashfield().to_raw());
+                ResponseType::GetHashfield(GetHashfieldResponse { hashfield_raw })
             } else {
                 Self::unknown_site_response()
             }
@@ -286,128 +586,441 @@ impl ConnectionController {
         }
     }
 
-    fn handle_get_file(&mut self, req: ZeroNetRequest, streaming: bool) -> ResponseType {
-        if let Ok(res) = req.body::<GetFile>() {
+    /// Stores hashfield of inbound `peer`, which is only known if it told its fileserver port.
+    fn set_hashfield(
+        sites_controller: &mut SitesController,
+        peer: Option<Peer>,
+        req: ZeroNetRequest,
+    ) -> ResponseType {
+        if let Ok(res) = req.body::<SetHashfield>() {
             let site = &res.site;
-            if self.sites_controller.sites.contains_key(site) {
-                let site = self.sites_controller.sites.get_mut(site).unwrap();
-                let inner_path = &res.inner_path;
-                match site.get_path(inner_path) {
-                    Ok(path) => {
-                        let location = res.location;
-                        let read_bytes = res.read_bytes.unwrap_or(512 * 1024);
-                        let file_size = res.file_size;
-                        let file = File::open(path).unwrap();
-                        #[allow(clippy::unbuffered_bytes)]
-                        let bytes = file.bytes();
-                        let file_size_actual = bytes.size_hint().1.unwrap();
-                        if location > file_size_actual {
-                            ResponseType::Err(ErrorResponse {
-                                error: "File read error, Bad file location".to_string(),
-                            })
-                        } else if file_size > file_size_actual {
-                            ResponseType::Err(ErrorResponse {
-                                error: "File read error, Bad file size".to_string(),
-                            })
-                        } else if read_bytes > file_size_actual {
-                            ResponseType::Err(ErrorResponse {
-                                error: "File read error, File size does not match".to_string(),
-                            })
-                        } else {
-                            let bytes = bytes
-                                .skip(location)
-                                .take(read_bytes)
-                                .filter_map(|a| a.ok())
-                                .collect_vec();
-                            if streaming {
-                                ResponseType::StreamFile(
-                                    builders::response::stream_file(
-                                        bytes.len(),
-                                        location,
-                                        file_size,
-                                    ),
-                                    ByteBuf::from(bytes),
-                                )
-                            } else {
-                                ResponseType::GetFile(builders::response::get_file(
-                                    ByteBuf::from(bytes),
-                                    file_size_actual,
-                                    location + read_bytes,
-                                ))
-                            }
-                        }
-                    }
-                    Err(err) => ResponseType::Err(ErrorResponse {
-                        error: format!("{err:?}"),
-                    }),
+            if let Some(site) = sites_controller.sites.get_mut(site) {
+                let Some(peer) = peer else {
+                    debug!("Ignoring setHashfield of peer with unknown fileserver port");
+                    return ResponseType::Err(ErrorResponse {
+                        error: "Unknown fileserver port".into(),
+                    });
+                };
+                let hashfield = Hashfield::from_raw(&res.hashfield_raw);
+                let key = peer.address().to_string();
+                if !site.peers.contains_key(&key) {
+                    site.add_peer(peer);
                 }
+                site.peers.get_mut(&key).unwrap().set_hashfield(hashfield);
+                ResponseType::Ok(OkResponse {
+                    ok: "Updated".into(),
+                })
             } else {
                 Self::unknown_site_response()
             }
         } else {
-            error!("Invalid GetFile Request {req:?}");
+            error!("Invalid SetHashfield Request {req:?}");
             ResponseType::InvalidRequest
         }
     }
 
-    fn handle_update(&mut self, req: ZeroNetRequest) -> ResponseType {
-        if let Ok(res) = req.body::<Update>() {
+    fn handle_find_hash_ids(
+        sites_controller: &mut SitesController,
+        req: ZeroNetRequest,
+    ) -> ResponseType {
+        if let Ok(res) = req.body::<FindHashIds>() {
             let site = &res.site;
-            if self.sites_controller.sites.contains_key(site) {
-                let inner_path = &res.inner_path;
-                let content_modified = res.modified;
-                if !inner_path.ends_with("content.json") {
-                    return ResponseType::Err(ErrorResponse {
-                        error: "Only content.json update allowed".to_string(),
-                    });
-                }
-                let validate_content = {
-                    let site = self.sites_controller.sites.get(site).unwrap();
-                    if !site.inner_content_exists(inner_path) {
-                        false
-                    } else {
-                        let exists_in_includes = site
-                            .content(None)
-                            .unwrap()
-                            .includes
-                            .keys()
-                            .any(|k| k.ends_with(inner_path));
-                        if exists_in_includes {
-                            true
-                        } else {
-                            let site_content_modified =
-                                site.content(Some(inner_path)).unwrap().modified.clone();
-                            content_modified > Into::<usize>::into(site_content_modified)
-                        }
+            if let Some(site) = sites_controller.sites.get(site) {
+                let my_hashfield = site.hashfield();
+                let mut peers = HashMap::new();
+                let mut peers_ipv6 = HashMap::new();
+                let mut peers_onion = HashMap::new();
+                let mut my = vec![];
+                for hash_id in res.hash_ids {
+                    if my_hashfield.has_hash_id(hash_id) {
+                        my.push(hash_id);
+                    }
+                    let holders = site
+                        .peers
+                        .values()
+                        .filter(|peer| peer.hashfield().has_hash_id(hash_id))
+                        .take(MAX_HASH_ID_PEERS);
+                    let (ip_v4, ip_v6, onion) = pack_peers(holders);
+                    if !ip_v4.is_empty() {
+                        peers.insert(hash_id, ip_v4);
+                    }
+                    if !ip_v6.is_empty() {
+                        peers_ipv6.insert(hash_id, ip_v6);
+                    }
+                    if !onion.is_empty() {
+                        peers_onion.insert(hash_id, onion);
                     }
-                };
-                if !validate_content {
-                    return ResponseType::Ok(OkResponse {
-                        ok: "File not changed".to_string(),
-                    });
-                }
-                let body = res.body;
-                if body.is_empty() {
-                    unimplemented!()
-                }
-                let content = Content::from_buf(body);
-                //TODO!
-                if let Ok(_content) = content {
-                    let _site = self.sites_controller.sites.get_mut(site).unwrap();
-                    ResponseType::Ok(OkResponse {
-                        ok: "File updated".to_string(),
-                    })
-                } else {
-                    ResponseType::Err(ErrorResponse {
-                        error: "File invalid JSON".to_string(),
-                    })
                 }
+                ResponseType::FindHashIds(FindHashIdsResponse {
+                    peers,
+                    peers_ipv6,
+                    peers_onion,
+                    my,
+                })
             } else {
                 Self::unknown_site_response()
             }
         } else {
-            error!("Error Parsing Request Body: Invalid Update Request {req:?}");
+            error!("Invalid FindHashIds Request {req:?}");
             ResponseType::InvalidRequest
         }
     }
+
+    /// Site path of `site` if we have it, looked up without holding sites while reading files.
+    async fn site_path(
+        sites_controller: &Addr<SitesController>,
+        site: String,
+    ) -> Result<Option<PathBuf>, Error> {
+        with_sites(sites_controller, move |sites_controller| {
+            sites_controller
+                .sites
+                .get(&site)
+                .map(|site| site.site_path())
+        })
+        .await
+    }
+
+    /// Reads up to `read_bytes` of file from `location`, along with size of whole file.
+    async fn read_file_part(
+        path: &Path,
+        location: usize,
+        read_bytes: usize,
+    ) -> Result<(Vec<u8>, usize), Error> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len() as usize;
+        if location > size {
+            return Err(Error::Err("Bad file location".into()));
+        }
+        file.seek(SeekFrom::Start(location as u64)).await?;
+        let mut bytes = vec![];
+        file.take(read_bytes as u64).read_to_end(&mut bytes).await?;
+        Ok((bytes, size))
+    }
+
+    /// Path of `inner_path` in `site_path`, or None if it's absolute or leads out of site dir.
+    async fn site_file_path(site_path: &Path, inner_path: &str) -> Result<Option<PathBuf>, Error> {
+        let allowed = Path::new(inner_path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !allowed {
+            return Ok(None);
+        }
+        let site_path = tokio::fs::canonicalize(site_path).await?;
+        let path = tokio::fs::canonicalize(site_path.join(inner_path)).await?;
+        Ok(path.starts_with(&site_path).then_some(path))
+    }
+
+    async fn handle_get_file(
+        sites_controller: &Addr<SitesController>,
+        req: ZeroNetRequest,
+        streaming: bool,
+    ) -> ResponseType {
+        let Ok(res) = req.body::<GetFile>() else {
+            error!("Invalid GetFile Request {req:?}");
+            return ResponseType::InvalidRequest;
+        };
+        let site_path = match Self::site_path(sites_controller, res.site.clone()).await {
+            Ok(Some(site_path)) => site_path,
+            Ok(None) => return Self::unknown_site_response(),
+            Err(err) => {
+                return ResponseType::Err(ErrorResponse {
+                    error: format!("{err:?}"),
+                });
+            }
+        };
+        let inner_path = &res.inner_path;
+        let path = match Self::site_file_path(&site_path, inner_path).await {
+            Ok(Some(path)) => path,
+            Ok(None) => {
+                return ResponseType::Err(ErrorResponse {
+                    error: format!("Path Not Allowed: {inner_path}"),
+                });
+            }
+            Err(err) => {
+                return ResponseType::Err(ErrorResponse {
+                    error: format!("File read error, {err}"),
+                });
+            }
+        };
+        let location = res.location;
+        let read_bytes = res.read_bytes.unwrap_or(FILE_BUFF);
+        let (bytes, size) = match Self::read_file_part(&path, location, read_bytes).await {
+            Ok(part) => part,
+            Err(err) => {
+                return ResponseType::Err(ErrorResponse {
+                    error: format!("File read error, {err}"),
+                });
+            }
+        };
+        // Whole file is sent at once, so its size has to be what requester expects
+        if size <= read_bytes && res.file_size > 0 && res.file_size != size {
+            return ResponseType::Err(ErrorResponse {
+                error: format!(
+                    "File read error, File size does not match: {}B != {size}B",
+                    res.file_size
+                ),
+            });
+        }
+        if streaming {
+            ResponseType::StreamFile(
+                builders::response::stream_file(bytes.len(), location, size),
+                ByteBuf::from(bytes),
+            )
+        } else {
+            let next_location = location + bytes.len();
+            ResponseType::GetFile(builders::response::get_file(
+                ByteBuf::from(bytes),
+                size,
+                next_location,
+            ))
+        }
+    }
+
+    async fn handle_list_modified(
+        sites_controller: &Addr<SitesController>,
+        req: ZeroNetRequest,
+    ) -> ResponseType {
+        let Ok(res) = req.body::<ListModified>() else {
+            error!("Invalid ListModified Request {req:?}");
+            return ResponseType::InvalidRequest;
+        };
+        // Contents are read from disk on a copy of site, so sites aren't held meanwhile
+        let site = with_sites(sites_controller, move |sites_controller| {
+            sites_controller.sites.get(&res.site).cloned()
+        });
+        let site = match site.await {
+            Ok(Some(site)) => site,
+            Ok(None) => return Self::unknown_site_response(),
+            Err(err) => {
+                return ResponseType::Err(ErrorResponse {
+                    error: format!("{err:?}"),
+                });
+            }
+        };
+        match site.list_modified_contents(res.since).await {
+            Ok(modified_files) => {
+                ResponseType::ListModified(builders::response::list_modified(modified_files))
+            }
+            Err(err) => ResponseType::Err(ErrorResponse {
+                error: format!("{err:?}"),
+            }),
+        }
+    }
+
+    async fn handle_update(
+        sites_controller: &Addr<SitesController>,
+        peer_addr: SocketAddr,
+        fileserver_port: Option<u16>,
+        req: ZeroNetRequest,
+    ) -> ResponseType {
+        let Ok(res) = req.body::<Update>() else {
+            error!("Error Parsing Request Body: Invalid Update Request {req:?}");
+            return ResponseType::InvalidRequest;
+        };
+        let inner_path = res.inner_path.clone();
+        if !inner_path.ends_with("content.json") {
+            return ResponseType::Err(ErrorResponse {
+                error: "Only content.json update allowed".to_string(),
+            });
+        }
+        // Work on a copy of site, so signature checks and downloads don't hold the lock
+        let site_addr = res.site.clone();
+        let site = with_sites(sites_controller, move |sites_controller| {
+            sites_controller.sites.get(&site_addr).cloned()
+        });
+        let mut site = match site.await {
+            Ok(Some(site)) => site,
+            Ok(None) => return Self::unknown_site_response(),
+            Err(err) => {
+                return ResponseType::Err(ErrorResponse {
+                    error: format!("{err:?}"),
+                });
+            }
+        };
+        if let Err(err) = site.load_parent_contents(&inner_path).await {
+            debug!("Failed to load parent contents of {inner_path}, Error : {err:?}");
+        }
+        let old_content = site.content(Some(&inner_path)).cloned();
+        if let Some(old_content) = &old_content
+            && res.modified <= Into::<usize>::into(old_content.modified.clone())
+        {
+            return ResponseType::Ok(OkResponse {
+                ok: "File not changed".to_string(),
+            });
+        }
+        let body = if res.body.is_empty() {
+            // Sender only sent diffs, fetch new content.json from it
+            match Self::download_from_sender(&site, peer_addr, fileserver_port, &inner_path).await {
+                Ok(body) => body,
+                Err(err) => {
+                    return ResponseType::Err(ErrorResponse {
+                        error: format!("Download failed : {err:?}"),
+                    });
+                }
+            }
+        } else {
+            res.body
+        };
+        let Ok(content) = Content::from_buf(body.clone()) else {
+            return ResponseType::Err(ErrorResponse {
+                error: "File invalid JSON".to_string(),
+            });
+        };
+        let modified: usize = content.modified.clone().into();
+        if modified as u64 > current_unix_epoch() + 60 * 60 {
+            return ResponseType::Err(ErrorResponse {
+                error: "File invalid: Modify timestamp is in the far future".to_string(),
+            });
+        }
+        if let Err(err) = site.update_inner_content(&inner_path, content.clone(), body.len()) {
+            warn!("Invalid update of {inner_path} from {peer_addr}, Error : {err:?}");
+            let (site_addr, bad_file, reason) =
+                (res.site.clone(), inner_path.clone(), err.to_string());
+            let recorded = with_sites(sites_controller, move |sites_controller| {
+                if let Some(site) = sites_controller.sites.get_mut(&site_addr) {
+                    site.add_bad_file(&bad_file, reason);
+                }
+            });
+            if let Err(record_err) = recorded.await {
+                error!("Failed to record bad file {inner_path}, Error : {record_err:?}");
+            }
+            return ResponseType::Err(ErrorResponse {
+                error: format!("File invalid : {err:?}"),
+            });
+        }
+        // Checked again against live site, as another update may have been applied meanwhile,
+        // writing and applying it in the same call, so an older update can't overwrite it.
+        let (site_addr, updated_path, updated_body) =
+            (res.site.clone(), inner_path.clone(), body.clone());
+        let applied = with_sites(sites_controller, move |sites_controller| {
+            let Some(site) = sites_controller.sites.get_mut(&site_addr) else {
+                return Ok(None);
+            };
+            if let Some(live_content) = site.content(Some(&updated_path))
+                && modified <= Into::<usize>::into(live_content.modified.clone())
+            {
+                return Ok(Some(false));
+            }
+            Self::write_content(site, &updated_path, &updated_body)?;
+            site.modify_content(Some(&updated_path), content);
+            site.remove_bad_file(&updated_path);
+            Ok::<_, Error>(Some(true))
+        });
+        match applied.await.and_then(|res| res) {
+            Ok(Some(true)) => {}
+            Ok(Some(false)) => {
+                return ResponseType::Ok(OkResponse {
+                    ok: "File not changed".to_string(),
+                });
+            }
+            Ok(None) => return Self::unknown_site_response(),
+            Err(err) => {
+                error!("Failed to save {inner_path}, Error : {err:?}");
+                return ResponseType::Err(ErrorResponse {
+                    error: "File write error".to_string(),
+                });
+            }
+        }
+        let sender = fileserver_port.map(|port| SocketAddr::new(peer_addr.ip(), port).to_string());
+        let diffs = res.diffs;
+        let sites_controller = sites_controller.clone();
+        tokio::spawn(async move {
+            let pool = site.peer_pool();
+            site.download_changed_files(&inner_path, old_content.as_ref(), &diffs, &pool)
+                .await;
+            let published = site
+                .publish(
+                    &inner_path,
+                    body,
+                    diffs,
+                    modified,
+                    UPDATE_PUBLISH_LIMIT,
+                    sender.as_deref(),
+                    &pool,
+                )
+                .await;
+            debug!("Published {inner_path} update to {published} peers");
+            let site_addr = site.address().to_string();
+            for optional_path in site.downloadable_optional_files(&inner_path, old_content.as_ref())
+            {
+                sites_controller.do_send(OptionalFileNeedRequest {
+                    address: site_addr.clone(),
+                    inner_path: optional_path,
+                });
+            }
+            let res = with_sites(&sites_controller, move |sites_controller| {
+                if let Some(site) = sites_controller.sites.get_mut(site.address()) {
+                    site.update_peer_stats(pool.peers());
+                }
+            });
+            if let Err(err) = res.await {
+                error!("Failed to update peer stats of {site_addr}, Error : {err:?}");
+            }
+        });
+        ResponseType::Ok(OkResponse {
+            ok: format!("Thanks, file {} updated!", res.inner_path),
+        })
+    }
+
+    async fn download_from_sender(
+        site: &Site,
+        peer_addr: SocketAddr,
+        fileserver_port: Option<u16>,
+        inner_path: &str,
+    ) -> Result<ByteBuf, Error> {
+        let Some(port) = fileserver_port else {
+            return Err(Error::Err("Sender fileserver port unknown".into()));
+        };
+        let addr = SocketAddr::new(peer_addr.ip(), port).to_string();
+        let Ok(addr) = PeerAddr::parse(addr) else {
+            return Err(Error::Err(format!("Invalid sender address {peer_addr}")));
+        };
+        let mut peer = Peer::new(addr);
+        peer.connect_handshake().await?;
+        site.download_file_from_peer(inner_path.into(), None, &mut peer)
+            .await
+    }
+
+    fn write_content(site: &Site, inner_path: &str, body: &[u8]) -> Result<(), Error> {
+        let path = site.site_path().join(inner_path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, body)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SITE_PATH: &str = "tests/data/15UYrA7aXr2Nto1Gg4yWXpY3EAJwafMTNk";
+
+    #[tokio::test]
+    async fn test_site_file_path() {
+        let site_path = Path::new(SITE_PATH);
+        let path = ConnectionController::site_file_path(site_path, "content.json")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(path.ends_with("content.json"));
+
+        let absolute = std::fs::canonicalize("Cargo.toml").unwrap();
+        let absolute = absolute.to_str().unwrap();
+        for inner_path in [
+            "/etc/passwd",
+            absolute,
+            "../../../Cargo.toml",
+            "data/../../content.json",
+        ] {
+            let path = ConnectionController::site_file_path(site_path, inner_path).await;
+            assert!(path.unwrap().is_none(), "{inner_path} is allowed");
+        }
+        assert!(
+            ConnectionController::site_file_path(site_path, "missing.json")
+                .await
+                .is_err()
+        );
+    }
 }
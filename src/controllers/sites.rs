@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use actix::{Actor, Addr};
+use actix::{Actor, Addr, AsyncContext, Context, Recipient, WrapFuture};
 use futures::executor::block_on;
 use itertools::Itertools;
 use log::*;
@@ -15,8 +15,13 @@ use crate::{
         io::SiteIO,
         site::{models::SiteStorage, Site},
     },
+    discovery::{
+        announcer::{Announcer, AnnouncerChanged},
+        local::LocalDiscovery,
+    },
     environment::{ENV, SITE_STORAGE},
     io::{db::DbManager, utils::current_unix_epoch},
+    plugins::optional_manager::optional_limit_bytes,
     utils::to_json_value,
 };
 
@@ -28,6 +33,16 @@ pub async fn run() -> Result<Addr<SitesController>, Error> {
     site_controller
         .extend_sites_from_sitedata(site_storage.clone())
         .await;
+    site_controller.load_all_peers();
+    site_controller.bind_local_discovery();
+    site_controller.optional_limit = match ENV.optional_limit {
+        Some(limit) => optional_limit_bytes(limit),
+        None => site_controller.saved_optional_limit(),
+    };
+    site_controller.sync_all_optional_files().await;
+    if let Err(err) = site_controller.check_optional_limit() {
+        error!("Failed to check optional limit, Error : {err:?}");
+    }
     for site in site_storage.keys().clone() {
         if let Some(addr) = site_controller.get_site_addr(site).cloned() {
             site_controller.get(&addr)?;
@@ -44,6 +59,13 @@ pub struct SitesController {
     pub nonce: HashMap<String, Address>,
     pub sites_changed: u64,
     pub db_manager: DbManager,
+    pub announcer: Announcer,
+    /// Websockets notified with announce results of their site.
+    pub announcer_listeners: Vec<Recipient<AnnouncerChanged>>,
+    /// Set once local discovery socket is bound, see [`SitesController::start_local_discovery`].
+    pub local_discovery: Option<LocalDiscovery>,
+    /// Total size of downloaded optional files of all sites in bytes, kept by evicting them.
+    pub optional_limit: u64,
 }
 
 impl SitesController {
@@ -55,6 +77,10 @@ impl SitesController {
             ajax_keys: HashMap::new(),
             nonce: HashMap::new(),
             sites_changed: current_unix_epoch(),
+            announcer: Announcer::default(),
+            announcer_listeners: vec![],
+            local_discovery: None,
+            optional_limit: u64::MAX,
         }
     }
 
@@ -129,6 +155,24 @@ impl SitesController {
         self.sites.get_mut(site_addr)
     }
 
+    /// Saves storage of site to sites.json in background, keeping settings across restarts.
+    pub fn save_site_storage(&self, site_addr: &str, ctx: &mut Context<Self>) {
+        let Some(site) = self.sites.get(site_addr).cloned() else {
+            return;
+        };
+        ctx.spawn(
+            async move {
+                if let Err(err) = site.save_storage().await {
+                    error!(
+                        "Failed to save storage of {}, Error : {err:?}",
+                        site.address()
+                    );
+                }
+            }
+            .into_actor(self),
+        );
+    }
+
     pub fn remove_site(&mut self, address: &str) {
         self.sites.remove(address);
         self.update_sites_changed();
@@ -142,6 +186,9 @@ impl SitesController {
                 site.modify_storage(site_storage.clone());
                 let res = site.load_content().await;
                 if res.is_ok() {
+                    if let Err(err) = site.update_hashfield().await {
+                        warn!("Failed to build hashfield for {address}, Error: {err:?}");
+                    }
                     self.sites.insert(address, site.clone());
                     self.nonce
                         .insert(site_storage.keys.wrapper_key, site.addr().clone());
@@ -0,0 +1,126 @@
+use std::collections::BTreeSet;
+
+use base64::{engine::general_purpose, Engine as _};
+
+/// Compact set of optional file hash ids, used by `getHashfield`/`setHashfield`.
+/// Hash id of a file is first 4 hex chars of its sha512 hash.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Hashfield {
+    hash_ids: BTreeSet<u16>,
+}
+
+impl Hashfield {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn hash_id(sha512: &str) -> Option<u16> {
+        u16::from_str_radix(sha512.get(0..4)?, 16).ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash_ids.is_empty()
+    }
+
+    pub fn hash_ids(&self) -> impl Iterator<Item = &u16> {
+        self.hash_ids.iter()
+    }
+
+    pub fn append_hash(&mut self, sha512: &str) -> bool {
+        match Self::hash_id(sha512) {
+            Some(hash_id) => self.append_hash_id(hash_id),
+            None => false,
+        }
+    }
+
+    pub fn append_hash_id(&mut self, hash_id: u16) -> bool {
+        self.hash_ids.insert(hash_id)
+    }
+
+    pub fn remove_hash(&mut self, sha512: &str) -> bool {
+        match Self::hash_id(sha512) {
+            Some(hash_id) => self.remove_hash_id(hash_id),
+            None => false,
+        }
+    }
+
+    pub fn remove_hash_id(&mut self, hash_id: u16) -> bool {
+        self.hash_ids.remove(&hash_id)
+    }
+
+    pub fn has_hash(&self, sha512: &str) -> bool {
+        Self::hash_id(sha512).is_some_and(|hash_id| self.has_hash_id(hash_id))
+    }
+
+    pub fn has_hash_id(&self, hash_id: u16) -> bool {
+        self.hash_ids.contains(&hash_id)
+    }
+
+    /// Parses `hashfield_raw` bytes, array of little endian u16 hash ids.
+    pub fn from_raw(bytes: &[u8]) -> Self {
+        let hash_ids = bytes
+            .chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+            .collect();
+        Self { hash_ids }
+    }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        self.hash_ids
+            .iter()
+            .flat_map(|hash_id| hash_id.to_le_bytes())
+            .collect()
+    }
+
+    /// Hashfield is stored in `SiteCache` as base64 string of raw bytes.
+    pub fn from_base64(string: &str) -> Self {
+        match general_purpose::STANDARD.decode(string) {
+            Ok(bytes) => Self::from_raw(&bytes),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn to_base64(&self) -> String {
+        general_purpose::STANDARD.encode(self.to_raw())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA512: &str = "31af951ddc29b710e8f2bb9f434b2f211c9b8cbdcd594270bb6c1e748b8f9821";
+
+    #[test]
+    fn test_hash_id() {
+        assert_eq!(Hashfield::hash_id(SHA512), Some(0x31af));
+        assert_eq!(Hashfield::hash_id("31a"), None);
+        assert_eq!(Hashfield::hash_id("zzzz"), None);
+    }
+
+    #[test]
+    fn test_append_and_remove() {
+        let mut hashfield = Hashfield::new();
+        assert!(hashfield.append_hash(SHA512));
+        assert!(!hashfield.append_hash(SHA512));
+        assert!(hashfield.has_hash(SHA512));
+        assert!(hashfield.has_hash_id(0x31af));
+        assert!(hashfield.remove_hash(SHA512));
+        assert!(hashfield.is_empty());
+    }
+
+    #[test]
+    fn test_raw_roundtrip() {
+        let mut hashfield = Hashfield::new();
+        hashfield.append_hash_id(0x31af);
+        hashfield.append_hash_id(0x0001);
+        let raw = hashfield.to_raw();
+        assert_eq!(raw, vec![0x01, 0x00, 0xaf, 0x31]);
+        assert_eq!(Hashfield::from_raw(&raw), hashfield);
+        assert_eq!(Hashfield::from_base64(&hashfield.to_base64()), hashfield);
+    }
+}
@@ -1,6 +1,7 @@
 pub mod address;
 pub mod discovery;
 pub mod error;
+pub mod hashfield;
 pub mod io;
 pub mod peer;
 pub mod schema;
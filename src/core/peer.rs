@@ -1,15 +1,37 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, net::SocketAddr, sync::Arc, time::Instant};
 
-use super::error::Error;
-use decentnet_protocol::address::PeerAddr as PeerAddress;
+use super::{error::Error, hashfield::Hashfield};
+use crate::{
+    environment::ENV,
+    net::{
+        is_own_handshake,
+        socks::{socks5_connect, socks5_connect_async, split_host_port, use_proxy},
+        tls::{self, CRYPT_TLS_RSA},
+        Protocol,
+    },
+};
+use decentnet_protocol::{
+    address::PeerAddr as PeerAddress, interface::RequestImpl, templates::Handshake,
+};
 use log::*;
+use serde_bytes::ByteBuf;
 use time::{Duration, OffsetDateTime};
 use zeronet_protocol::ZeroConnection;
 
+/// Time a single request to peer may take before it's counted as failed.
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+/// Peers with more consecutive errors than this are considered bad.
+const MAX_ERRORS: usize = 5;
+/// Peers with reputation below this are considered bad.
+const MIN_REPUTATION: isize = -10;
+const MAX_REPUTATION: isize = 100;
+
 #[derive(Clone)]
 pub struct Peer {
     address: PeerAddress,
     connection: Option<ZeroConnection>,
+    /// Socket of plain connection, until it's upgraded to tls.
+    socket: Option<Arc<std::net::TcpStream>>,
     reputation: isize,
     time_found: OffsetDateTime,
     time_added: OffsetDateTime,
@@ -19,6 +41,13 @@ pub struct Peer {
     download_time: Duration,
     bad_files: usize,
     errors: usize,
+    hashfield: Hashfield,
+    time_hashfield: Option<OffsetDateTime>,
+    time_pex: Option<OffsetDateTime>,
+    peer_id: Option<String>,
+    version: Option<String>,
+    rev: Option<usize>,
+    onion: Option<String>,
 }
 
 impl Peer {
@@ -36,6 +65,12 @@ impl Peer {
 
     pub fn set_connection(&mut self, connection: ZeroConnection) {
         self.connection = Some(connection);
+        self.socket = None;
+    }
+
+    pub fn disconnect(&mut self) {
+        self.connection = None;
+        self.socket = None;
     }
 
     pub fn reputation(&self) -> isize {
@@ -73,6 +108,156 @@ impl Peer {
     pub fn last_content_json_update(&self) -> OffsetDateTime {
         self.last_content_json_update
     }
+
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    pub fn bad_files(&self) -> usize {
+        self.bad_files
+    }
+
+    pub fn download_bytes(&self) -> usize {
+        self.download_bytes
+    }
+
+    /// Average download speed in bytes per second.
+    pub fn download_speed(&self) -> usize {
+        let secs = self.download_time.as_seconds_f64();
+        if secs > 0.0 {
+            (self.download_bytes as f64 / secs) as usize
+        } else {
+            0
+        }
+    }
+
+    pub fn record_response(&mut self) {
+        self.time_response = OffsetDateTime::now_utc();
+        self.errors = 0;
+        self.reputation = (self.reputation + 1).min(MAX_REPUTATION);
+    }
+
+    pub fn record_error(&mut self) {
+        self.errors += 1;
+        self.reputation -= 1;
+    }
+
+    /// Peer served file which didn't match its hash.
+    pub fn record_bad_file(&mut self) {
+        self.bad_files += 1;
+        self.reputation -= 5;
+    }
+
+    pub fn record_download(&mut self, bytes: usize, took: std::time::Duration) {
+        self.download_bytes += bytes;
+        self.download_time += took;
+    }
+
+    /// Score used for peer selection, higher is better.
+    pub fn score(&self) -> isize {
+        let mut score = self.reputation - 2 * self.errors as isize;
+        if self.connection.is_some() {
+            score += 2;
+        }
+        // Up to 10 extra points for fast peers, one per 100KB/s
+        score + (self.download_speed() / (100 * 1024)).min(10) as isize
+    }
+
+    pub fn is_bad(&self) -> bool {
+        self.errors > MAX_ERRORS || self.reputation < MIN_REPUTATION
+    }
+
+    /// Runs `request` over peer connection with a timeout,
+    /// recording response or error on peer.
+    pub async fn track<T>(
+        &mut self,
+        request: impl AsyncFnOnce(Protocol<'_>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let Some(connection) = self.connection.as_mut() else {
+            return Err(Error::Err(format!("Peer {} not connected", self.address)));
+        };
+        let started = Instant::now();
+        let res = tokio::time::timeout(REQUEST_TIMEOUT, request(Protocol::new(connection))).await;
+        let res = match res {
+            Ok(res) => res,
+            Err(_) => Err(Error::Err(format!(
+                "Request to {} timed out after {:?}",
+                self.address,
+                started.elapsed()
+            ))),
+        };
+        match &res {
+            // Peer responded, it just doesn't have the file
+            Ok(_) | Err(Error::FileNotFound(_)) => self.record_response(),
+            Err(_) => self.record_error(),
+        }
+        res
+    }
+
+    pub fn hashfield(&self) -> &Hashfield {
+        &self.hashfield
+    }
+
+    pub fn set_hashfield(&mut self, hashfield: Hashfield) {
+        self.hashfield = hashfield;
+        self.time_hashfield = Some(OffsetDateTime::now_utc());
+    }
+
+    pub fn time_hashfield(&self) -> Option<OffsetDateTime> {
+        self.time_hashfield
+    }
+
+    /// Last time peers were exchanged with this peer.
+    pub fn time_pex(&self) -> Option<OffsetDateTime> {
+        self.time_pex
+    }
+
+    pub fn set_time_pex(&mut self, time_pex: OffsetDateTime) {
+        self.time_pex = Some(time_pex);
+    }
+
+    pub fn peer_id(&self) -> Option<&str> {
+        self.peer_id.as_deref()
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn rev(&self) -> Option<usize> {
+        self.rev
+    }
+
+    /// Onion address of peer, without `.onion` suffix.
+    pub fn onion(&self) -> Option<&str> {
+        self.onion.as_deref()
+    }
+
+    /// Records identity peer sent in its handshake.
+    pub fn set_handshake(&mut self, handshake: &Handshake) {
+        self.peer_id = Some(handshake.peer_id.clone());
+        self.version = Some(handshake.version.clone());
+        self.rev = Some(handshake.rev);
+        self.onion = handshake.onion.clone().filter(|onion| !onion.is_empty());
+    }
+}
+
+/// Packs peer addresses, split into ipv4, ipv6 and onion lists.
+pub fn pack_peers<'p>(
+    peers: impl Iterator<Item = &'p Peer>,
+) -> (Vec<ByteBuf>, Vec<ByteBuf>, Vec<ByteBuf>) {
+    let mut ip_v4 = vec![];
+    let mut ip_v6 = vec![];
+    let mut onion = vec![];
+    for peer in peers {
+        let packed = ByteBuf::from(peer.address().pack());
+        match peer.address() {
+            PeerAddress::IPV4(_, _) => ip_v4.push(packed),
+            PeerAddress::IPV6(_, _) => ip_v6.push(packed),
+            PeerAddress::OnionV2(_, _) | PeerAddress::OnionV3(_, _) => onion.push(packed),
+        }
+    }
+    (ip_v4, ip_v6, onion)
 }
 
 impl Debug for Peer {
@@ -86,6 +271,7 @@ impl Peer {
         Peer {
             address,
             connection: None,
+            socket: None,
             reputation: 0,
             time_found: OffsetDateTime::now_utc(),
             time_added: OffsetDateTime::now_utc(),
@@ -95,35 +281,189 @@ impl Peer {
             download_time: Duration::seconds(0),
             bad_files: 0,
             errors: 0,
+            hashfield: Hashfield::default(),
+            time_hashfield: None,
+            time_pex: None,
+            peer_id: None,
+            version: None,
+            rev: None,
+            onion: None,
         }
     }
+
+    /// Peer of inbound connection from `peer_addr`, if it accepts connections per its `handshake`.
+    /// Peers connecting through Tor come from local address, and are reachable at their onion.
+    pub fn inbound(peer_addr: SocketAddr, handshake: &Handshake) -> Option<Peer> {
+        let port = handshake.fileserver_port;
+        if port == 0 {
+            return None;
+        }
+        let addr = match handshake.onion.as_deref() {
+            Some(onion) if peer_addr.ip().is_loopback() && !onion.is_empty() => {
+                format!("{onion}.onion:{port}")
+            }
+            _ => SocketAddr::new(peer_addr.ip(), port).to_string(),
+        };
+        let mut peer = Peer::new(PeerAddress::parse(addr).ok()?);
+        peer.set_handshake(handshake);
+        Some(peer)
+    }
+
+    /// Host and port of peer, if connections to it go through proxy.
+    fn proxied_host(&self) -> Option<(String, u16)> {
+        let addr = self.address.to_string();
+        let (host, port) = split_host_port(&addr)?;
+        use_proxy(host).then(|| (host.to_string(), port))
+    }
+
+    /// Sets up connection over `stream`, keeping socket to upgrade connection to tls.
+    fn set_stream(&mut self, stream: std::net::TcpStream) -> Result<(), Error> {
+        let socket = stream.try_clone()?;
+        let reader = Box::new(stream.try_clone()?);
+        let writer = Box::new(stream);
+        self.connection = Some(ZeroConnection::new(reader, writer)?);
+        self.socket = Some(Arc::new(socket));
+        Ok(())
+    }
+
     pub fn connect(&mut self) -> Result<(), Error> {
         if self.connection.is_none() {
-            let conn = ZeroConnection::from_address(self.address.clone());
-            if conn.is_err() {
+            let stream = match self.proxied_host() {
+                Some((host, port)) => socks5_connect(ENV.proxy, &host, port),
+                None => std::net::TcpStream::connect(self.address.to_string()),
+            };
+            if stream.is_err() {
                 trace!(
                     "Failed to establish connection to {}.",
                     self.address.to_string()
                 );
             }
+            self.set_stream(stream?)?;
             debug!("Connected to Peer : {:?}", self.address());
-            self.connection = Some(conn?);
         }
         Ok(())
     }
 
     pub async fn connect_async(&mut self) -> Result<(), Error> {
         if self.connection.is_none() {
-            let conn = ZeroConnection::from_address_async(self.address.clone()).await;
-            if conn.is_err() {
+            let stream = match self.proxied_host() {
+                Some((host, port)) => socks5_connect_async(ENV.proxy, &host, port).await,
+                None => tokio::net::TcpStream::connect(self.address.to_string()).await,
+            };
+            if stream.is_err() {
                 trace!(
                     "Failed to establish connection to {}.",
                     self.address.to_string()
                 );
             }
+            self.set_stream(stream?.into_std()?)?;
             debug!("Connected to Peer : {:?}", self.address());
-            self.connection = Some(conn?);
         }
         Ok(())
     }
+
+    /// Connects to peer and handshakes over new connection, so crypt is negotiated before
+    /// any other request. Connection is dropped if handshake fails.
+    pub async fn connect_handshake(&mut self) -> Result<(), Error> {
+        if self.connection.is_some() {
+            return Ok(());
+        }
+        self.connect_async().await?;
+        if let Err(err) = self.handshake().await {
+            self.disconnect();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Handshakes with peer, upgrading connection to tls if peer selected it.
+    pub async fn handshake(&mut self) -> Result<Handshake, Error> {
+        let handshake = self
+            .track(async |mut protocol| protocol.handshake().await)
+            .await?;
+        if is_own_handshake(&handshake) {
+            self.disconnect();
+            // Marked bad, so it's evicted from site peers
+            self.reputation = MIN_REPUTATION - 1;
+            return Err(Error::Err(format!("Peer {} is ourselves", self.address)));
+        }
+        self.set_handshake(&handshake);
+        if handshake.crypt.as_deref() == Some(CRYPT_TLS_RSA)
+            && let Some(socket) = self.socket.take()
+        {
+            let addr = self.address.to_string();
+            let host = split_host_port(&addr).map_or(addr.as_str(), |(host, _)| host);
+            match tls::connect(socket.try_clone()?, host).await {
+                Ok(connection) => self.connection = Some(connection),
+                Err(err) => {
+                    self.disconnect();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(handshake)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use decentnet_protocol::{address::PeerAddr, templates::Handshake};
+
+    use super::Peer;
+    use crate::io::utils::current_unix_epoch;
+
+    fn peer(addr: &str) -> Peer {
+        Peer::new(PeerAddr::parse(addr.to_string()).unwrap())
+    }
+
+    #[test]
+    fn test_peer_score() {
+        let mut good = peer("127.0.0.1:15441");
+        let mut bad = peer("127.0.0.2:15441");
+        good.record_response();
+        good.record_download(1024 * 1024, std::time::Duration::from_secs(1));
+        bad.record_error();
+        assert!(good.score() > bad.score());
+        assert_eq!(good.download_speed(), 1024 * 1024);
+
+        bad.record_bad_file();
+        bad.record_bad_file();
+        assert!(bad.is_bad());
+        assert!(!good.is_bad());
+
+        bad.record_response();
+        assert_eq!(bad.errors(), 0);
+    }
+
+    #[test]
+    fn test_inbound_peer() {
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd";
+        let mut handshake = Handshake {
+            version: "0.7.6".into(),
+            rev: 4800,
+            peer_id: "-UT3530-abcdefghijkl".into(),
+            protocol: "v2".into(),
+            use_bin_type: true,
+            time: current_unix_epoch(),
+            fileserver_port: 15441,
+            crypt: None,
+            crypt_supported: vec![],
+            onion: None,
+            port_opened: Some(true),
+            target_address: None,
+        };
+        let peer = Peer::inbound("1.2.3.4:50000".parse().unwrap(), &handshake).unwrap();
+        assert_eq!(peer.address().to_string(), "1.2.3.4:15441");
+        assert_eq!(peer.peer_id(), Some("-UT3530-abcdefghijkl"));
+        assert_eq!(peer.version(), Some("0.7.6"));
+        assert_eq!(peer.rev(), Some(4800));
+
+        handshake.onion = Some(onion.into());
+        let peer = Peer::inbound("127.0.0.1:50000".parse().unwrap(), &handshake).unwrap();
+        assert!(matches!(peer.address(), PeerAddr::OnionV3(_, 15441)));
+        assert_eq!(peer.onion(), Some(onion));
+
+        handshake.fileserver_port = 0;
+        assert!(Peer::inbound("1.2.3.4:50000".parse().unwrap(), &handshake).is_none());
+    }
 }
@@ -5,12 +5,12 @@ use std::{
     path::PathBuf,
     str::FromStr,
 };
-use zerucontent::Content;
+use zerucontent::{Content, File as ZFile};
 
 use crate::environment::ENV;
 
 use self::models::SiteStorage;
-use super::{address::Address as Addr, error::Error, peer::Peer};
+use super::{address::Address as Addr, error::Error, hashfield::Hashfield, peer::Peer};
 
 pub mod models {
     use serde::{Deserialize, Serialize};
@@ -56,6 +56,9 @@ pub mod models {
     pub struct SiteCache {
         #[serde(default, skip_serializing_if = "is_default")]
         pub bad_files: BTreeMap<String, usize>,
+        /// Why bad files were rejected, if known, only kept in memory.
+        #[serde(skip)]
+        pub bad_file_errors: BTreeMap<String, String>,
         #[serde(default, skip_serializing_if = "is_default")]
         pub hashfield: String,
         #[serde(default, skip_serializing_if = "is_default")]
@@ -88,6 +91,12 @@ pub mod models {
         pub own: bool,
         pub permissions: Vec<String>,
         pub size_limit: usize,
+        /// Directories whose optional files we help distribute, with their titles.
+        #[serde(default, skip_serializing_if = "is_default")]
+        pub optional_help: BTreeMap<String, String>,
+        /// Whether every optional file of site is downloaded.
+        #[serde(default, skip_serializing_if = "is_default")]
+        pub autodownloadoptional: bool,
     }
 
     #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -141,15 +150,84 @@ impl Site {
         self.content.get_mut(inner_path.unwrap_or("content.json"))
     }
 
+    /// Checks whether `inner_path` is listed in files_optional of any loaded content.
+    pub fn is_optional_file(&self, inner_path: &str) -> bool {
+        self.optional_file(inner_path).is_some()
+    }
+
+    /// Optional file of `inner_path`, as listed in files_optional of loaded content.
+    pub fn optional_file(&self, inner_path: &str) -> Option<&ZFile> {
+        self.content.iter().find_map(|(content_path, content)| {
+            let content_dir = content_path
+                .strip_suffix("content.json")
+                .unwrap_or_default();
+            let path = inner_path.strip_prefix(content_dir)?;
+            content.files_optional.get(path)
+        })
+    }
+
+    /// Whether optional file is downloaded along with site, as its directory is helped
+    /// with optionalHelp or all optional files are with optionalHelpAll.
+    pub fn is_downloadable(&self, inner_path: &str) -> bool {
+        let settings = &self.storage.settings;
+        settings.autodownloadoptional
+            || settings
+                .optional_help
+                .keys()
+                .any(|directory| inner_path.starts_with(directory.as_str()))
+    }
+
+    /// Downloadable optional files of `inner_path` content, which are new or changed
+    /// compared to `old_content`.
+    pub fn downloadable_optional_files(
+        &self,
+        inner_path: &str,
+        old_content: Option<&Content>,
+    ) -> Vec<String> {
+        let Some(content) = self.content(Some(inner_path)) else {
+            return vec![];
+        };
+        let content_dir = inner_path.strip_suffix("content.json").unwrap_or_default();
+        content
+            .files_optional
+            .iter()
+            .filter(|(path, file)| {
+                let old_file = old_content.and_then(|old| old.files_optional.get(path.as_str()));
+                old_file.is_none_or(|old_file| old_file.sha512 != file.sha512)
+            })
+            .map(|(path, _)| format!("{content_dir}{path}"))
+            .filter(|inner_path| self.is_downloadable(inner_path))
+            .collect()
+    }
+
+    /// Loaded content which holds user_contents rules, usually data/users/content.json.
+    pub fn user_contents_content(&self) -> Option<&Content> {
+        self.content
+            .values()
+            .find(|content| content.user_contents.is_some())
+    }
+
     pub fn modify_content(&mut self, inner_path: Option<&str>, content: Content) {
         self.content
             .insert(inner_path.unwrap_or("content.json").into(), content);
     }
 
+    pub fn remove_content(&mut self, inner_path: &str) -> Option<Content> {
+        self.content.remove(inner_path)
+    }
+
     pub fn modify_storage(&mut self, storage: SiteStorage) {
         self.storage = storage;
     }
 
+    pub fn hashfield(&self) -> Hashfield {
+        Hashfield::from_base64(&self.storage.cache.hashfield)
+    }
+
+    pub fn set_hashfield(&mut self, hashfield: &Hashfield) {
+        self.storage.cache.hashfield = hashfield.to_base64();
+    }
+
     pub fn get_size(&self) -> usize {
         self.storage.stats.size
     }
@@ -0,0 +1,371 @@
+use std::{collections::HashMap, time::Duration};
+
+use actix::{AsyncContext, Context, Handler, Message};
+use futures::future::join_all;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controllers::sites::SitesController,
+    core::{error::Error, peer::Peer},
+    discovery::tracker::AnnounceInterval,
+    environment::ENV,
+    io::utils::current_unix_epoch,
+};
+
+/// Serving sites are re-announced to every tracker this often, unless tracker asks otherwise.
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(20 * 60);
+/// How often scheduler checks for sites due to announce.
+pub const ANNOUNCE_TICK: Duration = Duration::from_secs(60);
+/// Tracker is skipped for this long after an error, doubled with each consecutive error.
+const TRACKER_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_TRACKER_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct AnnouncerStats {
+    pub status: String,
+    pub num_request: usize,
+    pub num_success: usize,
+    pub num_error: usize,
+    pub time_request: f64,
+    pub time_last_error: f64,
+    pub time_status: f64,
+    pub last_error: String,
+}
+
+impl AnnouncerStats {
+    fn record_request(&mut self, now: f64) {
+        self.num_request += 1;
+        self.time_request = now;
+        self.status = "announcing".into();
+        self.time_status = now;
+    }
+
+    fn record_success(&mut self, now: f64) {
+        self.num_success += 1;
+        self.status = "announced".into();
+        self.time_status = now;
+    }
+
+    fn record_error(&mut self, error: String, now: f64) {
+        self.num_error += 1;
+        self.last_error = error;
+        self.time_last_error = now;
+        self.status = "error".into();
+        self.time_status = now;
+    }
+
+    /// Combines stats of same tracker from different sites.
+    fn merge(&mut self, other: &AnnouncerStats) {
+        self.num_request += other.num_request;
+        self.num_success += other.num_success;
+        self.num_error += other.num_error;
+        self.time_request = self.time_request.max(other.time_request);
+        if other.time_last_error > self.time_last_error {
+            self.time_last_error = other.time_last_error;
+            self.last_error = other.last_error.clone();
+        }
+        if other.time_status > self.time_status {
+            self.time_status = other.time_status;
+            self.status = other.status.clone();
+        }
+    }
+}
+
+/// Sent to subscribed websockets after site was announced.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct AnnouncerChanged {
+    pub address: String,
+    pub stats: HashMap<String, AnnouncerStats>,
+}
+
+/// Results of announcing site to each tracker, sent back to sites controller once done.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct SiteAnnounced {
+    address: String,
+    results: Vec<(String, Result<(Vec<Peer>, AnnounceInterval), Error>)>,
+}
+
+/// Earliest times site may be announced to a tracker again.
+#[derive(Debug, Clone, Copy, Default)]
+struct TrackerSchedule {
+    /// For scheduled announces, tracker's `interval` or [`ANNOUNCE_INTERVAL`].
+    next: f64,
+    /// For announces requested by user, tracker's `min interval`.
+    min_next: f64,
+}
+
+/// Announce schedule of sites, with per tracker stats of each site.
+#[derive(Default)]
+pub struct Announcer {
+    stats: HashMap<String, HashMap<String, AnnouncerStats>>,
+    schedule: HashMap<String, HashMap<String, TrackerSchedule>>,
+    /// Consecutive errors of tracker and time of last one.
+    tracker_errors: HashMap<String, (u32, f64)>,
+}
+
+impl Announcer {
+    fn now() -> f64 {
+        current_unix_epoch() as f64
+    }
+
+    /// Whether any of `trackers` is due for a scheduled announce of site.
+    pub fn is_due(&self, site: &str, trackers: &[String], now: f64) -> bool {
+        !self.ready_trackers(site, trackers, false, now).is_empty()
+    }
+
+    fn backoff(errors: u32) -> f64 {
+        let backoff = TRACKER_BACKOFF.as_secs_f64() * 2f64.powi(errors.saturating_sub(1) as i32);
+        backoff.min(MAX_TRACKER_BACKOFF.as_secs_f64())
+    }
+
+    /// Trackers which are not backing off after errors, and whose announce interval
+    /// for site has passed. `forced` announces only respect tracker's `min interval`.
+    pub fn ready_trackers<'a>(
+        &self,
+        site: &str,
+        trackers: &'a [String],
+        forced: bool,
+        now: f64,
+    ) -> Vec<&'a String> {
+        let schedule = self.schedule.get(site);
+        trackers
+            .iter()
+            .filter(|tracker| {
+                self.tracker_errors
+                    .get(*tracker)
+                    .is_none_or(|(errors, last)| now - last >= Self::backoff(*errors))
+            })
+            .filter(|tracker| {
+                schedule.and_then(|s| s.get(*tracker)).is_none_or(|s| {
+                    let next = if forced { s.min_next } else { s.next };
+                    now >= next
+                })
+            })
+            .collect()
+    }
+
+    pub fn start(&mut self, site: &str, trackers: &[&String], now: f64) {
+        let schedule = self.schedule.entry(site.into()).or_default();
+        let stats = self.stats.entry(site.into()).or_default();
+        for tracker in trackers {
+            let schedule = schedule.entry(tracker.to_string()).or_default();
+            schedule.next = now + ANNOUNCE_INTERVAL.as_secs_f64();
+            stats
+                .entry(tracker.to_string())
+                .or_default()
+                .record_request(now);
+        }
+    }
+
+    pub fn finish(
+        &mut self,
+        site: &str,
+        tracker: &str,
+        res: Result<(usize, AnnounceInterval), &Error>,
+        now: f64,
+    ) {
+        let stats = self
+            .stats
+            .entry(site.into())
+            .or_default()
+            .entry(tracker.into())
+            .or_default();
+        match res {
+            Ok((_, interval)) => {
+                stats.record_success(now);
+                self.tracker_errors.remove(tracker);
+                let schedule = self
+                    .schedule
+                    .entry(site.into())
+                    .or_default()
+                    .entry(tracker.into())
+                    .or_default();
+                if let Some(interval) = interval.interval {
+                    schedule.next = now + interval.as_secs_f64();
+                }
+                if let Some(min_interval) = interval.min_interval {
+                    schedule.min_next = now + min_interval.as_secs_f64();
+                    schedule.next = schedule.next.max(schedule.min_next);
+                }
+            }
+            Err(err) => {
+                stats.record_error(format!("{err:?}"), now);
+                let (errors, last) = self.tracker_errors.entry(tracker.into()).or_default();
+                *errors += 1;
+                *last = now;
+            }
+        }
+    }
+
+    pub fn site_stats(&self, site: &str) -> HashMap<String, AnnouncerStats> {
+        self.stats.get(site).cloned().unwrap_or_default()
+    }
+
+    /// Stats of each tracker, combined over all sites.
+    pub fn stats(&self) -> HashMap<String, AnnouncerStats> {
+        let mut all = HashMap::<String, AnnouncerStats>::new();
+        for stats in self.stats.values() {
+            for (tracker, stats) in stats {
+                all.entry(tracker.clone()).or_default().merge(stats);
+            }
+        }
+        all
+    }
+}
+
+impl SitesController {
+    /// Announces serving sites to trackers whose announce interval has passed.
+    pub fn announce_due_sites(&mut self, ctx: &mut Context<Self>) {
+        let now = Announcer::now();
+        let due = self
+            .sites
+            .iter()
+            .filter(|(address, site)| {
+                site.storage.settings.serving && self.announcer.is_due(address, &ENV.trackers, now)
+            })
+            .map(|(address, _)| address.clone())
+            .collect::<Vec<_>>();
+        for address in due {
+            self.announce_site(&address, false, ctx);
+        }
+    }
+
+    /// Announces site to all ready trackers, adding found peers to site.
+    /// `forced` announces, requested by user, ignore tracker's regular interval.
+    pub fn announce_site(&mut self, address: &str, forced: bool, ctx: &mut Context<Self>) {
+        let Some(site) = self.sites.get(address).cloned() else {
+            return;
+        };
+        let now = Announcer::now();
+        let trackers = self
+            .announcer
+            .ready_trackers(address, &ENV.trackers, forced, now);
+        if trackers.is_empty() {
+            return;
+        }
+        self.announcer.start(address, &trackers, now);
+        let trackers = trackers.into_iter().cloned().collect::<Vec<_>>();
+        let address = address.to_string();
+        let sites_controller = ctx.address();
+        // Trackers are announced to in a task of its own, so actor isn't held up by their I/O
+        tokio::spawn(async move {
+            let results = join_all(trackers.iter().map(|tracker| site.announce_to(tracker))).await;
+            let results = trackers.into_iter().zip(results).collect();
+            sites_controller.do_send(SiteAnnounced { address, results });
+        });
+    }
+
+    fn notify_announcer_changed(&mut self, address: &str) {
+        let msg = AnnouncerChanged {
+            address: address.into(),
+            stats: self.announcer.site_stats(address),
+        };
+        self.announcer_listeners
+            .retain(|listener| listener.connected());
+        for listener in &self.announcer_listeners {
+            listener.do_send(msg.clone());
+        }
+    }
+}
+
+impl Handler<SiteAnnounced> for SitesController {
+    type Result = ();
+
+    fn handle(&mut self, msg: SiteAnnounced, _ctx: &mut Context<Self>) {
+        let SiteAnnounced { address, results } = msg;
+        let now = Announcer::now();
+        let mut peers = vec![];
+        for (tracker, res) in results {
+            let summary = res
+                .as_ref()
+                .map(|(found, interval)| (found.len(), *interval));
+            self.announcer.finish(&address, &tracker, summary, now);
+            match res {
+                Ok((found, _)) => peers.extend(found),
+                Err(err) => debug!("Announcing {address} to {tracker} failed : {err:?}"),
+            }
+        }
+        if let Some(site) = self.sites.get_mut(&address) {
+            let new_peers = peers
+                .into_iter()
+                .filter(|peer| !site.peers.contains_key(&peer.address().to_string()))
+                .collect::<Vec<_>>();
+            debug!("Found {} new peers for {address}", new_peers.len());
+            site.add_peers(new_peers);
+        }
+        self.notify_announcer_changed(&address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Announcer, ANNOUNCE_INTERVAL};
+    use crate::{core::error::Error, discovery::tracker::AnnounceInterval};
+
+    #[test]
+    fn test_tracker_backoff() {
+        let mut announcer = Announcer::default();
+        let trackers = vec!["zero://127.0.0.1:15441".to_string()];
+        let tracker = &trackers[0];
+        announcer.start("site", &[tracker], 0.0);
+        assert!(!announcer.is_due("site", &trackers, 60.0));
+        announcer.finish("site", tracker, Err(&Error::Err("timeout".into())), 0.0);
+        assert!(announcer
+            .ready_trackers("site", &trackers, true, 30.0)
+            .is_empty());
+        assert_eq!(
+            announcer
+                .ready_trackers("site", &trackers, true, 60.0)
+                .len(),
+            1
+        );
+
+        announcer.finish("site", tracker, Err(&Error::Err("timeout".into())), 60.0);
+        assert!(announcer
+            .ready_trackers("site", &trackers, true, 150.0)
+            .is_empty());
+        announcer.finish("site", tracker, Ok((5, Default::default())), 200.0);
+        assert_eq!(
+            announcer
+                .ready_trackers("site", &trackers, true, 200.0)
+                .len(),
+            1
+        );
+
+        let stats = &announcer.stats()[tracker];
+        assert_eq!(stats.num_request, 1);
+        assert_eq!(stats.num_error, 2);
+        assert_eq!(stats.num_success, 1);
+        assert_eq!(stats.status, "announced");
+    }
+
+    #[test]
+    fn test_tracker_interval() {
+        let mut announcer = Announcer::default();
+        let trackers = vec!["udp://127.0.0.1:6969".to_string()];
+        let tracker = &trackers[0];
+        assert!(announcer.is_due("site", &trackers, 0.0));
+        announcer.start("site", &[tracker], 0.0);
+        let interval = AnnounceInterval {
+            interval: Some(Duration::from_secs(3600)),
+            min_interval: Some(Duration::from_secs(300)),
+        };
+        announcer.finish("site", tracker, Ok((0, interval)), 0.0);
+        assert!(!announcer.is_due("site", &trackers, ANNOUNCE_INTERVAL.as_secs_f64()));
+        assert!(announcer.is_due("site", &trackers, 3600.0));
+        assert!(announcer
+            .ready_trackers("site", &trackers, true, 100.0)
+            .is_empty());
+        assert_eq!(
+            announcer
+                .ready_trackers("site", &trackers, true, 300.0)
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,376 @@
+// local network discovery over udp broadcast, compatible with LocalDiscovery plugin of ZeroNet
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use actix::{AsyncContext, Context, StreamHandler, WrapFuture};
+use decentnet_protocol::address::PeerAddr;
+use futures::Stream;
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use tokio::{net::UdpSocket, time::timeout};
+
+use crate::{
+    controllers::sites::SitesController,
+    core::{discovery::Discovery, error::Error, peer::Peer},
+    discovery::{fileserver_port, tracker::zero::announce_hash},
+    environment::ENV,
+};
+
+/// How often serving sites are looked up on local network.
+pub const BROADCAST_INTERVAL: Duration = Duration::from_secs(20 * 60);
+/// Time to collect responses after broadcasting a discover request.
+const DISCOVER_WAIT: Duration = Duration::from_secs(3);
+const SERVICE_NAME: &str = "zeronet";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sender {
+    pub service_name: String,
+    pub port: u16,
+    pub peer_id: String,
+    pub broadcast_port: u16,
+    pub rev: usize,
+}
+
+impl Sender {
+    /// Sender info of this node, `broadcast_port` is set when socket is bound.
+    pub fn local() -> Self {
+        Self {
+            service_name: SERVICE_NAME.into(),
+            port: fileserver_port().unwrap_or(0),
+            peer_id: ENV.peer_id.clone(),
+            broadcast_port: 0,
+            rev: ENV.rev,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Params {
+    /// Sha256 hashes of served sites, only sent in responses.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sites: Vec<ByteBuf>,
+    #[serde(default)]
+    pub sites_changed: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocalMessage {
+    pub cmd: String,
+    pub params: Params,
+    pub sender: Sender,
+}
+
+/// Udp socket sending and answering discover broadcasts.
+#[derive(Clone)]
+pub struct LocalDiscovery {
+    socket: Arc<UdpSocket>,
+    broadcast_port: u16,
+    sender: Sender,
+    /// Sites to find peers of with [`Discovery::discover`].
+    pub sites: Vec<String>,
+}
+
+impl LocalDiscovery {
+    /// Binds to `port` on all interfaces, zero `port` binds to any free port,
+    /// which is only useful for one-shot [`Discovery::discover`].
+    pub fn bind(port: u16, broadcast_port: u16, mut sender: Sender) -> io::Result<Self> {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        sender.broadcast_port = socket.local_addr()?.port();
+        Ok(Self {
+            socket: Arc::new(UdpSocket::from_std(socket)?),
+            broadcast_port,
+            sender,
+            sites: vec![],
+        })
+    }
+
+    fn message(&self, cmd: &str, params: Params) -> LocalMessage {
+        LocalMessage {
+            cmd: cmd.into(),
+            params,
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Own broadcasts are received too.
+    fn is_own(&self, message: &LocalMessage) -> bool {
+        message.sender.peer_id == self.sender.peer_id
+    }
+
+    async fn send(&self, message: &LocalMessage, to: SocketAddr) -> Result<(), Error> {
+        let bytes = rmp_serde::to_vec_named(message)?;
+        self.socket.send_to(&bytes, to).await?;
+        Ok(())
+    }
+
+    /// Asks all nodes on local network for sites they serve.
+    pub async fn broadcast(&self, sites_changed: u64) -> Result<(), Error> {
+        let params = Params {
+            sites: vec![],
+            sites_changed,
+        };
+        let message = self.message("discoverRequest", params);
+        let to = SocketAddr::from((Ipv4Addr::BROADCAST, self.broadcast_port));
+        self.send(&message, to).await
+    }
+
+    /// Answers discover request from `from` with hashes of `sites`.
+    pub async fn respond(
+        &self,
+        request: &LocalMessage,
+        from: SocketAddr,
+        sites: &[String],
+        sites_changed: u64,
+    ) -> Result<(), Error> {
+        let params = Params {
+            sites: sites.iter().map(|site| announce_hash(site)).collect(),
+            sites_changed,
+        };
+        let message = self.message("discoverResponse", params);
+        let to = SocketAddr::new(from.ip(), request.sender.broadcast_port);
+        self.send(&message, to).await
+    }
+
+    /// Receives next message of other nodes, skipping own and invalid messages.
+    pub async fn recv(&self) -> io::Result<(LocalMessage, SocketAddr)> {
+        let mut buf = vec![0_u8; 65536];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            match rmp_serde::from_slice::<LocalMessage>(&buf[..len]) {
+                Ok(message)
+                    if message.sender.service_name == SERVICE_NAME && !self.is_own(&message) =>
+                {
+                    return Ok((message, from));
+                }
+                Ok(_) => {}
+                Err(err) => trace!("Invalid local discovery message from {from} : {err:?}"),
+            }
+        }
+    }
+
+    /// Stream of messages received by socket, ending on socket error.
+    pub fn messages(self) -> impl Stream<Item = (LocalMessage, SocketAddr)> {
+        futures::stream::unfold(self, |discovery| async move {
+            match discovery.recv().await {
+                Ok(message) => Some((message, discovery)),
+                Err(err) => {
+                    error!("Local discovery socket failed, Error : {err:?}");
+                    None
+                }
+            }
+        })
+    }
+
+    /// Peers of `sites` found in discover response from `from`, keyed by site.
+    pub fn response_peers(
+        response: &LocalMessage,
+        from: SocketAddr,
+        sites: &[String],
+    ) -> HashMap<String, Peer> {
+        if response.cmd != "discoverResponse" || response.sender.port == 0 {
+            return HashMap::new();
+        }
+        let Ok(addr) =
+            PeerAddr::parse(SocketAddr::new(from.ip(), response.sender.port).to_string())
+        else {
+            return HashMap::new();
+        };
+        sites
+            .iter()
+            .filter(|site| response.params.sites.contains(&announce_hash(site)))
+            .map(|site| (site.clone(), Peer::new(addr.clone())))
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Discovery for LocalDiscovery {
+    async fn discover(&self) -> Result<Vec<Peer>, Error> {
+        self.broadcast(0).await?;
+        let mut peers = HashMap::new();
+        let _ = timeout(DISCOVER_WAIT, async {
+            while let Ok((message, from)) = self.recv().await {
+                for (_, peer) in Self::response_peers(&message, from, &self.sites) {
+                    peers.insert(peer.address().to_string(), peer);
+                }
+            }
+        })
+        .await;
+        Ok(peers.into_values().collect())
+    }
+}
+
+impl SitesController {
+    fn serving_sites(&self) -> Vec<String> {
+        self.sites
+            .iter()
+            .filter(|(_, site)| site.storage.settings.serving)
+            .map(|(address, _)| address.clone())
+            .collect()
+    }
+
+    /// Binds local discovery socket to `broadcast_port`, advertising fileserver port if it's
+    /// already listening. Zero `broadcast_port` disables local discovery.
+    pub fn bind_local_discovery(&mut self) {
+        if ENV.broadcast_port == 0 {
+            return;
+        }
+        let port = ENV.broadcast_port;
+        match LocalDiscovery::bind(port, port, Sender::local()) {
+            Ok(discovery) => self.local_discovery = Some(discovery),
+            Err(err) => error!("Failed to bind local discovery port, Error : {err:?}"),
+        }
+    }
+
+    /// Answers and periodically broadcasts discover requests, once socket is bound
+    /// with [`SitesController::bind_local_discovery`].
+    pub fn start_local_discovery(&mut self, ctx: &mut Context<Self>) {
+        let Some(discovery) = self.local_discovery.clone() else {
+            return;
+        };
+        ctx.add_stream(discovery.messages());
+        self.broadcast_local_discovery(ctx);
+        ctx.run_interval(BROADCAST_INTERVAL, |act, ctx| {
+            act.broadcast_local_discovery(ctx)
+        });
+    }
+
+    fn broadcast_local_discovery(&mut self, ctx: &mut Context<Self>) {
+        let Some(discovery) = self.local_discovery.clone() else {
+            return;
+        };
+        let sites_changed = self.sites_changed;
+        ctx.spawn(
+            async move {
+                if let Err(err) = discovery.broadcast(sites_changed).await {
+                    debug!("Local discovery broadcast failed, Error : {err:?}");
+                }
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+impl StreamHandler<(LocalMessage, SocketAddr)> for SitesController {
+    fn handle(&mut self, (message, from): (LocalMessage, SocketAddr), ctx: &mut Context<Self>) {
+        let Some(discovery) = self.local_discovery.clone() else {
+            return;
+        };
+        match message.cmd.as_str() {
+            // Nodes without fileserver can't be connected to, so they don't announce sites
+            "discoverRequest" if discovery.sender.port != 0 => {
+                let sites = self.serving_sites();
+                let sites_changed = self.sites_changed;
+                ctx.spawn(
+                    async move {
+                        let res = discovery.respond(&message, from, &sites, sites_changed);
+                        if let Err(err) = res.await {
+                            debug!("Local discovery response to {from} failed : {err:?}");
+                        }
+                    }
+                    .into_actor(self),
+                );
+            }
+            "discoverResponse" => {
+                let peers = LocalDiscovery::response_peers(&message, from, &self.serving_sites());
+                for (address, peer) in peers {
+                    if let Some(site) = self.sites.get_mut(&address)
+                        && !site.peers.contains_key(&peer.address().to_string())
+                    {
+                        debug!("Found local peer {} for {address}", peer.address());
+                        site.add_peer(peer);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finished(&mut self, _: &mut Context<Self>) {
+        // Socket failed, SitesController keeps running without local discovery
+        self.local_discovery = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix::Actor;
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::{core::site::Site, io::db::DbManager};
+
+    const SITE: &str = "1HELLoE3sFD9569CLCbHEAVqvqV7U2Ri9d";
+
+    fn sender(peer_id: &str, port: u16) -> Sender {
+        Sender {
+            service_name: SERVICE_NAME.into(),
+            port,
+            peer_id: peer_id.into(),
+            broadcast_port: 0,
+            rev: 4800,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_discover_response() {
+        let node = LocalDiscovery::bind(0, 0, sender("-node-", 15441)).unwrap();
+        let mut requester = LocalDiscovery::bind(0, 0, sender("-requester-", 0)).unwrap();
+        requester.sites = vec![SITE.into()];
+        let node_port = node.socket.local_addr().unwrap().port();
+
+        let request = requester.message("discoverRequest", Params::default());
+        let to = SocketAddr::from((Ipv4Addr::LOCALHOST, node_port));
+        requester.send(&request, to).await.unwrap();
+        let (request, from) = node.recv().await.unwrap();
+        assert_eq!(request.cmd, "discoverRequest");
+        node.respond(&request, from, &[SITE.into()], 0)
+            .await
+            .unwrap();
+
+        let (response, from) = requester.recv().await.unwrap();
+        assert_eq!(response.params.sites, vec![announce_hash(SITE)]);
+        let peers = LocalDiscovery::response_peers(&response, from, &requester.sites);
+        assert_eq!(peers[SITE].address().to_string(), "127.0.0.1:15441");
+        assert!(LocalDiscovery::response_peers(&request, from, &requester.sites).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_sites_controller_answers_discover_request() {
+        let mut controller = SitesController::new(DbManager::new());
+        controller
+            .insert_content_db(Connection::open_in_memory().unwrap())
+            .unwrap();
+        let mut site = Site::new(SITE, Default::default()).unwrap();
+        site.storage.settings.serving = true;
+        controller.sites.insert(SITE.into(), site);
+        let node = LocalDiscovery::bind(0, 0, sender("-node-", 15441)).unwrap();
+        let node_port = node.socket.local_addr().unwrap().port();
+        controller.local_discovery = Some(node);
+        let _controller = controller.start();
+
+        let mut requester = LocalDiscovery::bind(0, 0, sender("-requester-", 0)).unwrap();
+        requester.sites = vec![SITE.into()];
+        let request = requester.message("discoverRequest", Params::default());
+        let to = SocketAddr::from((Ipv4Addr::LOCALHOST, node_port));
+        requester.send(&request, to).await.unwrap();
+
+        let (response, from) = timeout(DISCOVER_WAIT, requester.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.cmd, "discoverResponse");
+        assert_eq!(response.params.sites, vec![announce_hash(SITE)]);
+        let peers = LocalDiscovery::response_peers(&response, from, &requester.sites);
+        assert_eq!(peers[SITE].address().to_string(), "127.0.0.1:15441");
+    }
+}
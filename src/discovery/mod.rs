@@ -1,20 +1,24 @@
+pub mod announcer;
+pub mod local;
+pub mod pex;
 pub mod tracker;
+use std::sync::OnceLock;
+
 use futures::future::join_all;
 use log::*;
 use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
 
-use decentnet_protocol::{address::PeerAddr, interface::RequestImpl};
+use decentnet_protocol::address::PeerAddr;
 
 use crate::{
-    core::{discovery::Discovery, error::Error, peer::Peer, site::Site},
-    discovery::tracker::{announce, get_info_hash, make_addr},
+    core::{discovery::Discovery, error::Error, io::SiteIO, peer::Peer, site::Site},
+    discovery::tracker::{
+        announce, get_info_hash, make_addr, zero::zero_announce, Addr, AnnounceInterval,
+    },
     environment::ENV,
     io::utils::load_peers,
-    net::Protocol,
 };
 
-use self::tracker::IpPort;
-
 #[async_trait::async_trait]
 impl Discovery for Site {
     //TODO? :: Make this to return stream of peers, instead of full result at once.
@@ -22,38 +26,64 @@ impl Discovery for Site {
         info!("Discovering peers");
         let mut res_all = vec![];
         let mut futures = vec![];
-        for tracker_addr in ENV.trackers.clone() {
-            match make_addr(&tracker_addr) {
-                Ok(tracker_addr) => {
-                    let info_hash = get_info_hash(self.address().to_string());
-                    let res = announce(tracker_addr, info_hash, 0, &ENV.peer_id);
-                    futures.push(res);
-                }
-                Err(err) => {
-                    error!("Error : {err}");
-                }
-            }
+        for tracker_addr in &ENV.trackers {
+            futures.push(self.announce_to(tracker_addr));
         }
         let results = join_all(futures).await;
         for res in results {
-            if let Err(e) = &res {
-                error!("Error : {e:?}");
-            } else {
-                let mut _res: Vec<Peer> = res
-                    .unwrap()
-                    .extract_if(.., |a| a.port > 1) //consider ips with no port
-                    .collect::<Vec<_>>()
-                    .iter()
-                    .map(|p: &IpPort| Peer::new(PeerAddr::parse(p.to_string()).unwrap()))
-                    .collect();
-                res_all.append(&mut _res);
+            match res {
+                Ok((mut peers, _)) => res_all.append(&mut peers),
+                Err(e) => error!("Error : {e:?}"),
             }
         }
         Ok(res_all)
     }
 }
 
+/// Port fileserver listens on, set once it's bound, see [`set_fileserver_port`].
+static FILESERVER_PORT: OnceLock<u16> = OnceLock::new();
+
+/// Fileserver port to announce, if fileserver is accepting connections.
+pub(crate) fn fileserver_port() -> Option<u16> {
+    FILESERVER_PORT.get().copied()
+}
+
+pub(crate) fn set_fileserver_port(port: u16) {
+    let _ = FILESERVER_PORT.set(port);
+}
+
 impl Site {
+    /// Announces site to a single tracker, dispatching on tracker protocol.
+    /// Returns found peers along with re-announce intervals tracker asked for.
+    pub async fn announce_to(&self, tracker: &str) -> Result<(Vec<Peer>, AnnounceInterval), Error> {
+        let tracker_addr = make_addr(tracker).map_err(Error::Err)?;
+        let (peers, interval) = match tracker_addr {
+            Addr::Zero(addr) => {
+                let peers = zero_announce(addr, &[self.address()], fileserver_port().unwrap_or(0))
+                    .await?
+                    .pop()
+                    .unwrap_or_default();
+                (peers, AnnounceInterval::default())
+            }
+            tracker_addr => {
+                let info_hash = get_info_hash(self.address().to_string());
+                // Port 1 tells other peers we aren't connectable
+                let port = fileserver_port().unwrap_or(1);
+                // Trackers only need to tell seeders from leechers
+                let left = if self.content_path().is_file() { 0 } else { 1 };
+                let (peers, interval) =
+                    announce(tracker_addr, info_hash, port, left, &ENV.peer_id).await?;
+                let peers = peers
+                    .into_iter()
+                    .filter(|a| a.port() > 1) //consider ips with no port
+                    .filter_map(|addr| PeerAddr::parse(addr.to_string()).ok())
+                    .collect();
+                (peers, interval)
+            }
+        };
+        Ok((peers.into_iter().map(Peer::new).collect(), interval))
+    }
+
     pub async fn find_peers(&mut self) -> Result<Vec<Peer>, Error> {
         let peers = self.discover().await?;
         let mut peers = peers
@@ -83,9 +113,7 @@ impl Site {
         let valid_connections = connections
             .iter_mut()
             .map(|peer| async {
-                let res = Protocol::new((*peer).connection_mut().unwrap())
-                    .handshake()
-                    .await;
+                let res = peer.handshake().await;
                 if let Err(e) = res {
                     let peer = &peer.address().to_string();
                     error!("Error on Handshake: {e:?} with Peer {peer:?}");
@@ -0,0 +1,236 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use actix::{AsyncContext, Context, Handler, Message};
+use decentnet_protocol::address::PeerAddr;
+use futures::future::join_all;
+use log::*;
+use time::OffsetDateTime;
+
+use crate::{
+    controllers::sites::SitesController,
+    core::{
+        error::Error,
+        peer::{pack_peers, Peer},
+        site::Site,
+    },
+    environment::ENV,
+};
+
+/// How often serving sites exchange peers with their connected peers.
+pub const PEX_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Connected peers asked in each exchange round.
+const PEX_QUERY_NUM: usize = 3;
+/// Peers requested from, and sent to, each asked peer.
+const PEX_NEED_NUM: usize = 10;
+/// Peers learnt from a peer start with a quarter of its reputation, up to this.
+const MAX_SEED_REPUTATION: isize = 5;
+
+/// Outcome of exchanging peers of a site, merged into site once exchange is done.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct PeersExchanged {
+    address: String,
+    /// Peers learnt from exchange, which site didn't know when it started.
+    found: Vec<Peer>,
+    /// Known peers asked for peers or connected to, carrying their new stats.
+    contacted: Vec<Peer>,
+}
+
+impl Site {
+    pub fn connected_peers(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|peer| peer.connection().is_some())
+            .count()
+    }
+
+    /// Exchanges peers with connected peers, asking ones not asked for longest first,
+    /// and adds newly found peers to site. Returns addresses of new peers.
+    pub async fn fetch_peers(&mut self) -> Result<Vec<String>, Error> {
+        let site_addr = self.address().to_owned();
+        let mut connected = self
+            .peers
+            .values()
+            .filter(|peer| peer.connection().is_some())
+            .collect::<Vec<_>>();
+        // Never asked peers sort first
+        connected.sort_by_key(|peer| peer.time_pex());
+        let keys = connected
+            .into_iter()
+            .take(PEX_QUERY_NUM)
+            .map(|peer| peer.address().to_string())
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Err(Error::Err(format!("No connected peers of {site_addr}")));
+        }
+        // Send our best peers, other than the asked one
+        let best = self.peers_by_score();
+        let mut packed = keys
+            .iter()
+            .map(|key| {
+                let peers = best
+                    .iter()
+                    .copied()
+                    .filter(|peer| !peer.is_bad() && peer.address().to_string() != *key)
+                    .take(PEX_NEED_NUM);
+                (key.clone(), pack_peers(peers))
+            })
+            .collect::<HashMap<_, _>>();
+        let now = OffsetDateTime::now_utc();
+        let tasks = self.peers.iter_mut().filter_map(|(key, peer)| {
+            let packed = packed.remove(key)?;
+            peer.set_time_pex(now);
+            let site_addr = &site_addr;
+            Some(async move {
+                let res = peer
+                    .track(async move |mut protocol| {
+                        protocol
+                            .pex_with_peers(site_addr, packed, PEX_NEED_NUM)
+                            .await
+                    })
+                    .await;
+                (peer.address().to_string(), peer.reputation(), res)
+            })
+        });
+        let results = join_all(tasks).await;
+        let mut found = HashMap::new();
+        for (source, reputation, res) in results {
+            let res = match res {
+                Ok(res) => res,
+                Err(err) => {
+                    debug!("Pex with {source} for {site_addr} failed : {err:?}");
+                    continue;
+                }
+            };
+            //TODO! Add onion peers, once they can be connected to
+            let packed = res
+                .peers
+                .into_iter()
+                .chain(res.peers_ipv6.unwrap_or_default());
+            for addr in packed.filter_map(|packed| PeerAddr::unpack(packed.as_slice()).ok()) {
+                let key = addr.to_string();
+                if self.peers.contains_key(&key) || found.contains_key(&key) {
+                    continue;
+                }
+                let mut peer = Peer::new(addr);
+                peer.set_reputation((reputation / 4).clamp(0, MAX_SEED_REPUTATION));
+                found.insert(key, peer);
+            }
+        }
+        let new_peers = found.keys().cloned().collect::<Vec<_>>();
+        self.add_peers(found.into_values().collect());
+        Ok(new_peers)
+    }
+
+    /// Connects and handshakes best scored unconnected peers, until `limit` peers are connected.
+    /// Returns addresses of peers it tried to connect to.
+    pub async fn connect_peers(&mut self, limit: usize) -> Vec<String> {
+        let need = limit.saturating_sub(self.connected_peers());
+        let keys = self
+            .peers_by_score()
+            .into_iter()
+            .filter(|peer| peer.connection().is_none() && !peer.is_bad())
+            .take(need)
+            .map(|peer| peer.address().to_string())
+            .collect::<Vec<_>>();
+        let tasks = self
+            .peers
+            .iter_mut()
+            .filter(|(key, _)| keys.contains(key))
+            .map(|(_, peer)| async move {
+                if peer.connect_async().await.is_err() {
+                    peer.record_error();
+                    return false;
+                }
+                let res = peer.handshake().await;
+                if res.is_err() {
+                    peer.disconnect();
+                }
+                res.is_ok()
+            });
+        let connected = join_all(tasks).await.into_iter().filter(|ok| *ok).count();
+        debug!("Connected {connected} of {} peers", keys.len());
+        keys
+    }
+}
+
+impl SitesController {
+    /// Exchanges peers of each serving site in background, then connects new peers
+    /// within `connected_limit` of site and `global_connected_limit` over all sites.
+    /// Only found peers and stats of contacted ones are merged back into site.
+    pub fn exchange_peers(&mut self, ctx: &mut Context<Self>) {
+        let connected = self
+            .sites
+            .values()
+            .map(Site::connected_peers)
+            .sum::<usize>();
+        let mut budget = ENV.global_connected_limit.saturating_sub(connected);
+        let serving = self
+            .sites
+            .iter()
+            .filter(|(_, site)| site.storage.settings.serving)
+            .map(|(address, site)| (address.clone(), site.clone()))
+            .collect::<Vec<_>>();
+        for (address, mut site) in serving {
+            let connected = site.connected_peers();
+            let connect = ENV.connected_limit.saturating_sub(connected).min(budget);
+            budget -= connect;
+            let sites_controller = ctx.address();
+            // Peers are exchanged in a task of its own, so actor isn't held up by their I/O
+            tokio::spawn(async move {
+                let started = OffsetDateTime::now_utc();
+                let found = match site.fetch_peers().await {
+                    Ok(found) => {
+                        debug!("Pex found {} new peers for {address}", found.len());
+                        found
+                    }
+                    Err(err) => {
+                        debug!("Pex for {address} failed : {err:?}");
+                        vec![]
+                    }
+                };
+                // Asked peers had their pex time set
+                let mut contacted = site
+                    .peers
+                    .iter()
+                    .filter(|(_, peer)| peer.time_pex().is_some_and(|time| time >= started))
+                    .map(|(key, _)| key.clone())
+                    .collect::<HashSet<_>>();
+                if connect > 0 {
+                    contacted.extend(site.connect_peers(connected + connect).await);
+                }
+                let mut msg = PeersExchanged {
+                    address,
+                    found: vec![],
+                    contacted: vec![],
+                };
+                for (key, peer) in site.peers {
+                    if found.contains(&key) {
+                        msg.found.push(peer);
+                    } else if contacted.contains(&key) {
+                        msg.contacted.push(peer);
+                    }
+                }
+                sites_controller.do_send(msg);
+            });
+        }
+    }
+}
+
+impl Handler<PeersExchanged> for SitesController {
+    type Result = ();
+
+    fn handle(&mut self, msg: PeersExchanged, _ctx: &mut Context<Self>) {
+        let Some(site) = self.sites.get_mut(&msg.address) else {
+            return;
+        };
+        site.update_peer_stats(msg.contacted);
+        for peer in msg.found {
+            site.peers.entry(peer.address().to_string()).or_insert(peer);
+        }
+        site.evict_bad_peers();
+    }
+}
@@ -1,103 +1,111 @@
 // functionality for decoding bencoded byte strings
-#![allow(dead_code)]
 
 use super::{Error, Item};
 
 use std::collections::BTreeMap;
 
-fn parse_int(str: &mut Vec<u8>) -> Result<usize, Error> {
-    let mut len: usize = 0;
-    let mut int_string: String = String::new();
-    for c in str.iter() {
-        len += 1;
-        if *c == b'i' {
-            continue;
-        }
-        if *c == b'e' {
-            break;
-        }
-        int_string.push(*c as char);
+// lists and dicts nested deeper than this are rejected, so input can't overflow stack
+const MAX_DEPTH: usize = 64;
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn peek(&self) -> Result<u8, Error> {
+        self.bytes
+            .get(self.pos)
+            .copied()
+            .ok_or(Error::UnexpectedEnd)
     }
-    str.drain(0..len);
-    let res = int_string.parse::<usize>();
-    if res.is_err() {
-        Err(Error::InvalidInt)
-    } else {
-        Ok(res.unwrap())
+
+    // reads bytes up to `end`, consuming `end` too
+    fn read_until(&mut self, end: u8) -> Result<&'a [u8], Error> {
+        let rest = &self.bytes[self.pos..];
+        let len = rest
+            .iter()
+            .position(|c| *c == end)
+            .ok_or(Error::UnexpectedEnd)?;
+        self.pos += len + 1;
+        Ok(&rest[..len])
     }
-}
 
-fn parse_str(str: &mut Vec<u8>) -> Result<Vec<u8>, Error> {
-    let mut int_len: usize = 0;
-    let mut int_string: String = String::new();
-    for c in str.iter() {
-        int_len += 1;
-        if *c == b':' {
-            break;
-        }
-        int_string.push(*c as char);
+    fn parse_int(&mut self) -> Result<i64, Error> {
+        self.pos += 1;
+        let digits = self.read_until(b'e')?;
+        std::str::from_utf8(digits)
+            .ok()
+            .and_then(|int| int.parse().ok())
+            .ok_or(Error::InvalidInt)
     }
-    let len = int_string.parse::<usize>();
-    if len.is_err() {
-        return Err(Error::InvalidString);
+
+    fn parse_str(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.read_until(b':')?;
+        let len: usize = std::str::from_utf8(len)
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .ok_or(Error::InvalidString)?;
+        let end = self.pos.checked_add(len).ok_or(Error::InvalidString)?;
+        let str = self.bytes.get(self.pos..end).ok_or(Error::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(str.to_vec())
     }
-    let len = len.unwrap();
-    str.drain(0..int_len);
 
-    let s = str[..len].to_vec();
-    let mut copy = str[len..].to_vec();
-    str.clear();
-    str.append(&mut copy);
-    Ok(s)
-}
+    fn parse_list(&mut self) -> Result<Vec<Item>, Error> {
+        self.pos += 1;
+        let mut list = vec![];
+        while self.peek()? != b'e' {
+            list.push(self.parse_item()?);
+        }
+        self.pos += 1;
+        Ok(list)
+    }
 
-fn parse_list(str: &mut Vec<u8>) -> Result<Vec<Item>, Error> {
-    str.drain(0..1);
-    let mut list: Vec<Item> = Vec::<Item>::new();
-    loop {
-        match *str.get(0).unwrap() as char {
-            'i' => list.push(Item::Int(parse_int(str)?)),
-            'l' => list.push(Item::List(parse_list(str)?)),
-            'd' => list.push(Item::Dict(parse_dict(str)?)),
-            '0'..='9' => list.push(Item::String(parse_str(str)?)),
-            'e' => break,
-            _ => unreachable!(),
+    fn parse_dict(&mut self) -> Result<BTreeMap<Vec<u8>, Item>, Error> {
+        self.pos += 1;
+        let mut dict = BTreeMap::new();
+        while self.peek()? != b'e' {
+            let key = self.parse_str()?;
+            let value = self.parse_item()?;
+            dict.insert(key, value);
         }
+        self.pos += 1;
+        Ok(dict)
     }
-    str.drain(0..1);
-    Ok(list)
-}
 
-fn parse_dict(str: &mut Vec<u8>) -> Result<BTreeMap<Vec<u8>, Item>, Error> {
-    str.drain(0..1);
-    let mut dict: BTreeMap<Vec<u8>, Item> = BTreeMap::new();
-    loop {
-        if *str.get(0).unwrap() == b'e' {
-            break;
+    fn parse_nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(Error::TooDeep);
         }
-        let s = parse_str(str)?;
-        match *str.get(0).unwrap() as char {
-            'i' => dict.insert(s, Item::Int(parse_int(str)?)),
-            'l' => dict.insert(s, Item::List(parse_list(str)?)),
-            'd' => dict.insert(s, Item::Dict(parse_dict(str)?)),
-            '0'..='9' => dict.insert(s, Item::String(parse_str(str)?)),
-            _ => unreachable!(),
-        };
+        self.depth += 1;
+        let item = parse(self)?;
+        self.depth -= 1;
+        Ok(item)
     }
-    str.drain(0..1);
-    Ok(dict)
-}
 
-pub fn parse(str: &mut Vec<u8>) -> Result<Vec<Item>, Error> {
-    let mut tree: Vec<Item> = Vec::<Item>::new();
-    while let Some(c) = str.get(0) {
-        match *c {
-            b'i' => tree.push(Item::Int(parse_int(str)?)),
-            b'l' => tree.push(Item::List(parse_list(str)?)),
-            b'd' => tree.push(Item::Dict(parse_dict(str)?)),
-            b'0'..=b'9' => tree.push(Item::String(parse_str(str)?)),
-            _ => break,
+    fn parse_item(&mut self) -> Result<Item, Error> {
+        match self.peek()? {
+            b'i' => Ok(Item::Int(self.parse_int()?)),
+            b'l' => Ok(Item::List(self.parse_nested(Self::parse_list)?)),
+            b'd' => Ok(Item::Dict(self.parse_nested(Self::parse_dict)?)),
+            b'0'..=b'9' => Ok(Item::String(self.parse_str()?)),
+            c => Err(Error::UnexpectedByte(c)),
         }
     }
-    Ok(tree)
+}
+
+// decodes a single bencoded item, which must span all of `bytes`
+pub fn decode(bytes: &[u8]) -> Result<Item, Error> {
+    let mut decoder = Decoder {
+        bytes,
+        pos: 0,
+        depth: 0,
+    };
+    let item = decoder.parse_item()?;
+    if decoder.pos != bytes.len() {
+        return Err(Error::TrailingBytes);
+    }
+    Ok(item)
 }
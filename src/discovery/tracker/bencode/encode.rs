@@ -1,58 +1,35 @@
 // functionality for encoding bencode trees
-#![allow(dead_code)]
 
 use super::Item;
 
-use std::{collections::BTreeMap, str::from_utf8};
-
-fn encode_int(int: usize) -> Vec<u8> {
-    format!("i{}e", int).as_bytes().to_vec()
-}
-
-fn encode_str(str: &[u8]) -> Vec<u8> {
-    format!("{}:{}", str.len(), from_utf8(&str).unwrap())
-        .as_bytes()
-        .to_vec()
-}
-
-fn encode_dict(dict: BTreeMap<Vec<u8>, Item>) -> Vec<u8> {
-    let mut encdict: Vec<u8> = vec![b'd'];
-    for (key, val) in dict {
-        encdict.extend_from_slice(&encode_str(&key));
-        match val {
-            Item::Int(int) => encdict.extend_from_slice(&encode_int(int)),
-            Item::String(str) => encdict.extend_from_slice(&encode_str(&str)),
-            Item::List(list) => encdict.extend_from_slice(&encode_list(list)),
-            Item::Dict(dict) => encdict.extend_from_slice(&encode_dict(dict)),
+fn encode_into(item: &Item, buf: &mut Vec<u8>) {
+    match item {
+        Item::Int(int) => buf.extend_from_slice(format!("i{}e", int).as_bytes()),
+        Item::String(str) => {
+            buf.extend_from_slice(format!("{}:", str.len()).as_bytes());
+            buf.extend_from_slice(str);
         }
-    }
-    encdict.push(b'e');
-    encdict
-}
-
-fn encode_list(list: Vec<Item>) -> Vec<u8> {
-    let mut enclist: Vec<u8> = vec![b'l'];
-    for item in list {
-        match item {
-            Item::Int(int) => enclist.extend_from_slice(&encode_int(int)),
-            Item::String(str) => enclist.extend_from_slice(&encode_str(&str)),
-            Item::List(list) => enclist.extend_from_slice(&encode_list(list)),
-            Item::Dict(dict) => enclist.extend_from_slice(&encode_dict(dict)),
+        Item::List(list) => {
+            buf.push(b'l');
+            for item in list {
+                encode_into(item, buf);
+            }
+            buf.push(b'e');
+        }
+        Item::Dict(dict) => {
+            // BTreeMap keeps keys sorted, as bencode requires
+            buf.push(b'd');
+            for (key, value) in dict {
+                encode_into(&Item::String(key.clone()), buf);
+                encode_into(value, buf);
+            }
+            buf.push(b'e');
         }
     }
-    enclist.push(b'e');
-    enclist
 }
 
-pub fn encode(tree: Vec<Item>) -> Vec<u8> {
-    let mut enctree: Vec<u8> = vec![];
-    for item in tree {
-        match item {
-            Item::Int(int) => enctree.extend_from_slice(&encode_int(int)),
-            Item::String(str) => enctree.extend_from_slice(&encode_str(&str)),
-            Item::List(list) => enctree.extend_from_slice(&encode_list(list)),
-            Item::Dict(dict) => enctree.extend_from_slice(&encode_dict(dict)),
-        }
-    }
-    enctree
+pub fn encode(item: &Item) -> Vec<u8> {
+    let mut buf = vec![];
+    encode_into(item, &mut buf);
+    buf
 }
@@ -1,56 +1,129 @@
 // bencode subfolder and item enum implementation
-#![allow(dead_code)]
 
 pub mod decode;
 pub mod encode;
 
 use std::collections::BTreeMap;
 
-#[derive(Clone, Debug)]
+pub use self::{decode::decode, encode::encode};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Item {
-    Int(usize),
+    Int(i64),
     String(Vec<u8>),
     List(Vec<Item>),
     Dict(BTreeMap<Vec<u8>, Item>),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
     InvalidInt,
     InvalidString,
+    UnexpectedEnd,
+    UnexpectedByte(u8),
+    TrailingBytes,
+    TooDeep,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
 impl Item {
-    #[allow(dead_code)]
-    pub fn get_int(&self) -> usize {
-        let int = match &self {
-            Item::Int(int) => int,
-            _ => unreachable!(),
-        };
-        *int
-    }
-    #[allow(dead_code)]
-    pub fn get_str(&self) -> Vec<u8> {
-        let str = match &self {
-            Item::String(str) => str,
-            _ => unreachable!(),
-        };
-        str.clone()
-    }
-    #[allow(dead_code)]
-    pub fn get_list(&self) -> Vec<Item> {
-        let list = match &self {
-            Item::List(list) => list,
-            _ => unreachable!(),
-        };
-        list.clone()
-    }
-    #[allow(dead_code)]
-    pub fn get_dict(&self) -> BTreeMap<Vec<u8>, Item> {
-        let dict = match &self {
-            Item::Dict(dict) => dict,
-            _ => unreachable!(),
-        };
-        dict.clone()
+    pub fn get_int(&self) -> Option<i64> {
+        match self {
+            Item::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self) -> Option<&[u8]> {
+        match self {
+            Item::String(str) => Some(str),
+            _ => None,
+        }
+    }
+
+    pub fn get_list(&self) -> Option<&[Item]> {
+        match self {
+            Item::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn get_dict(&self) -> Option<&BTreeMap<Vec<u8>, Item>> {
+        match self {
+            Item::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    // looks up key of a dict item
+    pub fn get(&self, key: &str) -> Option<&Item> {
+        self.get_dict()?.get(key.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dict(entries: Vec<(&str, Item)>) -> Item {
+        Item::Dict(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let item = dict(vec![
+            ("interval", Item::Int(1800)),
+            ("offset", Item::Int(-42)),
+            ("peers", Item::String(vec![127, 0, 0, 1, 0x3c, 0x51])),
+            (
+                "list",
+                Item::List(vec![Item::String(b"spam".to_vec()), Item::List(vec![])]),
+            ),
+            ("empty", dict(vec![])),
+        ]);
+        let encoded = encode(&item);
+        assert_eq!(decode(&encoded).unwrap(), item);
+    }
+
+    #[test]
+    fn test_decode() {
+        let item = decode(b"d8:intervali900e5:peersld2:ip9:127.0.0.14:porti15441eeee").unwrap();
+        assert_eq!(item.get("interval").and_then(Item::get_int), Some(900));
+        let peer = &item.get("peers").and_then(Item::get_list).unwrap()[0];
+        assert_eq!(
+            peer.get("ip").and_then(Item::get_str),
+            Some(&b"127.0.0.1"[..])
+        );
+        assert_eq!(
+            encode(&item),
+            b"d8:intervali900e5:peersld2:ip9:127.0.0.14:porti15441eeee"
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(decode(b"i12"), Err(Error::UnexpectedEnd));
+        assert_eq!(decode(b"i1x2e"), Err(Error::InvalidInt));
+        assert_eq!(decode(b"5:abc"), Err(Error::UnexpectedEnd));
+        assert_eq!(decode(b"i1ei2e"), Err(Error::TrailingBytes));
+        assert_eq!(decode(b"x"), Err(Error::UnexpectedByte(b'x')));
+    }
+
+    #[test]
+    fn test_decode_deep_nesting() {
+        let nested = |depth| [vec![b'l'; depth], vec![b'e'; depth]].concat();
+        assert!(decode(&nested(64)).is_ok());
+        assert_eq!(decode(&nested(65)), Err(Error::TooDeep));
+        assert_eq!(decode(&vec![b'l'; 1024 * 1024]), Err(Error::TooDeep));
     }
 }
@@ -1,106 +1,312 @@
-// http tracker functionality
-#![allow(dead_code)]
-
-use super::IpPort;
-
-use super::bencode::{decode::parse, Item};
+// http(s) tracker functionality, see https://www.bittorrent.org/beps/bep_0003.html#trackers
 
 use std::{
     io::{Error, ErrorKind},
-    net::SocketAddr,
-    str::from_utf8,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
 };
 
+use lazy_static::lazy_static;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
+    time::timeout,
+};
+use tokio_rustls::{
+    rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
 };
 
-// takes in info_hash and tracker addr, announces and gets peer IpPorts
-pub async fn http_announce(
-    addr: SocketAddr,
+use super::{
+    bencode::{decode, Item},
+    udp::parse_peers,
+};
+use crate::net::socks::socks5_connect_async;
+
+// whole request, including connecting and reading response, must finish within this time
+const TIMEOUT: Duration = Duration::from_secs(30);
+// responses larger than this are not tracker responses
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+// # of peers to request
+const NUM_WANT: usize = 200;
+
+lazy_static! {
+    static ref TLS_CONFIG: Arc<ClientConfig> = {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Arc::new(config)
+    };
+}
+
+/// Resolved http or https tracker url.
+#[derive(Debug, Clone)]
+pub struct HttpTracker {
+    pub addr: SocketAddr,
+    /// Host as given in url, sent in `Host` header and used as TLS server name.
+    pub host: String,
+    /// Announce path, including query of url if any.
+    pub path: String,
+    pub tls: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpAnnounceResponse {
+    pub interval: Option<u64>,
+    pub min_interval: Option<u64>,
+    pub warning: Option<String>,
+    pub peers: Vec<SocketAddr>,
+}
+
+fn url_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 3);
+    for byte in bytes {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(*byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn announce_request(
+    tracker: &HttpTracker,
     info_hash: [u8; 20],
     port: u16,
-    anounce_route: Option<&str>,
+    left: u64,
     peer_id: &str,
-) -> Result<Vec<IpPort>, Error> {
-    let mut get: Vec<u8> = vec![];
-    // prefix
-    let _route = if let Some(route) = anounce_route {
-        format!("/{}", route)
+) -> String {
+    let separator = if tracker.path.contains('?') { '&' } else { '?' };
+    format!(
+        "GET {}{}info_hash={}&peer_id={}&port={}&uploaded=0&downloaded=0&left={}\
+        &event=started&numwant={}&compact=1 HTTP/1.1\r\n\
+        Host: {}:{}\r\nUser-Agent: ZeroNet\r\nAccept-Encoding: identity\r\nConnection: close\r\n\r\n",
+        tracker.path,
+        separator,
+        url_encode(&info_hash),
+        url_encode(peer_id.as_bytes()),
+        port,
+        left,
+        NUM_WANT,
+        tracker.host,
+        tracker.addr.port(),
+    )
+}
+
+async fn send_request<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: &[u8],
+) -> Result<Vec<u8>, Error> {
+    stream.write_all(request).await?;
+    let mut buf = vec![];
+    stream.take(MAX_RESPONSE_SIZE).read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+// joins chunks of `Transfer-Encoding: chunked` body
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid chunked body");
+    let mut res = vec![];
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(invalid)?;
+        let size = std::str::from_utf8(&body[..line_end]).map_err(|_| invalid())?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(res);
+        }
+        res.extend_from_slice(body.get(..size).ok_or_else(invalid)?);
+        body = body.get(size + 2..).ok_or_else(invalid)?;
+    }
+}
+
+// splits http response into status code and body
+fn parse_http_response(res: &[u8]) -> Result<(u16, Vec<u8>), Error> {
+    let header_end = res
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid Response"))?;
+    let header = String::from_utf8_lossy(&res[..header_end]).to_ascii_lowercase();
+    let status = header
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid Response Status"))?;
+    let body = &res[header_end + 4..];
+    let body = if header.contains("transfer-encoding: chunked") {
+        dechunk(body)?
     } else {
-        format!("/announce")
+        body.to_vec()
     };
-    let mut base: String = "GET /announce?info_hash=".to_string();
-    // appending each hex as a string
-    for byte in &info_hash {
-        base.push_str(&format!("%{:02x}", byte));
+    Ok((status, body))
+}
+
+fn parse_dict_peers(peers: &[Item]) -> Vec<SocketAddr> {
+    peers
+        .iter()
+        .filter_map(|peer| {
+            let ip = std::str::from_utf8(peer.get("ip")?.get_str()?).ok()?;
+            let port = u16::try_from(peer.get("port")?.get_int()?).ok()?;
+            Some(SocketAddr::new(ip.parse::<IpAddr>().ok()?, port))
+        })
+        .collect()
+}
+
+/// Parses bencoded announce response, `failure reason` is returned as error.
+pub fn parse_announce_response(body: &[u8]) -> Result<HttpAnnounceResponse, Error> {
+    let item = decode(body).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Parse Response Error: {err}"),
+        )
+    })?;
+    let text = |key| {
+        item.get(key)
+            .and_then(Item::get_str)
+            .map(|str| String::from_utf8_lossy(str).to_string())
+    };
+    if let Some(reason) = text("failure reason") {
+        return Err(Error::other(format!("Tracker error: {reason}")));
     }
-    // append suffix of get request
-    base.push_str("&peer_id=");
-    base.push_str(peer_id);
-    base.push_str("&port=");
-    base.push_str(&format!("{}", port));
-    base.push_str("&uploaded=0&downloaded=0&left=1456927919\
-    &corrupt=0&key=8B26698B&event=started&numwant=200&compact=1&no_peer_id=1&supportcrypto=1&redundant=0\
-    HTTP/1.1\r\n\r\n");
-    // convert base to Vec<u8> and append to get vector
-    get.extend_from_slice(&base.as_bytes());
-    // connect to the tracker
-    let mut stream = TcpStream::connect(addr).await?;
-    // send the get request to the tracker
-    stream.write_all(&get).await?;
-    // read it's reply
-    let mut buf: Vec<u8> = vec![0; 10000];
-    let len;
-    match stream.read(&mut buf).await {
-        Ok(l) => len = l,
-        Err(e) => return Err(Error::new(ErrorKind::Other, e.to_string())),
+    let interval = |key| {
+        item.get(key)
+            .and_then(Item::get_int)
+            .and_then(|int| u64::try_from(int).ok())
+    };
+    let mut peers = match item.get("peers") {
+        Some(Item::String(compact)) => parse_peers(compact, false),
+        Some(Item::List(dicts)) => parse_dict_peers(dicts),
+        _ => vec![],
+    };
+    if let Some(compact) = item.get("peers6").and_then(Item::get_str) {
+        peers.extend(parse_peers(compact, true));
     }
-    buf.truncate(len);
-    // remove http header
-    let mut count = 0;
-    for i in buf.windows(4) {
-        count += 1;
-        if i.iter().zip(b"\r\n\r\n").all(|(a, b)| *a == *b) {
-            break;
+    Ok(HttpAnnounceResponse {
+        interval: interval("interval"),
+        min_interval: interval("min interval"),
+        warning: text("warning message"),
+        peers,
+    })
+}
+
+// announces to http(s) tracker, gets peers and requested announce intervals,
+// connecting through socks5 `proxy` if given
+pub async fn http_announce(
+    tracker: &HttpTracker,
+    info_hash: [u8; 20],
+    port: u16,
+    left: u64,
+    peer_id: &str,
+    proxy: Option<SocketAddr>,
+) -> Result<HttpAnnounceResponse, Error> {
+    let request = announce_request(tracker, info_hash, port, left, peer_id);
+    let host = tracker.host.trim_start_matches('[').trim_end_matches(']');
+    let res = timeout(TIMEOUT, async {
+        let stream = match proxy {
+            Some(proxy) => socks5_connect_async(proxy, host, tracker.addr.port()).await?,
+            None => TcpStream::connect(tracker.addr).await?,
         };
-    }
-    if (count + 4) > buf.len() {
-        return Err(Error::new(ErrorKind::Other, "Invalid Response"));
-    }
-    buf.drain(0..count + 4);
-    if buf[0] != b'd' {
-        buf.insert(0, b'd');
-        buf.push(b'e');
-    }
-    let parse = parse(&mut buf);
-    if parse.is_err() {
-        return Err(Error::new(ErrorKind::Other, "Parse Response Error"));
-    }
-    // parse out ip port and return
-    let tree: Vec<Item> = parse.unwrap();
-    match &tree[0] {
-        Item::Dict(d) => {
-            if let Some(e) = d.get("failure reason".as_bytes()) {
-                match e {
-                    Item::String(s) => {
-                        return Err(Error::new(
-                            ErrorKind::Other,
-                            from_utf8(s).unwrap().to_string(),
-                        ));
-                    }
-                    _ => unreachable!(),
-                }
-            }
+        if tracker.tls {
+            let server_name = ServerName::try_from(host)
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+            let stream = TlsConnector::from(TLS_CONFIG.clone())
+                .connect(server_name, stream)
+                .await?;
+            send_request(stream, request.as_bytes()).await
+        } else {
+            send_request(stream, request.as_bytes()).await
         }
-        _ => unreachable!(),
+    })
+    .await
+    .map_err(|_| Error::new(ErrorKind::TimedOut, "Tracker request timed out"))??;
+    let (status, body) = parse_http_response(&res)?;
+    // Trackers may report failure reason along with error status
+    match parse_announce_response(&body) {
+        Err(err) if err.kind() == ErrorKind::InvalidData && status != 200 => Err(Error::other(
+            format!("Tracker responded with status {status}"),
+        )),
+        res => res,
     }
-    let peers = tree[0]
-        .get_dict()
-        .get("peers".as_bytes())
-        .unwrap()
-        .get_str();
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
 
-    Ok(IpPort::from_bytes(&peers))
+    #[test]
+    fn test_parse_announce_response() {
+        let mut body = b"d8:intervali1800e12:min intervali900e5:peers6:".to_vec();
+        body.extend_from_slice(&[127, 0, 0, 1, 0x3c, 0x51]);
+        body.extend_from_slice(b"6:peers618:");
+        body.extend_from_slice(&std::net::Ipv6Addr::LOCALHOST.octets());
+        body.extend_from_slice(&[0x3c, 0x51]);
+        body.extend_from_slice(b"15:warning message4:busye");
+        let res = parse_announce_response(&body).unwrap();
+        assert_eq!(res.interval, Some(1800));
+        assert_eq!(res.min_interval, Some(900));
+        assert_eq!(res.warning.as_deref(), Some("busy"));
+        let peers = vec![
+            "127.0.0.1:15441".parse().unwrap(),
+            "[::1]:15441".parse().unwrap(),
+        ];
+        assert_eq!(res.peers, peers);
+
+        let body = b"d5:peersld2:ip9:127.0.0.17:peer id20:-qB4250-rj6kZQu4P_Mh4:porti15441eeee";
+        let res = parse_announce_response(body).unwrap();
+        assert_eq!(res.peers, vec!["127.0.0.1:15441".parse().unwrap()]);
+        assert_eq!(res.interval, None);
+
+        let err = parse_announce_response(b"d14:failure reason12:unregisterede").unwrap_err();
+        assert_eq!(err.to_string(), "Tracker error: unregistered");
+    }
+
+    #[tokio::test]
+    async fn test_http_announce() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let len = stream.read(&mut buf).await.unwrap();
+            let body = b"d8:intervali60e5:peers6:\x7f\x00\x00\x01\x3c\x51e";
+            let mut res = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+            res.extend_from_slice(format!("{:x}\r\n", body.len()).as_bytes());
+            res.extend_from_slice(body);
+            res.extend_from_slice(b"\r\n0\r\n\r\n");
+            stream.write_all(&res).await.unwrap();
+            String::from_utf8_lossy(&buf[..len]).to_string()
+        });
+        let tracker = HttpTracker {
+            addr,
+            host: "localhost".into(),
+            path: "/announce?passkey=abc".into(),
+            tls: false,
+        };
+        let res = http_announce(&tracker, [0xff; 20], 15441, 0, "-ZN0000-abcdefghijkl", None)
+            .await
+            .unwrap();
+        assert_eq!(res.interval, Some(60));
+        assert_eq!(res.peers, vec!["127.0.0.1:15441".parse().unwrap()]);
+        let request = server.await.unwrap();
+        assert!(request.starts_with("GET /announce?passkey=abc&info_hash=%FF%FF"));
+        assert!(request.contains(&format!("Host: localhost:{}\r\n", addr.port())));
+    }
 }
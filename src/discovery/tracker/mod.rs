@@ -6,16 +6,24 @@
 pub mod bencode;
 pub mod http;
 pub mod udp;
+pub mod zero;
 
 use std::{
-    io::Error,
+    io::{Error, ErrorKind},
     net::{SocketAddr, ToSocketAddrs},
+    time::Duration,
 };
 
+use decentnet_protocol::address::PeerAddr;
+use log::*;
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 
-use self::{http::http_announce, udp::udp_announce};
+use self::{
+    http::{http_announce, HttpTracker},
+    udp::udp_announce,
+};
+use crate::{environment::ENV, net::socks::use_proxy};
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct IpPort {
     pub ip: u32,
@@ -43,6 +51,12 @@ impl IpPort {
     }
 }
 
+impl From<IpPort> for SocketAddr {
+    fn from(peer: IpPort) -> Self {
+        SocketAddr::from((std::net::Ipv4Addr::from(peer.ip), peer.port))
+    }
+}
+
 impl std::fmt::Debug for IpPort {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let one: u64 = (u64::from(self.ip) & (0xff << 24)) >> 24;
@@ -74,53 +88,126 @@ pub fn get_info_hash(addr: String) -> [u8; 20] {
     hasher.finalize().into()
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Addr {
     Udp(SocketAddr),
-    Http(SocketAddr),
+    Http(HttpTracker),
+    /// ZeroNet bootstrapper, announced to with `announce` command, see [`zero::zero_announce`].
+    /// Onion bootstrappers keep their host, to be reached through proxy.
+    Zero(PeerAddr),
 }
 
 pub fn make_addr(addr: &str) -> Result<Addr, String> {
-    let udp = addr.starts_with("udp");
-    let i = addr.find("://");
-    let addr = if let Some(i) = i {
-        &addr[i + 3..]
-    } else {
-        addr
+    let (scheme, rest) = addr.split_once("://").unwrap_or(("http", addr));
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/announce"),
     };
-    let has_announce = addr.find("/announce");
-    let addr = if let Some(i) = has_announce {
-        &addr[..i]
-    } else {
-        addr
+    let default_port = match scheme {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
     };
-    if let Ok(addrs) = addr.to_socket_addrs() {
-        // resolve socketaddr
-        let mut addrs = addrs.into_iter();
-        match addrs.next() {
-            Some(s) => {
-                if udp {
-                    return Ok(Addr::Udp(s));
-                } else {
-                    return Ok(Addr::Http(s));
-                }
-            }
-            None => return Err("no addr resolved".to_string()),
+    // Port is optional for http(s) trackers, last colon outside of ipv6 brackets separates it
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']') && port.parse::<u16>().is_ok());
+    let (host, port) = match (has_port, default_port) {
+        (true, _) => {
+            let (host, port) = authority.rsplit_once(':').unwrap();
+            (host, port.parse::<u16>().unwrap())
+        }
+        (false, Some(port)) => (authority, port),
+        (false, None) => return Err(format!("no port given in address: {}", addr)),
+    };
+    let onion = host.ends_with(".onion");
+    match (scheme, onion) {
+        // Onion hosts are only reachable through proxy, which resolves them
+        ("zero", true) => {
+            return PeerAddr::parse(format!("{}:{}", host, port))
+                .map(Addr::Zero)
+                .map_err(|_| format!("invalid onion address: {}", addr));
         }
+        ("udp", true) => return Err(format!("udp trackers can't be reached at onion: {}", addr)),
+        _ => {}
+    }
+    let resolved = if onion {
+        // Http trackers connect to host through proxy, only port of resolved addr is used
+        SocketAddr::from(([0, 0, 0, 0], port))
+    } else {
+        (host.trim_start_matches('[').trim_end_matches(']'), port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| format!("no addr resolved for adddress: {}", addr))?
+    };
+    match scheme {
+        "udp" => Ok(Addr::Udp(resolved)),
+        "zero" => PeerAddr::parse(resolved.to_string())
+            .map(Addr::Zero)
+            .map_err(|_| format!("invalid address: {}", addr)),
+        "http" | "https" => Ok(Addr::Http(HttpTracker {
+            addr: resolved,
+            host: host.to_string(),
+            path: path.to_string(),
+            tls: scheme == "https",
+        })),
+        _ => Err(format!("unsupported tracker scheme: {}", scheme)),
     }
-    Err(format!("no addr resolved for adddress: {}", addr))
+}
+
+/// Announce intervals requested by tracker, in addition to returned peers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AnnounceInterval {
+    /// Time to wait before regular re-announces.
+    pub interval: Option<Duration>,
+    /// Time to wait before any re-announce.
+    pub min_interval: Option<Duration>,
 }
 
 pub async fn announce(
     addr: Addr,
     info_hash: [u8; 20],
     port: u16,
+    left: u64,
     peer_id: &str,
-) -> Result<Vec<IpPort>, Error> {
-    println!("{:?}", addr);
+) -> Result<(Vec<SocketAddr>, AnnounceInterval), Error> {
     match addr {
-        Addr::Http(a) => http_announce(a, info_hash, port, None, peer_id).await,
-        Addr::Udp(a) => udp_announce(a, info_hash, port, peer_id).await,
+        Addr::Http(tracker) => {
+            let host = tracker.host.trim_start_matches('[').trim_end_matches(']');
+            let proxy = use_proxy(host).then_some(ENV.proxy);
+            if proxy.is_none() && host.ends_with(".onion") {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "onion trackers can only be reached through proxy",
+                ));
+            }
+            let res = http_announce(&tracker, info_hash, port, left, peer_id, proxy).await?;
+            if let Some(warning) = res.warning {
+                warn!("Tracker {} warning : {}", tracker.host, warning);
+            }
+            let interval = AnnounceInterval {
+                interval: res.interval.map(Duration::from_secs),
+                min_interval: res.min_interval.map(Duration::from_secs),
+            };
+            Ok((res.peers, interval))
+        }
+        Addr::Udp(a) if use_proxy(&a.ip().to_string()) => Err(Error::new(
+            ErrorKind::Unsupported,
+            "udp trackers can't be reached through socks5 proxy",
+        )),
+        Addr::Udp(a) => {
+            let res = udp_announce(a, info_hash, port, left, peer_id).await?;
+            let interval = AnnounceInterval {
+                interval: Some(Duration::from_secs(res.interval as u64)),
+                min_interval: None,
+            };
+            Ok((res.peers, interval))
+        }
+        Addr::Zero(_) => Err(Error::new(
+            ErrorKind::Unsupported,
+            "zero:// trackers don't use info hash, use zero_announce",
+        )),
     }
 }
 
@@ -137,13 +224,33 @@ mod tests {
         assert_eq!(&hash, "29d191d7caf351ba054a9cb38e8d8477c19bdd1c");
     }
 
+    #[test]
+    fn test_make_addr() {
+        let addr = make_addr("zero://127.0.0.1:15441").unwrap();
+        assert!(matches!(addr, Addr::Zero(a) if a.to_string() == "127.0.0.1:15441"));
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:15441";
+        let addr = make_addr(&format!("zero://{}", onion)).unwrap();
+        assert!(matches!(addr, Addr::Zero(a) if a.to_string() == onion));
+        assert!(make_addr(&format!("udp://{}/announce", onion)).is_err());
+        let addr = make_addr("udp://127.0.0.1:1337/announce").unwrap();
+        assert!(matches!(addr, Addr::Udp(_)));
+        let addr = make_addr("https://127.0.0.1/announce?passkey=abc").unwrap();
+        assert!(
+            matches!(addr, Addr::Http(t) if t.tls && t.addr.port() == 443
+            && t.host == "127.0.0.1" && t.path == "/announce?passkey=abc")
+        );
+        let addr = make_addr("http://[::1]:6969").unwrap();
+        assert!(matches!(addr, Addr::Http(t) if t.addr.port() == 6969 && t.path == "/announce"));
+        assert!(make_addr("udp://127.0.0.1/announce").is_err());
+    }
+
     #[tokio::test]
     async fn test_announce() {
         let tracker_addr = "udp://tracker.opentrackr.org:1337/announce";
         let site_addr = "15UYrA7aXr2Nto1Gg4yWXpY3EAJwafMTNk".to_string();
         let info_hash = get_info_hash(site_addr.to_string());
         let tracker_addr = make_addr(tracker_addr).unwrap();
-        let res = announce(tracker_addr, info_hash, 0, "-qB4250-rj6kZQu4P_Mh").await;
+        let res = announce(tracker_addr, info_hash, 1, 0, "-qB4250-rj6kZQu4P_Mh").await;
         assert!(res.is_ok());
         println!("{:?}", res.unwrap());
     }
@@ -1,138 +1,393 @@
-// udp tracker functionality
-#![allow(dead_code)]
+// udp tracker functionality, see https://www.bittorrent.org/beps/bep_0015.html
 
-use super::IpPort;
-
-use std::{io::Error, net::SocketAddr, vec};
-
-use tokio::net::UdpSocket;
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
+use lazy_static::lazy_static;
 use rand::random;
-use serde::{Deserialize, Serialize};
+use tokio::{net::UdpSocket, time::timeout};
 
 // literal magic number used for handshake
 const MAGIC: u64 = 0x0417_2710_1980;
-// # of peers to request
-const PEERS: usize = 32;
-
-// structs to be (de)serialized and sent/received
-#[derive(Serialize, Deserialize, Debug)]
-struct ConnectReq {
-    protocol_id: u64,
-    action: u32,
-    transaction_id: u32,
+// # of peers to request, -1 lets tracker decide
+const NUM_WANT: i32 = -1;
+// first response timeout, doubled with each retransmission
+const TIMEOUT: Duration = Duration::from_secs(15);
+// retransmissions before giving up, BEP 15 allows up to 8
+const MAX_RETRIES: u32 = 2;
+// connection ids may be reused for a minute after being received
+const CONNECTION_ID_TTL: Duration = Duration::from_secs(60);
+
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+const ACTION_ERROR: u32 = 3;
+
+lazy_static! {
+    static ref CONNECTION_IDS: Mutex<HashMap<SocketAddr, (u64, Instant)>> =
+        Mutex::new(HashMap::new());
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ConnectResp {
-    action: u32,
-    transaction_id: u32,
-    connection_id: u64,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    None = 0,
+    Completed = 1,
+    Started = 2,
+    Stopped = 3,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct AnnounceReq {
-    connection_id: u64,
-    action: u32,
-    transaction_id: u32,
-    info_hash: [u8; 20],
-    peer_id: [u8; 20],
-    downloaded: u64,
-    left: u64,
-    uploaded: u64,
-    event: u32,
-    ip_address: u32,
-    key: u32,
-    num_want: u32,
-    port: u16,
+#[derive(Debug, Clone)]
+pub struct AnnounceRequest {
+    pub info_hash: [u8; 20],
+    pub peer_id: [u8; 20],
+    pub downloaded: u64,
+    pub left: u64,
+    pub uploaded: u64,
+    pub event: Event,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnnounceResponse {
+    pub interval: u32,
+    pub leechers: u32,
+    pub seeders: u32,
+    pub peers: Vec<SocketAddr>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct AnnounceResp {
-    action: u32,
-    transaction_id: u32,
-    interval: u32,
-    leechers: u32,
-    seeders: u32,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScrapeStats {
+    pub seeders: u32,
+    pub completed: u32,
+    pub leechers: u32,
 }
 
-impl AnnounceResp {
-    fn from_be(&mut self) -> &mut Self {
-        self.action = u32::from_be(self.action);
-        self.transaction_id = u32::from_be(self.transaction_id);
-        self.interval = u32::from_be(self.interval);
-        self.leechers = u32::from_be(self.leechers);
-        self.seeders = u32::from_be(self.seeders);
+pub struct UdpTrackerClient {
+    addr: SocketAddr,
+    timeout: Duration,
+    max_retries: u32,
+}
 
+impl UdpTrackerClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            timeout: TIMEOUT,
+            max_retries: MAX_RETRIES,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration, max_retries: u32) -> Self {
+        self.timeout = timeout;
+        self.max_retries = max_retries;
         self
     }
+
+    async fn socket(&self) -> Result<UdpSocket, Error> {
+        let bind = if self.addr.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        UdpSocket::bind(bind).await
+    }
+
+    /// Sends `packet` until a response with matching transaction id arrives,
+    /// waiting twice as long after each retransmission. Returns response body after header.
+    async fn request(
+        &self,
+        socket: &UdpSocket,
+        packet: &[u8],
+        transaction_id: u32,
+        action: u32,
+    ) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0_u8; 65536];
+        for attempt in 0..=self.max_retries {
+            socket.send_to(packet, self.addr).await?;
+            let wait = self.timeout * 2_u32.pow(attempt);
+            let res = timeout(wait, async {
+                loop {
+                    let (len, from) = socket.recv_from(&mut buf).await?;
+                    if from != self.addr || len < 8 || read_u32(&buf, 4) != transaction_id {
+                        continue;
+                    }
+                    return Ok::<_, Error>(buf[..len].to_vec());
+                }
+            })
+            .await;
+            let Ok(res) = res else {
+                continue;
+            };
+            let res = res?;
+            return match read_u32(&res, 0) {
+                a if a == action => Ok(res[8..].to_vec()),
+                ACTION_ERROR => Err(Error::other(format!(
+                    "Tracker error: {}",
+                    String::from_utf8_lossy(&res[8..])
+                ))),
+                a => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unexpected action {a} in tracker response"),
+                )),
+            };
+        }
+        Err(Error::new(
+            ErrorKind::TimedOut,
+            format!("No response from tracker {}", self.addr),
+        ))
+    }
+
+    async fn connection_id(&self, socket: &UdpSocket) -> Result<u64, Error> {
+        if let Some((connection_id, received)) = CONNECTION_IDS.lock().unwrap().get(&self.addr)
+            && received.elapsed() < CONNECTION_ID_TTL
+        {
+            return Ok(*connection_id);
+        }
+        let transaction_id = random::<u32>();
+        let mut packet = Vec::with_capacity(16);
+        packet.extend_from_slice(&MAGIC.to_be_bytes());
+        packet.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+        packet.extend_from_slice(&transaction_id.to_be_bytes());
+        let res = self
+            .request(socket, &packet, transaction_id, ACTION_CONNECT)
+            .await?;
+        if res.len() < 8 {
+            return Err(Error::new(ErrorKind::InvalidData, "Short connect response"));
+        }
+        let connection_id = u64::from_be_bytes(res[..8].try_into().unwrap());
+        CONNECTION_IDS
+            .lock()
+            .unwrap()
+            .insert(self.addr, (connection_id, Instant::now()));
+        Ok(connection_id)
+    }
+
+    /// Cached connection id may have been expired by tracker, drop it after failures.
+    fn forget_connection(&self) {
+        CONNECTION_IDS.lock().unwrap().remove(&self.addr);
+    }
+
+    pub async fn announce(&self, req: &AnnounceRequest) -> Result<AnnounceResponse, Error> {
+        let socket = self.socket().await?;
+        let connection_id = self.connection_id(&socket).await?;
+        let transaction_id = random::<u32>();
+        let mut packet = Vec::with_capacity(98);
+        packet.extend_from_slice(&connection_id.to_be_bytes());
+        packet.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+        packet.extend_from_slice(&transaction_id.to_be_bytes());
+        packet.extend_from_slice(&req.info_hash);
+        packet.extend_from_slice(&req.peer_id);
+        packet.extend_from_slice(&req.downloaded.to_be_bytes());
+        packet.extend_from_slice(&req.left.to_be_bytes());
+        packet.extend_from_slice(&req.uploaded.to_be_bytes());
+        packet.extend_from_slice(&(req.event as u32).to_be_bytes());
+        packet.extend_from_slice(&0_u32.to_be_bytes()); // ip address, 0 for sender's
+        packet.extend_from_slice(&random::<u32>().to_be_bytes()); // key
+        packet.extend_from_slice(&NUM_WANT.to_be_bytes());
+        packet.extend_from_slice(&req.port.to_be_bytes());
+        let res = self
+            .request(&socket, &packet, transaction_id, ACTION_ANNOUNCE)
+            .await
+            .inspect_err(|_| self.forget_connection())?;
+        if res.len() < 12 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Short announce response",
+            ));
+        }
+        Ok(AnnounceResponse {
+            interval: read_u32(&res, 0),
+            leechers: read_u32(&res, 4),
+            seeders: read_u32(&res, 8),
+            peers: parse_peers(&res[12..], self.addr.is_ipv6()),
+        })
+    }
+
+    pub async fn scrape(&self, info_hashes: &[[u8; 20]]) -> Result<Vec<ScrapeStats>, Error> {
+        let socket = self.socket().await?;
+        let connection_id = self.connection_id(&socket).await?;
+        let transaction_id = random::<u32>();
+        let mut packet = Vec::with_capacity(16 + 20 * info_hashes.len());
+        packet.extend_from_slice(&connection_id.to_be_bytes());
+        packet.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+        packet.extend_from_slice(&transaction_id.to_be_bytes());
+        for info_hash in info_hashes {
+            packet.extend_from_slice(info_hash);
+        }
+        let res = self
+            .request(&socket, &packet, transaction_id, ACTION_SCRAPE)
+            .await
+            .inspect_err(|_| self.forget_connection())?;
+        Ok(res
+            .chunks_exact(12)
+            .map(|chunk| ScrapeStats {
+                seeders: read_u32(chunk, 0),
+                completed: read_u32(chunk, 4),
+                leechers: read_u32(chunk, 8),
+            })
+            .collect())
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+// peers are 6 byte ipv4 or 18 byte ipv6 entries, depending on address family of tracker
+pub(super) fn parse_peers(bytes: &[u8], ipv6: bool) -> Vec<SocketAddr> {
+    if ipv6 {
+        bytes
+            .chunks_exact(18)
+            .map(|chunk| {
+                let ip: [u8; 16] = chunk[..16].try_into().unwrap();
+                let port = u16::from_be_bytes([chunk[16], chunk[17]]);
+                SocketAddr::from((Ipv6Addr::from(ip), port))
+            })
+            .collect()
+    } else {
+        bytes
+            .chunks_exact(6)
+            .map(|chunk| {
+                let ip: [u8; 4] = chunk[..4].try_into().unwrap();
+                let port = u16::from_be_bytes([chunk[4], chunk[5]]);
+                SocketAddr::from((Ipv4Addr::from(ip), port))
+            })
+            .collect()
+    }
+}
+
+/// Peer id sent to trackers must be exactly 20 bytes.
+pub fn peer_id_bytes(peer_id: &str) -> [u8; 20] {
+    let mut bytes = [0_u8; 20];
+    let len = peer_id.len().min(20);
+    bytes[..len].copy_from_slice(&peer_id.as_bytes()[..len]);
+    bytes
 }
 
-// announces to udp tracker, gets vector of ip and ports
+// announces to udp tracker, gets peer addresses and announce interval
 pub async fn udp_announce(
     addr: SocketAddr,
     info_hash: [u8; 20],
     port: u16,
+    left: u64,
     peer_id: &str,
-) -> Result<Vec<IpPort>, Error> {
-    // set up udp socket
-    let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-
-    // init structs and serialize
-    let conreq = ConnectReq {
-        protocol_id: u64::to_be(MAGIC),
-        action: 0,
-        transaction_id: random::<u32>(),
-    };
-    let mut conresp = ConnectResp {
-        action: 0,
-        transaction_id: 0,
-        connection_id: 0,
-    };
-    let mut serreq: Vec<u8> = bincode::serialize(&conreq).unwrap();
-    let mut serresp: Vec<u8> = bincode::serialize(&conresp).unwrap();
-
-    // send connection request and get response
-    socket.send_to(&serreq, addr).await?;
-    socket.recv_from(&mut serresp).await?;
-
-    // deserialize struct and check tx id
-    conresp = bincode::deserialize(&serresp).unwrap();
-
-    let peer_id = peer_id.as_bytes()[..20].try_into().unwrap();
-    // init structs and serialize
-    let announce_req = AnnounceReq {
-        connection_id: conresp.connection_id,
-        action: u32::to_be(1),
-        transaction_id: random::<u32>(),
+) -> Result<AnnounceResponse, Error> {
+    let req = AnnounceRequest {
         info_hash,
-        peer_id,
+        peer_id: peer_id_bytes(peer_id),
         downloaded: 0,
-        left: 0,
+        left,
         uploaded: 0,
-        event: 0,
-        ip_address: 0,
-        key: 0,
-        num_want: u32::to_be(200),
-        port: u16::to_be(port),
+        event: Event::Started,
+        port,
     };
-    serreq = bincode::serialize(&announce_req).unwrap();
-    let mut resp_buf = vec![0_u8; 32767];
+    UdpTrackerClient::new(addr).announce(&req).await
+}
 
-    // send announce request and get response
-    socket.send_to(&serreq, addr).await?;
-    let bytes = socket.recv_from(&mut resp_buf).await?.0;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    resp_buf.truncate(bytes);
+    const CONNECTION_ID: u64 = 0x1234_5678;
 
-    let drain_range = if resp_buf.len() >= 20 {
-        20
-    } else {
-        resp_buf.len()
-    };
+    /// Minimal in-process tracker, ignores first `drop_first` packets to test retransmission.
+    async fn fake_tracker(drop_first: usize, error: Option<&'static str>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0_u8; 2048];
+            let mut dropped = 0;
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                if dropped < drop_first {
+                    dropped += 1;
+                    continue;
+                }
+                let req = &buf[..len];
+                let action = read_u32(req, 8);
+                let transaction_id = &req[12..16];
+                let mut res = vec![];
+                if action == ACTION_CONNECT {
+                    assert_eq!(u64::from_be_bytes(req[..8].try_into().unwrap()), MAGIC);
+                    res.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+                    res.extend_from_slice(transaction_id);
+                    res.extend_from_slice(&CONNECTION_ID.to_be_bytes());
+                } else if let Some(error) = error {
+                    res.extend_from_slice(&ACTION_ERROR.to_be_bytes());
+                    res.extend_from_slice(transaction_id);
+                    res.extend_from_slice(error.as_bytes());
+                } else if action == ACTION_ANNOUNCE {
+                    assert_eq!(len, 98);
+                    assert_eq!(
+                        u64::from_be_bytes(req[..8].try_into().unwrap()),
+                        CONNECTION_ID
+                    );
+                    res.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+                    res.extend_from_slice(transaction_id);
+                    res.extend_from_slice(&1800_u32.to_be_bytes());
+                    res.extend_from_slice(&1_u32.to_be_bytes());
+                    res.extend_from_slice(&2_u32.to_be_bytes());
+                    res.extend_from_slice(&[127, 0, 0, 2]);
+                    // echo announced port back as peer port
+                    res.extend_from_slice(&req[96..98]);
+                } else if action == ACTION_SCRAPE {
+                    res.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+                    res.extend_from_slice(transaction_id);
+                    for _ in req[16..].chunks_exact(20) {
+                        for value in [2_u32, 5, 1] {
+                            res.extend_from_slice(&value.to_be_bytes());
+                        }
+                    }
+                }
+                socket.send_to(&res, from).await.unwrap();
+            }
+        });
+        addr
+    }
 
-    resp_buf.drain(0..drain_range);
+    fn announce_req() -> AnnounceRequest {
+        AnnounceRequest {
+            info_hash: [1; 20],
+            peer_id: peer_id_bytes("-ZN0001-test"),
+            downloaded: 0,
+            left: 0,
+            uploaded: 0,
+            event: Event::Started,
+            port: 15441,
+        }
+    }
 
-    // deserialize and return peers
-    Ok(IpPort::from_bytes(&resp_buf))
+    #[tokio::test]
+    async fn test_announce_and_scrape() {
+        let addr = fake_tracker(0, None).await;
+        let client = UdpTrackerClient::new(addr);
+        let res = client.announce(&announce_req()).await.unwrap();
+        assert_eq!(res.interval, 1800);
+        assert_eq!(res.seeders, 2);
+        assert_eq!(res.peers, vec!["127.0.0.2:15441".parse().unwrap()]);
+        let stats = client.scrape(&[[1; 20], [2; 20]]).await.unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].completed, 5);
+    }
+
+    #[tokio::test]
+    async fn test_retransmission_and_error() {
+        let addr = fake_tracker(1, Some("info hash not allowed")).await;
+        let client = UdpTrackerClient::new(addr).with_timeout(Duration::from_millis(100), 2);
+        let err = client.announce(&announce_req()).await.unwrap_err();
+        assert!(err.to_string().contains("info hash not allowed"));
+    }
+
+    #[test]
+    fn test_parse_ipv6_peers() {
+        let mut bytes = Ipv6Addr::LOCALHOST.octets().to_vec();
+        bytes.extend_from_slice(&15441_u16.to_be_bytes());
+        let peers = parse_peers(&bytes, true);
+        assert_eq!(peers, vec!["[::1]:15441".parse().unwrap()]);
+    }
 }
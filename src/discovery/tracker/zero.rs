@@ -0,0 +1,93 @@
+use decentnet_protocol::{address::PeerAddr, templates::Announce};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    core::{error::Error, peer::Peer},
+    environment::ENV,
+    net::socks::ProxyMode,
+};
+
+/// Address types requested from bootstrapper, onion peers only when proxy can reach them.
+/// Our onion isn't announced, as bootstrappers only add onions with onion_signs keyed by
+/// their public key, which [`Announce`] can't carry as list.
+fn need_types(proxy_mode: ProxyMode) -> Vec<String> {
+    let mut types = vec!["ip4".to_string(), "ipv6".to_string()];
+    if proxy_mode != ProxyMode::Disabled {
+        types.push("onion".to_string());
+    }
+    types
+}
+
+/// Sites are announced to zero:// bootstrappers by sha256 hash of their address.
+pub fn announce_hash(site: &str) -> ByteBuf {
+    ByteBuf::from(Sha256::digest(site.as_bytes()).to_vec())
+}
+
+/// Announces `sites` to bootstrapper at `addr` with `announce` command, returns peers
+/// found for each site, in order of `sites`. Zero `port` announces without adding us as peer.
+/// Onion bootstrappers are connected to through proxy.
+pub async fn zero_announce(
+    addr: PeerAddr,
+    sites: &[&str],
+    port: u16,
+) -> Result<Vec<Vec<PeerAddr>>, Error> {
+    let mut peer = Peer::new(addr);
+    peer.connect_handshake().await?;
+    let add = if port > 0 {
+        vec!["ip4".to_string()]
+    } else {
+        vec![]
+    };
+    let request = Announce {
+        hashes: sites.iter().map(|site| announce_hash(site)).collect(),
+        onions: vec![],
+        onion_signs: vec![],
+        onion_sign_this: String::new(),
+        port,
+        need_types: need_types(ENV.proxy_mode),
+        need_num: ENV.site_peers_need,
+        add,
+    };
+    let res = peer
+        .track(async |mut protocol| protocol.announce(request).await)
+        .await?;
+    let peers = res
+        .peers
+        .iter()
+        .map(|peers| {
+            peers
+                .ip4
+                .iter()
+                .chain(&peers.ipv6)
+                .chain(&peers.onion)
+                .filter_map(|packed| PeerAddr::unpack(packed).ok())
+                .collect()
+        })
+        .collect();
+    Ok(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::hex::ToHex;
+
+    use super::{announce_hash, need_types};
+    use crate::net::socks::ProxyMode;
+
+    #[test]
+    fn test_announce_hash() {
+        let hash = announce_hash("1HELLoE3sFD9569CLCbHEAVqvqV7U2Ri9d");
+        assert_eq!(hash.len(), 32);
+        assert_eq!(
+            hash.to_hex(),
+            "8eefb2818cba2cc1a8d7ac407c3155ef4fdc243204a4c69cf84e9c6988351f38"
+        );
+    }
+
+    #[test]
+    fn test_need_types() {
+        assert_eq!(need_types(ProxyMode::Disabled), ["ip4", "ipv6"]);
+        assert_eq!(need_types(ProxyMode::OnionOnly), ["ip4", "ipv6", "onion"]);
+    }
+}
@@ -1,6 +1,8 @@
-use std::{collections::HashMap, env::current_dir, fs, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap, env::current_dir, fs, net::SocketAddr, path::PathBuf, str::FromStr,
+};
 
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use lazy_static::lazy_static;
 use mut_static::MutStatic;
 use rand::Rng;
@@ -9,6 +11,7 @@ use serde_json::json;
 use crate::{
     core::{error::Error, site::models::SiteStorage, user::User},
     io::utils::{load_sites_file, load_trackers, load_users_file},
+    net::{socks::ProxyMode, tor::OnionMode},
     plugins::{
         self,
         core::plugin::Plugin,
@@ -94,7 +97,12 @@ pub struct Environment {
     pub ui_restrict: bool,
     pub ui_host: String,
     pub ui_trans_proxy: bool,
-    // pub broadcast_port: usize,
+    pub broadcast_port: u16,
+    pub proxy: SocketAddr,
+    pub proxy_mode: ProxyMode,
+    pub tor_controller: SocketAddr,
+    pub tor_password: Option<String>,
+    pub tor_onions: OnionMode,
     pub trackers: Vec<String>,
     pub homepage: String,
     pub lang: String,
@@ -103,7 +111,12 @@ pub struct Environment {
     pub access_key: String,
     pub size_limit: usize,
     pub file_size_limit: usize,
+    /// Optional limit in GB, if given instead of limit saved with optionalLimitSet.
+    pub optional_limit: Option<usize>,
     pub site_peers_need: usize,
+    pub connected_limit: usize,
+    pub global_connected_limit: usize,
+    pub tracker: bool,
 }
 
 fn get_matches() -> ArgMatches {
@@ -221,14 +234,17 @@ fn get_matches() -> ArgMatches {
                 .long("file_size_limit")
                 .default_value("10")
                 .help("Maximum per file size limit"),
-            // Arg::new("CONNECTED_LIMIT")
-            //     .long("connected_limit")
-            //     .default_value("8")
-            //     .help("Max connected peer per site"),
-            // Arg::new("GLOBAL_CONNECTED_LIMIT")
-            //     .long("global_connected_limit")
-            //     .default_value("512")
-            //     .help("Max connections"),
+            Arg::new("OPTIONAL_LIMIT")
+                .long("optional_limit")
+                .help("Limit total size of downloaded optional files in GB, overrides saved limit"),
+            Arg::new("CONNECTED_LIMIT")
+                .long("connected_limit")
+                .default_value("8")
+                .help("Max connected peer per site"),
+            Arg::new("GLOBAL_CONNECTED_LIMIT")
+                .long("global_connected_limit")
+                .default_value("512")
+                .help("Max connections"),
             Arg::new("FILESERVER_IP")
                 .long("fileserver_ip")
                 .default_value("*")
@@ -258,10 +274,35 @@ fn get_matches() -> ArgMatches {
             //     .long("tor_hs_port")
             //     .default_value("15441")
             //     .help("Hidden service port in Tor always mode"),
-            // Arg::new("BROADCAST_PORT")
-            //     .long("broadcast_port")
-            //     .default_value("1544")
-            //     .help("Port to broadcast local discovery messages"),
+            Arg::new("BROADCAST_PORT")
+                .long("broadcast_port")
+                .default_value("1544")
+                .help("Port to broadcast local discovery messages, 0 disables local discovery"),
+            Arg::new("PROXY")
+                .long("proxy")
+                .default_value("127.0.0.1:9050")
+                .help("Socks5 proxy address, usually of Tor"),
+            Arg::new("PROXY_MODE")
+                .long("proxy_mode")
+                .value_parser(["always", "onion-only", "disabled"])
+                .default_value("onion-only")
+                .help("Outbound connections going through proxy"),
+            Arg::new("TOR_CONTROLLER")
+                .long("tor_controller")
+                .default_value("127.0.0.1:9051")
+                .help("Tor control port address"),
+            Arg::new("TOR_PASSWORD")
+                .long("tor_password")
+                .help("Tor control port password, if cookie authentication is unavailable"),
+            Arg::new("TOR_ONIONS")
+                .long("tor_onions")
+                .value_parser(["disabled", "global"])
+                .default_value("disabled")
+                .help("Onion service of fileserver, shared by all sites"),
+            Arg::new("TRACKER")
+                .long("tracker")
+                .action(ArgAction::SetTrue)
+                .help("Serve announce requests as bootstrapper, along with sites"),
             Arg::new("USE_BLOCK_STORAGE")
                 .long("use_block_storage")
                 .short('b')
@@ -316,7 +357,22 @@ pub fn get_env(matches: &ArgMatches) -> Result<Environment, Error> {
         .to_owned();
     let ui_trans_proxy = matches.get_one::<bool>("UI_TRANS_PROXY").is_some();
     let ui_restrict = matches.get_one::<bool>("UI_RESTRICT").is_some();
-    // let broadcast_port: usize = matches.value_of("BROADCAST_PORT").unwrap().parse()?;
+    let broadcast_port: u16 = matches
+        .get_one::<String>("BROADCAST_PORT")
+        .unwrap()
+        .parse()?;
+    let proxy = matches
+        .get_one::<String>("PROXY")
+        .unwrap()
+        .parse()
+        .map_err(|_| Error::Err("Invalid proxy address".into()))?;
+    let proxy_mode = matches.get_one::<String>("PROXY_MODE").unwrap().parse()?;
+    let tor_controller = matches
+        .get_one::<String>("TOR_CONTROLLER")
+        .unwrap()
+        .parse()
+        .map_err(|_| Error::Err("Invalid tor controller address".into()))?;
+    let tor_onions = matches.get_one::<String>("TOR_ONIONS").unwrap().parse()?;
 
     #[cfg(debug_assertions)]
     {
@@ -341,6 +397,12 @@ pub fn get_env(matches: &ArgMatches) -> Result<Environment, Error> {
         ui_host,
         ui_trans_proxy,
         ui_restrict,
+        broadcast_port,
+        proxy,
+        proxy_mode,
+        tor_controller,
+        tor_password: matches.get_one::<String>("TOR_PASSWORD").cloned(),
+        tor_onions,
         trackers: (*TRACKERS).iter().map(String::from).collect(),
         homepage: String::from(matches.get_one::<String>("HOMEPAGE").unwrap()),
         lang: String::from(matches.get_one::<String>("LANGUAGE").unwrap()),
@@ -352,10 +414,23 @@ pub fn get_env(matches: &ArgMatches) -> Result<Environment, Error> {
             .get_one::<String>("FILE_SIZE_LIMIT")
             .unwrap()
             .parse()?,
+        optional_limit: matches
+            .get_one::<String>("OPTIONAL_LIMIT")
+            .map(|limit| limit.parse())
+            .transpose()?,
         site_peers_need: matches
             .get_one::<String>("SITE_PEERS_NEED")
             .unwrap()
             .parse()?,
+        connected_limit: matches
+            .get_one::<String>("CONNECTED_LIMIT")
+            .unwrap()
+            .parse()?,
+        global_connected_limit: matches
+            .get_one::<String>("GLOBAL_CONNECTED_LIMIT")
+            .unwrap()
+            .parse()?,
+        tracker: matches.get_flag("TRACKER"),
     };
     Ok(env)
 }
@@ -110,14 +110,180 @@ impl Site {
                 valid_signers.extend(content.signers.clone());
             }
         } else if let Some(rules) = self.get_file_rules(inner_path)
-            && let Some(Value::String(signers)) = rules.get("signers")
+            && let Some(Value::Array(signers)) = rules.get("signers")
         {
-            valid_signers.insert(signers.clone());
+            valid_signers.extend(
+                signers
+                    .iter()
+                    .filter_map(|signer| signer.as_str().map(str::to_string)),
+            );
         }
         valid_signers.insert(self.address().to_string());
         valid_signers
     }
 
+    /// Loads content.json of `inner_path` and of its parent dirs from disk,
+    /// if not already loaded, so that file rules can be resolved.
+    pub async fn load_parent_contents(&mut self, inner_path: &str) -> Result<(), Error> {
+        let mut dirs = inner_path.split('/').collect_vec();
+        dirs.pop();
+        loop {
+            let content_inner_path = if dirs.is_empty() {
+                "content.json".to_string()
+            } else {
+                format!("{}/content.json", dirs.join("/"))
+            };
+            if !self.inner_content_exists(&content_inner_path)
+                && self.site_path().join(&content_inner_path).is_file()
+            {
+                let content = self.load_content_from_path(&content_inner_path).await?;
+                self.modify_content(Some(&content_inner_path), content);
+            }
+            if dirs.pop().is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces content of `inner_path` with `content` if it is signed by a valid signer
+    /// and fits within its file rules, `size` is byte size of new content.json.
+    /// Previously loaded content is kept if verification fails.
+    pub fn update_inner_content(
+        &mut self,
+        inner_path: &str,
+        content: Content,
+        size: usize,
+    ) -> Result<(), Error> {
+        // Root signers have to come from currently trusted content, not from new one
+        let root_signers = self.get_valid_signers("content.json");
+        let old_content = self.content(Some(inner_path)).cloned();
+        self.modify_content(Some(inner_path), content);
+        let res = if inner_path == "content.json" {
+            self.verify_signers(inner_path, root_signers.into_iter().collect())
+        } else {
+            let valid_signers = self.get_valid_signers(inner_path).into_iter().collect();
+            self.verify_signers(inner_path, valid_signers)
+                .and_then(|_| self.verify_rules(inner_path, size))
+        };
+        if res.is_err() {
+            match old_content {
+                Some(old_content) => self.modify_content(Some(inner_path), old_content),
+                None => {
+                    self.remove_content(inner_path);
+                }
+            }
+        }
+        res
+    }
+
+    pub(crate) fn verify_signers(
+        &self,
+        inner_path: &str,
+        valid_signers: HashSet<String>,
+    ) -> Result<(), Error> {
+        let content = self.content(Some(inner_path)).unwrap();
+        let signed = content
+            .signs
+            .keys()
+            .any(|signer| valid_signers.contains(signer) && content.verify(signer));
+        if signed {
+            Ok(())
+        } else {
+            Err(Error::Err(format!(
+                "Invalid signers for {inner_path}, Valid signers : {valid_signers:?}"
+            )))
+        }
+    }
+
+    /// Checks content of `inner_path` against rules of its parent content, `size` is byte size
+    /// of content.json. User contents also need a valid cert and mustn't be banned.
+    pub(crate) fn verify_rules(&self, inner_path: &str, size: usize) -> Result<(), Error> {
+        let content = self.content(Some(inner_path)).unwrap();
+        let Some(rules) = self.get_file_rules(inner_path) else {
+            return Err(Error::Err(format!("No rules found for {inner_path}")));
+        };
+        if let Some(user_address) = rules["user_address"].as_str() {
+            if rules["banned"].as_bool().unwrap_or_default() {
+                return Err(Error::Err(format!(
+                    "{inner_path} : User {user_address} is banned"
+                )));
+            }
+            verify_content_cert(&rules, content)
+                .map_err(|err| Error::Err(format!("{inner_path} : {err}")))?;
+        }
+        let allowed = [
+            ("files_allowed", &content.files),
+            ("files_allowed_optional", &content.files_optional),
+        ];
+        for (rule, files) in allowed {
+            let Some(pattern) = rules[rule].as_str().filter(|pattern| !pattern.is_empty()) else {
+                continue;
+            };
+            let re = Regex::new(&format!("^(?:{pattern})$"))
+                .map_err(|_| Error::Err(format!("Invalid {rule} pattern {pattern}")))?;
+            if let Some(file) = files.keys().find(|file| !re.is_match(file)) {
+                return Err(Error::Err(format!(
+                    "{inner_path} : File not allowed {file}"
+                )));
+            }
+        }
+        if let Some(max_size) = rules["max_size"].as_u64() {
+            let total_size = size + content.files.values().map(|file| file.size).sum::<usize>();
+            if total_size > max_size as usize {
+                return Err(Error::Err(format!(
+                    "{inner_path} too large {total_size} > {max_size}"
+                )));
+            }
+        }
+        if let Some(max_size) = rules["max_size_optional"].as_u64() {
+            let total_size = content
+                .files_optional
+                .values()
+                .map(|file| file.size)
+                .sum::<usize>();
+            if total_size > max_size as usize {
+                return Err(Error::Err(format!(
+                    "{inner_path} optional files too large {total_size} > {max_size}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies cert of user, as added by certAdd, against cert signers accepted by site,
+    /// loading includes of root content to find its user_contents.
+    pub async fn verify_user_cert(
+        &mut self,
+        auth_address: &str,
+        auth_type: &str,
+        cert_user_id: &str,
+        cert_sign: &str,
+    ) -> Result<(), Error> {
+        let includes = match self.content(None) {
+            Some(content) => content.includes.keys().cloned().collect_vec(),
+            None => return Err(Error::Err("No content to verify cert with".into())),
+        };
+        for include in includes {
+            self.load_parent_contents(&include).await?;
+        }
+        let Some(user_contents) = self
+            .user_contents_content()
+            .and_then(|content| content.user_contents.as_ref())
+        else {
+            return Err(Error::Err("Site doesn't accept user certs".into()));
+        };
+        let user_contents = json!(user_contents);
+        verify_cert(
+            &user_contents["cert_signers"],
+            user_contents["cert_signers_pattern"].as_str(),
+            auth_address,
+            auth_type,
+            cert_user_id,
+            cert_sign,
+        )
+    }
+
     /// Get File Rules for Given inner_path
     /// If inner_path doesn't end with "content.json"
     pub fn get_file_rules(&self, inner_path: &str) -> Option<Value> {
@@ -142,13 +308,14 @@ impl Site {
             inner_path_parts.insert(0, dirs.pop().unwrap());
             inner_path_parts.insert(0, dirs.pop().unwrap());
             loop {
-                let content_inner_path = dirs.join("/");
+                let content_inner_path = if dirs.is_empty() {
+                    "content.json".to_string()
+                } else {
+                    format!("{}/content.json", dirs.join("/"))
+                };
                 if let Some(parent_content) = self.content(Some(&content_inner_path)) {
-                    if !parent_content.includes.is_empty() {
-                        let includes = parent_content
-                            .includes
-                            .get(&inner_path_parts.join("/"))
-                            .unwrap();
+                    if let Some(includes) = parent_content.includes.get(&inner_path_parts.join("/"))
+                    {
                         return Some(json!(includes));
                     } else if parent_content.user_contents.is_some() {
                         let content = self.content(Some(inner_path))?;
@@ -156,11 +323,11 @@ impl Site {
                             self.get_user_content_rules(parent_content, inner_path, content);
                         return Some(json!(user_content_rules));
                     }
-                } else if dirs.is_empty() {
+                }
+                if dirs.is_empty() {
                     break;
-                } else {
-                    inner_path_parts.insert(0, dirs.pop().unwrap());
                 }
+                inner_path_parts.insert(0, dirs.pop().unwrap());
             }
         }
         None
@@ -199,22 +366,14 @@ impl Site {
 
         let mut rules = Map::new();
         let r = if user_contents.permissions.contains_key(&user_address) {
-            user_contents
-                .permissions
-                .get(&user_address)
-                .unwrap()
-                .clone() // Default rules based on address
+            user_contents.permissions.get(&user_address).cloned() // Default rules based on address
         } else {
-            user_contents
-                .permissions
-                .get(&cert_user_id)
-                .unwrap()
-                .clone() // Default rules based on username
+            user_contents.permissions.get(&cert_user_id).cloned() // Default rules based on username
         };
         let mut banned = false;
-        if let PermissionRulesType::None(false) = r {
+        if let Some(PermissionRulesType::None(false)) = r {
             banned = true;
-        } else if let PermissionRulesType::Rules(r) = r {
+        } else if let Some(PermissionRulesType::Rules(r)) = r {
             banned = false;
             rules.extend(json!(r).as_object().unwrap().clone());
         }
@@ -226,7 +385,7 @@ impl Site {
             let permission_rules = json!(permission_rules);
             let permission_rules = permission_rules.as_object().unwrap();
             for (key, val) in permission_rules.iter() {
-                match rules.get(key) {
+                match rules.get(key).filter(|value| !value.is_null()) {
                     Some(value) => match (val, value) {
                         (Value::Number(val), Value::Number(value)) => {
                             if val.as_u64().unwrap() > value.as_u64().unwrap() {
@@ -273,6 +432,7 @@ impl Site {
                 .push(json!(user_address.clone())); // Add user as valid signer
         }
         rules.insert("user_address".to_string(), json!(user_address));
+        rules.insert("banned".to_string(), json!(banned));
         rules.insert("includes_allowed".to_string(), json!(false));
 
         rules
@@ -358,6 +518,72 @@ impl Site {
     }
 }
 
+/// Verifies cert of user content against `cert_signers` of its `rules`, if site requires one.
+fn verify_content_cert(rules: &Value, content: &Content) -> Result<(), Error> {
+    let no_signers = rules["cert_signers"]
+        .as_object()
+        .is_none_or(|signers| signers.is_empty());
+    let pattern = rules["cert_signers_pattern"]
+        .as_str()
+        .filter(|pattern| !pattern.is_empty());
+    if no_signers && pattern.is_none() {
+        return Ok(());
+    }
+    let Some(cert) = &content.cert else {
+        return Err(Error::Err("Missing cert_user_id".into()));
+    };
+    // cert_sign is only reachable by its content.json key
+    let cert_json = json!(cert);
+    let cert_sign = cert_json["cert_sign"].as_str().unwrap_or_default();
+    verify_cert(
+        &rules["cert_signers"],
+        pattern,
+        rules["user_address"].as_str().unwrap_or_default(),
+        &cert.auth_type,
+        &cert.user_id,
+        cert_sign,
+    )
+}
+
+/// Verifies `cert_sign` of `cert_user_id`, given as `user_name@domain`, which signs
+/// `auth_address#auth_type/user_name` with a key listed for domain in `cert_signers`,
+/// or with domain address itself if it matches `cert_signers_pattern`.
+pub fn verify_cert(
+    cert_signers: &Value,
+    cert_signers_pattern: Option<&str>,
+    auth_address: &str,
+    auth_type: &str,
+    cert_user_id: &str,
+    cert_sign: &str,
+) -> Result<(), Error> {
+    let Some((user_name, domain)) = cert_user_id.split_once('@') else {
+        return Err(Error::Err(format!("Invalid cert_user_id {cert_user_id}")));
+    };
+    let signers = match cert_signers[domain].as_array() {
+        Some(signers) => signers
+            .iter()
+            .filter_map(|signer| signer.as_str().map(str::to_string))
+            .collect(),
+        None if cert_signers_pattern
+            .filter(|pattern| !pattern.is_empty())
+            .and_then(|pattern| Regex::new(&format!("^(?:{pattern})")).ok())
+            .is_some_and(|re| re.is_match(domain)) =>
+        {
+            vec![domain.to_string()]
+        }
+        None => return Err(Error::Err(format!("Invalid cert signer {domain}"))),
+    };
+    let data = format!("{auth_address}#{auth_type}/{user_name}");
+    let signed = signers
+        .iter()
+        .any(|signer| zeronet_cryptography::verify(data.as_str(), signer, cert_sign).is_ok());
+    if signed {
+        Ok(())
+    } else {
+        Err(Error::Err(format!("Invalid cert_sign of {cert_user_id}")))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::{json, Map, Value};
@@ -415,6 +641,73 @@ mod tests {
         assert_eq!(res["optional"], Value::Null);
     }
 
+    #[tokio::test]
+    async fn test_verify_user_content_rules() {
+        let addr = "15UYrA7aXr2Nto1Gg4yWXpY3EAJwafMTNk";
+        let user_content = "data/users/1AmeB7f5wBfJm6iR7MRZfFh65xkJzaVCX7/content.json";
+        let path = PathBuf::from(format!("tests/data/{addr}"));
+        let mut site = Site::new(addr, path).unwrap();
+        load_site_content(&mut site, "data/users/content.json").await;
+        load_site_content(&mut site, user_content).await;
+        assert!(site.verify_rules(user_content, 1000).is_ok());
+        let err = site.verify_rules(user_content, 1_000_000).unwrap_err();
+        assert!(err.to_string().contains("too large"));
+
+        let content = site.content(Some(user_content)).unwrap().clone();
+        let mut modified = content.clone();
+        modified.files.insert(
+            "index.html".into(),
+            zerucontent::File {
+                sha512: "".into(),
+                size: 0,
+            },
+        );
+        site.modify_content(Some(user_content), modified);
+        let err = site.verify_rules(user_content, 1000).unwrap_err();
+        assert!(err.to_string().contains("File not allowed index.html"));
+
+        // Cert was issued to pramukesh, not to other users
+        for (user_id, reason) in [
+            ("nofish@zeroid.bit", "Invalid cert_sign"),
+            ("pramukesh@unknown.bit", "Invalid cert signer"),
+            ("bad@zeroid.bit", "banned"),
+        ] {
+            let mut modified = content.clone();
+            modified.cert.as_mut().unwrap().user_id = user_id.into();
+            site.modify_content(Some(user_content), modified);
+            let err = site.verify_rules(user_content, 1000).unwrap_err();
+            assert!(err.to_string().contains(reason), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_user_cert() {
+        let addr = "15UYrA7aXr2Nto1Gg4yWXpY3EAJwafMTNk";
+        let auth_address = "1AmeB7f5wBfJm6iR7MRZfFh65xkJzaVCX7";
+        let cert_sign =
+            "G7suwq3UAUQyJtvznnFEp4NxkYwLaRd/7vOVAcrfWPzrJf0Cw08/3xdGkuTiwXcOIsdAlTPLXvGkfrBf3T/FfKc=";
+        let path = PathBuf::from(format!("tests/data/{addr}"));
+        let mut site = Site::new(addr, path).unwrap();
+        load_site_content(&mut site, "content.json").await;
+        let res = site
+            .verify_user_cert(auth_address, "web", "pramukesh@zeroid.bit", cert_sign)
+            .await;
+        assert!(res.is_ok());
+        assert!(site.inner_content_exists("data/users/content.json"));
+
+        let forged = [
+            (auth_address, "web", "nofish@zeroid.bit"),
+            (auth_address, "key", "pramukesh@zeroid.bit"),
+            (addr, "web", "pramukesh@zeroid.bit"),
+        ];
+        for (auth_address, auth_type, cert_user_id) in forged {
+            let res = site
+                .verify_user_cert(auth_address, auth_type, cert_user_id, cert_sign)
+                .await;
+            assert!(res.is_err());
+        }
+    }
+
     async fn load_site_content<'a>(site: &'a mut Site, inner_path: &'a str) {
         let res = site.load_content_from_path(inner_path).await;
         let res = res.ok().unwrap();
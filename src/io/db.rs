@@ -236,11 +236,13 @@ impl DbManager {
 }
 
 impl DbManager {
-    pub async fn load_data(&mut self, site: &str) {
+    /// Loads json files of site into its db, except those in `excluded_dirs` of site.
+    pub async fn load_data(&mut self, site: &str, excluded_dirs: &[PathBuf]) {
         let schema = self.schema[site].clone();
         let version = schema.version;
         let maps = schema.maps;
         let db_path: PathBuf = schema.db_file.into();
+        let db_inner_dir = db_path.parent().map(Path::to_path_buf).unwrap_or_default();
         let data_path = ENV.data_path.join(site);
         let db_path = data_path.join(db_path);
         let db_dir = db_path.parent().unwrap();
@@ -253,6 +255,11 @@ impl DbManager {
         }
         let mut handlers = vec![];
         for path in paths {
+            let inner_path = db_inner_dir.join(&path);
+            if excluded_dirs.iter().any(|dir| inner_path.starts_with(dir)) {
+                debug!("Skipping {path} of rejected user content");
+                continue;
+            }
             for (regex_str, regex) in &regexes {
                 let matched = regex.is_match(&path);
                 if matched {
@@ -0,0 +1,308 @@
+use std::{
+    collections::HashSet,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use futures::{stream, StreamExt};
+use log::*;
+use serde_bytes::ByteBuf;
+
+use decentnet_protocol::{interface::RequestImpl, Either};
+use zerucontent::File as ZFile;
+
+use crate::{
+    core::{error::Error, peer::Peer, site::Site},
+    io::utils::get_zfile_info_from_bytes,
+};
+
+/// Large files are split into chunks of this size, each chunk may come from a different peer.
+pub const CHUNK_SIZE: usize = 512 * 1024;
+/// Consecutive failed requests after which a peer is no longer used for downloads.
+const MAX_PEER_FAILURES: usize = 3;
+/// Chunks of a single file downloaded at the same time.
+const MAX_CHUNK_DOWNLOADS: usize = 8;
+
+/// Peers shared between concurrent downloads, handed out in round robin order.
+/// Stats recorded on peers while downloading are kept, see [`PeerPool::peers`].
+#[derive(Clone, Default)]
+pub struct PeerPool {
+    inner: Arc<Mutex<PeerPoolInner>>,
+}
+
+#[derive(Default)]
+struct PeerPoolInner {
+    peers: Vec<Peer>,
+    next: usize,
+    unreachable: HashSet<String>,
+}
+
+impl PeerPoolInner {
+    fn usable(&self, peer: &Peer) -> bool {
+        peer.errors() < MAX_PEER_FAILURES
+            && !peer.is_bad()
+            && !self.unreachable.contains(&peer.address().to_string())
+    }
+}
+
+impl PeerPool {
+    pub fn new(peers: impl IntoIterator<Item = Peer>) -> Self {
+        let inner = PeerPoolInner {
+            peers: peers.into_iter().collect(),
+            ..Default::default()
+        };
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All peers of pool, including the ones no longer used, with their updated stats.
+    pub fn peers(&self) -> Vec<Peer> {
+        self.inner.lock().unwrap().peers.clone()
+    }
+
+    /// Returns next usable peer whose address is not in `tried`, connecting to it if needed.
+    pub async fn next(&self, tried: &HashSet<String>) -> Option<Peer> {
+        loop {
+            let mut peer = {
+                let mut inner = self.inner.lock().unwrap();
+                let len = inner.peers.len();
+                let idx = (0..len)
+                    .map(|offset| (inner.next + offset) % len)
+                    .find(|idx| {
+                        let peer = &inner.peers[*idx];
+                        inner.usable(peer) && !tried.contains(&peer.address().to_string())
+                    })?;
+                inner.next = idx + 1;
+                inner.peers[idx].clone()
+            };
+            if peer.connection().is_some() {
+                return Some(peer);
+            }
+            match peer.connect_handshake().await {
+                Ok(()) => {
+                    self.update(peer.clone());
+                    return Some(peer);
+                }
+                Err(err) => {
+                    debug!("Can't use {:?} for downloads, Error : {err:?}", peer);
+                    peer.record_error();
+                    let key = peer.address().to_string();
+                    self.update(peer);
+                    self.inner.lock().unwrap().unreachable.insert(key);
+                }
+            }
+        }
+    }
+
+    /// Records that peer at `addr` served file which didn't match its hash.
+    pub fn record_bad_file(&self, addr: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(peer) = inner
+            .peers
+            .iter_mut()
+            .find(|peer| peer.address().to_string() == addr)
+        {
+            peer.record_bad_file();
+        }
+    }
+
+    /// Stores `peer` with stats recorded during its last request.
+    pub fn update(&self, peer: Peer) {
+        let key = peer.address().to_string();
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(existing) = inner
+            .peers
+            .iter_mut()
+            .find(|existing| existing.address().to_string() == key)
+        {
+            *existing = peer;
+        }
+    }
+}
+
+/// Runs `download` against peers from `pool` until one of them succeeds.
+async fn with_peers<T, F, Fut>(inner_path: &str, pool: &PeerPool, download: F) -> Result<T, Error>
+where
+    F: Fn(Peer) -> Fut,
+    Fut: Future<Output = (Peer, Result<T, Error>)>,
+{
+    let mut tried = HashSet::new();
+    while let Some(peer) = pool.next(&tried).await {
+        tried.insert(peer.address().to_string());
+        let (peer, res) = download(peer).await;
+        if let Err(err) = &res {
+            debug!(
+                "Downloading {inner_path} from {:?} failed, Error : {err:?}",
+                peer
+            );
+        }
+        pool.update(peer);
+        if res.is_ok() {
+            return res;
+        }
+    }
+    Err(Error::Err(format!(
+        "No peers found to download file: {inner_path}"
+    )))
+}
+
+impl Site {
+    /// Pool of site peers, best scoring first.
+    pub fn peer_pool(&self) -> PeerPool {
+        PeerPool::new(self.peers_by_score().into_iter().cloned())
+    }
+
+    /// Downloads file from peers of `pool`, retrying failed requests with other peers.
+    /// Files larger than [`CHUNK_SIZE`] are downloaded in chunks from multiple peers in parallel,
+    /// if any chunk fails or they don't add up to the file, it's downloaded again as a whole
+    /// from a single peer. Listed files must have a hash, to verify what peers served.
+    pub async fn download_from_pool(
+        &self,
+        inner_path: &str,
+        file: Option<&ZFile>,
+        pool: &PeerPool,
+    ) -> Result<ByteBuf, Error> {
+        if let Some(file) = file
+            && file.sha512.is_empty()
+        {
+            return Err(Error::Err(format!("No hash to verify {inner_path}")));
+        }
+        match file {
+            Some(file) if file.size > CHUNK_SIZE => {
+                match self.download_chunks(inner_path, file, pool).await? {
+                    Some(bytes) => Ok(bytes),
+                    None => self.download_whole(inner_path, Some(file), pool).await,
+                }
+            }
+            _ => self.download_whole(inner_path, file, pool).await,
+        }
+    }
+
+    /// Downloads file from a single peer at a time, until one of them serves it intact.
+    async fn download_whole(
+        &self,
+        inner_path: &str,
+        file: Option<&ZFile>,
+        pool: &PeerPool,
+    ) -> Result<ByteBuf, Error> {
+        with_peers(inner_path, pool, |mut peer| async move {
+            let started = Instant::now();
+            let res = self
+                .download_file_from_peer(inner_path.into(), file.cloned(), &mut peer)
+                .await;
+            let res = res.and_then(|bytes| {
+                peer.record_download(bytes.len(), started.elapsed());
+                match file {
+                    Some(file) if get_zfile_info_from_bytes(&bytes).sha512 != file.sha512 => {
+                        peer.record_bad_file();
+                        Err(Error::Err(format!("Hash mismatch for {inner_path}")))
+                    }
+                    _ => Ok(bytes),
+                }
+            });
+            (peer, res)
+        })
+        .await
+    }
+
+    /// Downloads chunks of file in parallel, returns None if any of them fails or they don't
+    /// match file hash. Without per chunk hashes a bad chunk can only be blamed on its peer
+    /// if it served all of them.
+    async fn download_chunks(
+        &self,
+        inner_path: &str,
+        file: &ZFile,
+        pool: &PeerPool,
+    ) -> Result<Option<ByteBuf>, Error> {
+        let chunks = stream::iter((0..file.size).step_by(CHUNK_SIZE))
+            .map(|location| async move {
+                let (addr, bytes) = self
+                    .download_chunk(inner_path, file.size, location, pool)
+                    .await?;
+                Ok::<_, Error>((location, addr, bytes))
+            })
+            .buffer_unordered(MAX_CHUNK_DOWNLOADS)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>();
+        let mut chunks = match chunks {
+            Ok(chunks) => chunks,
+            Err(err) => {
+                warn!("Chunk of {inner_path} failed, downloading it again, Error : {err:?}");
+                return Ok(None);
+            }
+        };
+        chunks.sort_by_key(|(location, ..)| *location);
+        let mut bytes = ByteBuf::with_capacity(file.size);
+        let mut suppliers = HashSet::new();
+        for (_, addr, chunk) in chunks {
+            bytes.extend_from_slice(&chunk);
+            suppliers.insert(addr);
+        }
+        if get_zfile_info_from_bytes(&bytes).sha512 == file.sha512 {
+            return Ok(Some(bytes));
+        }
+        warn!("Chunks of {inner_path} don't match its hash, downloading it again");
+        if suppliers.len() == 1
+            && let Some(addr) = suppliers.iter().next()
+        {
+            pool.record_bad_file(addr);
+        }
+        Ok(None)
+    }
+
+    /// Downloads chunk of file at `location`, returns it along with address of peer serving it.
+    async fn download_chunk(
+        &self,
+        inner_path: &str,
+        file_size: usize,
+        location: usize,
+        pool: &PeerPool,
+    ) -> Result<(String, ByteBuf), Error> {
+        let read_bytes = CHUNK_SIZE.min(file_size - location);
+        with_peers(inner_path, pool, |mut peer| async move {
+            let started = Instant::now();
+            let res = peer
+                .track(async |mut protocol| {
+                    let res = protocol
+                        .get_file(
+                            self.address(),
+                            inner_path,
+                            file_size,
+                            location,
+                            Some(read_bytes),
+                        )
+                        .await?;
+                    match res {
+                        Either::Success(msg) if msg.body.len() == read_bytes => Ok(msg.body),
+                        Either::Success(msg) => Err(Error::Err(format!(
+                            "Chunk of {inner_path} at {location} has {} bytes, expected {read_bytes}",
+                            msg.body.len()
+                        ))),
+                        Either::Error(e) if e.error.starts_with("File read error") => {
+                            Err(Error::FileNotFound(inner_path.into()))
+                        }
+                        Either::Error(e) => Err(Error::Err(e.error)),
+                    }
+                })
+                .await;
+            if let Ok(bytes) = &res {
+                peer.record_download(bytes.len(), started.elapsed());
+            }
+            let addr = peer.address().to_string();
+            (peer, res.map(|bytes| (addr, bytes)))
+        })
+        .await
+    }
+}
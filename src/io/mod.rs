@@ -1,5 +1,7 @@
 pub mod content;
 pub mod db;
+pub mod download;
 pub mod site;
 pub mod user;
 pub mod utils;
+pub mod verify;
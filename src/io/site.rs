@@ -1,5 +1,6 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
     path::{Path, PathBuf},
     time::SystemTime,
 };
@@ -13,16 +14,16 @@ use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
-use decentnet_protocol::{interface::RequestImpl, Either};
+use decentnet_protocol::{address::PeerAddr, interface::RequestImpl, Either};
 use zerucontent::{Content, File as ZFile};
 
 use crate::{
-    core::{error::*, io::*, peer::*, site::*},
-    discovery::tracker::IpPort,
+    core::{error::*, hashfield::Hashfield, io::*, peer::*, site::*},
     environment::{ENV, PATH_PROVIDER_PLUGINS},
-    io::utils::check_file_integrity,
-    net::Protocol,
+    io::download::PeerPool,
+    io::utils::{check_file_integrity, get_zfile_info, get_zfile_info_from_bytes},
     plugins::path_provider::*,
+    utils::diff::apply_diff,
 };
 
 impl Site {
@@ -53,7 +54,7 @@ impl Site {
     }
 
     #[async_recursion::async_recursion]
-    async fn download_file_from_peer(
+    pub async fn download_file_from_peer(
         &self,
         inner_path: String,
         file: Option<ZFile>,
@@ -68,14 +69,18 @@ impl Site {
             let mut bytes = ByteBuf::new();
             let mut downloaded = 0;
             while downloaded != file_size {
-                let message = Protocol::new(peer.connection_mut().unwrap())
-                    .get_file(
-                        self.address(),
-                        &inner_path,
-                        file_size,
-                        downloaded,
-                        Some(def_read_bytes),
-                    )
+                let message = peer
+                    .track(async |mut protocol| {
+                        protocol
+                            .get_file(
+                                self.address(),
+                                &inner_path,
+                                file_size,
+                                downloaded,
+                                Some(def_read_bytes),
+                            )
+                            .await
+                    })
                     .await;
                 if let Err(e) = &message {
                     let err =
@@ -98,8 +103,12 @@ impl Site {
             }
             Ok(bytes)
         } else {
-            let message = Protocol::new(peer.connection_mut().unwrap())
-                .get_file(self.address(), &inner_path, file_size, 0, None)
+            let message = peer
+                .track(async |mut protocol| {
+                    protocol
+                        .get_file(self.address(), &inner_path, file_size, 0, None)
+                        .await
+                })
                 .await;
             if let Err(e) = &message {
                 let err = format!("Error Downloading File {inner_path} from Peer, Error : {e:?}");
@@ -134,7 +143,9 @@ impl Site {
     #[async_recursion::async_recursion] //Needed due to consumption fn are marked as async_recursion
     async fn handle_error_response(inner_path: &str, error: &str) -> Result<ByteBuf, Error> {
         match error {
-            "File read error" => Err(Error::FileNotFound(inner_path.into())),
+            error if error.starts_with("File read error") => {
+                Err(Error::FileNotFound(inner_path.into()))
+            }
             error => {
                 let err =
                     format!("Error Downloading File {inner_path} from Peer, Error : {error:?}");
@@ -143,75 +154,147 @@ impl Site {
         }
     }
 
+    /// Downloads file from peers of `pool`, optional files only from a peer holding them.
+    /// Stats of used peers are kept in `pool`, see [`Site::update_peer_stats`].
     pub async fn need_file(
         &self,
         inner_path: String,
         file: Option<ZFile>,
-        _peer: Option<Peer>,
+        pool: &PeerPool,
     ) -> Result<bool, Error> {
-        self.download_file(inner_path, file, _peer).await
+        let holder = match &file {
+            Some(zfile) if self.is_optional_file(&inner_path) => {
+                self.find_optional_file_peer(&zfile.sha512, pool).await
+            }
+            _ => None,
+        };
+        match holder {
+            Some(holder) => {
+                let holder_pool = PeerPool::new([holder]);
+                let res = self.download_file(inner_path, file, &holder_pool).await;
+                for peer in holder_pool.peers() {
+                    pool.update(peer);
+                }
+                res
+            }
+            None => self.download_file(inner_path, file, pool).await,
+        }
+    }
+
+    /// Finds a peer holding optional file, first from known peer hashfields
+    /// then by asking connected peers of `pool` via findHashIds.
+    async fn find_optional_file_peer(&self, sha512: &str, pool: &PeerPool) -> Option<Peer> {
+        let hash_id = Hashfield::hash_id(sha512)?;
+        let peers = pool.peers();
+        if let Some(peer) = peers
+            .iter()
+            .find(|peer| peer.hashfield().has_hash_id(hash_id))
+        {
+            return Some(peer.clone());
+        }
+        let connected = peers
+            .into_iter()
+            .filter(|peer| peer.connection().is_some())
+            .take(5);
+        for mut peer in connected {
+            let res = peer
+                .track(async |mut protocol| {
+                    protocol.find_hash_ids(self.address(), vec![hash_id]).await
+                })
+                .await;
+            pool.update(peer.clone());
+            let mut res = match res {
+                Ok(res) => res,
+                Err(err) => {
+                    debug!("findHashIds failed for {:?}, Error : {err:?}", peer);
+                    continue;
+                }
+            };
+            if res.my.contains(&hash_id) {
+                return Some(peer);
+            }
+            let holders = [
+                res.peers.remove(&hash_id),
+                res.peers_ipv6.remove(&hash_id),
+                res.peers_onion.remove(&hash_id),
+            ];
+            for packed in holders.into_iter().flatten().flatten() {
+                if let Ok(addr) = PeerAddr::unpack(packed.as_slice()) {
+                    let mut holder = Peer::new(addr);
+                    if holder.connect_handshake().await.is_ok() {
+                        return Some(holder);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns parent dir and path where file is stored, which depends on path provider plugins.
+    pub(crate) fn file_storage_path(
+        &self,
+        inner_path: &str,
+        file: Option<&ZFile>,
+    ) -> (PathBuf, PathBuf) {
+        if let Some(file) = file
+            && !PATH_PROVIDER_PLUGINS.read().unwrap().is_empty()
+        {
+            let file_path = get_file_path(&file.sha512).into();
+            let parent: PathBuf = get_storage_path().into();
+            (parent, file_path)
+        } else {
+            let path = self.site_path().join(inner_path);
+            (path.parent().unwrap().into(), path)
+        }
     }
 
     async fn download_file(
         &self,
         inner_path: String,
         file: Option<ZFile>,
-        _peer: Option<Peer>,
+        pool: &PeerPool,
     ) -> Result<bool, Error> {
-        let (parent, path) = if let Some(file) = file.clone() {
-            if !PATH_PROVIDER_PLUGINS.read().unwrap().is_empty() {
-                let file_path = get_file_path(&file.sha512).into();
-                let parent: PathBuf = get_storage_path().into();
-                (parent, file_path)
-            } else {
-                let path = self.site_path().join(&inner_path);
-                (path.parent().unwrap().into(), path)
-            }
-        } else {
-            let path = self.site_path().join(&inner_path);
-            (path.parent().unwrap().into(), path)
-        };
+        let (parent, path) = self.file_storage_path(&inner_path, file.as_ref());
         if !parent.is_dir() {
             fs::create_dir_all(parent).await?;
         }
         if path.is_file() {
-            //TODO! Verify file integrity here.
-            return Ok(true);
-        }
-        //TODO!: Download from multiple peers
-        if let Some(peer) = self.peers.values().next() {
-            let mut peer = peer.clone();
-            let bytes =
-                Self::download_file_from_peer(self, inner_path.clone(), file, &mut peer).await?;
-            let mut file = File::create(path).await?;
-            file.write_all(&bytes).await?;
-            Ok(true)
-        } else {
-            Err(Error::Err(format!(
-                "No peers found to download file: {}",
-                &inner_path
-            )))
+            let outdated = match &file {
+                Some(file) => get_zfile_info(&path)
+                    .await
+                    .is_ok_and(|info| info.sha512 != file.sha512),
+                None => false,
+            };
+            if !outdated {
+                return Ok(true);
+            }
         }
+        let bytes = self
+            .download_from_pool(&inner_path, file.as_ref(), pool)
+            .await?;
+        let mut file = File::create(path).await?;
+        file.write_all(&bytes).await?;
+        Ok(true)
     }
 
-    async fn download_site_files(&self) -> Result<(), Error> {
+    async fn download_site_files(&self, pool: &PeerPool) -> Result<(), Error> {
         let content = self.content(None).unwrap();
         let files = content.files.clone();
         let mut tasks = Vec::new();
         let mut inner_paths = Vec::new();
         for (inner_path, file) in files {
             inner_paths.push(inner_path.clone());
-            let task = self.download_file(inner_path, Some(file), None);
+            let task = self.download_file(inner_path, Some(file), pool);
             tasks.push(task);
         }
         let includes = &content.includes;
         for inner_path in includes.keys() {
             inner_paths.push(inner_path.clone());
-            let task = self.download_file(inner_path.clone(), None, None);
+            let task = self.download_file(inner_path.clone(), None, pool);
             tasks.push(task);
         }
         //TODO!: Other client may not have an up-to-date site files
-        let user_files = self.fetch_changes(1421043090).await?;
+        let user_files = self.fetch_changes(1421043090, pool).await?;
         //TODO!: Check for storage Permission
         let mut user_data_files = Vec::new();
         for (inner_path, _file) in user_files {
@@ -219,7 +302,7 @@ impl Site {
                 continue;
             }
             user_data_files.push(inner_path.clone());
-            let task = self.download_file(inner_path, None, None);
+            let task = self.download_file(inner_path, None, pool);
             tasks.push(task);
         }
         let mut res = join_all(tasks).await;
@@ -249,7 +332,7 @@ impl Site {
                     files.push(self.download_file(
                         parent.join(path).to_str().unwrap().to_owned(),
                         Some(file),
-                        None,
+                        pool,
                     ));
                 }
             }
@@ -276,28 +359,31 @@ impl Site {
         }
     }
 
-    pub async fn verify_files(&self, content_only: bool) -> Result<bool, Error> {
-        if self.content(None).is_none() {
-            Err(Error::Err("No content to verify".into()))
-        } else {
-            if !content_only {
-                let res = self.check_site_integrity().await?;
-                if !res.is_empty() {
-                    return Err(Error::Err(format!("Site Integrity Check Failed: {res:?}")));
-                }
-            }
-            let content = self.content(None).unwrap();
-            //TODO! Verify inner content also
-            let verified = content.verify(self.address());
-            if !verified {
-                Err(Error::Err(format!(
-                    "Content verification failed for {}",
-                    self.address()
-                )))
+    /// Verifies all site contents and files, marking failed ones as bad files.
+    pub async fn verify_files(&mut self, content_only: bool) -> Result<bool, Error> {
+        let report = self.verify_site(content_only).await?;
+        for (inner_path, status) in &report.files {
+            if status.is_valid() {
+                self.remove_bad_file(inner_path);
             } else {
-                Ok(verified)
+                self.add_bad_file(inner_path, format!("{status:?}"));
             }
         }
+        Ok(report.is_valid())
+    }
+
+    /// Marks `inner_path` as bad file, keeping `reason` to be reported by siteBadFiles.
+    pub fn add_bad_file(&mut self, inner_path: &str, reason: String) {
+        warn!("{inner_path} marked as bad file : {reason}");
+        let cache = &mut self.storage.cache;
+        cache.bad_files.entry(inner_path.into()).or_insert(1);
+        cache.bad_file_errors.insert(inner_path.into(), reason);
+    }
+
+    pub fn remove_bad_file(&mut self, inner_path: &str) {
+        let cache = &mut self.storage.cache;
+        cache.bad_files.remove(inner_path);
+        cache.bad_file_errors.remove(inner_path);
     }
 
     pub async fn check_site_integrity(&self) -> Result<Vec<(String, zerucontent::File)>, Error> {
@@ -315,7 +401,7 @@ impl Site {
             let task = check_file_integrity(site_path, inner_path, hash);
             tasks.push(task);
         }
-        //TODO!: Verify includes, user data files
+        // Only files listed in root content.json are checked, see verify_site for includes
         let mut res = join_all(tasks).await;
         let errs = res.extract_if(.., |res| res.is_err()).collect::<Vec<_>>();
         for err in &errs {
@@ -343,14 +429,279 @@ impl Site {
         Ok(res)
     }
 
-    pub async fn fetch_changes(&self, since: usize) -> Result<HashMap<String, usize>, Error> {
-        //TODO!: Download from multiple peers
-        let mut peer = self.peers.values().next().unwrap().clone();
-        let message = Protocol::new(peer.connection_mut().unwrap())
-            .list_modified(self.address(), since)
-            .await?;
-        let changes = message.modified_files;
-        Ok(changes)
+    pub async fn fetch_changes(
+        &self,
+        since: usize,
+        pool: &PeerPool,
+    ) -> Result<HashMap<String, usize>, Error> {
+        let mut tried = HashSet::new();
+        while let Some(mut peer) = pool.next(&tried).await {
+            tried.insert(peer.address().to_string());
+            let res = peer
+                .track(async |mut protocol| protocol.list_modified(self.address(), since).await)
+                .await;
+            pool.update(peer.clone());
+            match res {
+                Ok(message) => return Ok(message.modified_files),
+                Err(err) => debug!("listModified from {:?} failed, Error : {err:?}", peer),
+            }
+        }
+        Err(Error::Err(format!(
+            "No peers responded to listModified for {}",
+            self.address()
+        )))
+    }
+
+    /// Walks root content.json, its includes and user_contents directories,
+    /// returning every content.json modified after `since`.
+    pub async fn list_modified_contents(
+        &self,
+        since: usize,
+    ) -> Result<HashMap<String, usize>, Error> {
+        let modified_files = self
+            .load_all_contents()
+            .await?
+            .into_iter()
+            .filter_map(|(inner_path, content)| {
+                let modified: usize = content.modified.into();
+                (modified > since).then_some((inner_path, modified))
+            })
+            .collect();
+        Ok(modified_files)
+    }
+
+    /// Loads root content.json, its includes and user_contents recursively,
+    /// preferring already loaded content over reading from disk.
+    async fn load_all_contents(&self) -> Result<Vec<(String, Content)>, Error> {
+        let mut contents = vec![];
+        let mut inner_paths = vec!["content.json".to_string()];
+        while let Some(inner_path) = inner_paths.pop() {
+            let content = if let Some(content) = self.content(Some(&inner_path)) {
+                content.clone()
+            } else {
+                match self.load_content_from_path(&inner_path).await {
+                    Ok(content) => content,
+                    Err(err) => {
+                        debug!("Skipping {inner_path} from site contents, Error : {err:?}");
+                        continue;
+                    }
+                }
+            };
+            let content_dir = inner_path.strip_suffix("content.json").unwrap_or_default();
+            for include in content.includes.keys() {
+                inner_paths.push(format!("{content_dir}{include}"));
+            }
+            if content.user_contents.is_some() {
+                // User content directory is only created once first user signs in
+                let entries = match fs::read_dir(self.site_path().join(content_dir)).await {
+                    Ok(entries) => Some(entries),
+                    Err(err) if err.kind() == ErrorKind::NotFound => None,
+                    Err(err) => return Err(err.into()),
+                };
+                if let Some(mut entries) = entries {
+                    while let Some(entry) = entries.next_entry().await? {
+                        if entry.path().join("content.json").is_file() {
+                            let user_dir = entry.file_name();
+                            let user_dir = user_dir.to_string_lossy();
+                            inner_paths.push(format!("{content_dir}{user_dir}/content.json"));
+                        }
+                    }
+                }
+            }
+            contents.push((inner_path, content));
+        }
+        Ok(contents)
+    }
+
+    /// Optional files listed by all site contents, keyed by their inner path.
+    pub async fn optional_files(&self) -> Result<Vec<(String, ZFile)>, Error> {
+        let mut files = vec![];
+        for (inner_path, content) in self.load_all_contents().await? {
+            let content_dir = inner_path.strip_suffix("content.json").unwrap_or_default();
+            for (path, file) in content.files_optional {
+                files.push((format!("{content_dir}{path}"), file));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Optional files of all site contents, which we have on disk.
+    pub async fn downloaded_optional_files(&self) -> Result<Vec<(String, ZFile)>, Error> {
+        let files = self.optional_files().await?;
+        Ok(files
+            .into_iter()
+            .filter(|(inner_path, file)| self.has_file(inner_path, file))
+            .collect())
+    }
+
+    /// Checks whether file is stored on disk, wherever path provider plugins keep it.
+    pub fn has_file(&self, inner_path: &str, file: &ZFile) -> bool {
+        let (_, path) = self.file_storage_path(inner_path, Some(file));
+        path.is_file()
+    }
+
+    /// Rebuilds site hashfield from optional files we have on disk.
+    pub async fn update_hashfield(&mut self) -> Result<Hashfield, Error> {
+        let mut hashfield = Hashfield::new();
+        for (_, file) in self.downloaded_optional_files().await? {
+            hashfield.append_hash(&file.sha512);
+        }
+        self.set_hashfield(&hashfield);
+        Ok(hashfield)
+    }
+
+    /// Deletes downloaded optional file, which no longer is advertised in hashfield.
+    pub fn delete_optional_file(
+        &mut self,
+        inner_path: &str,
+        hash_id: Option<u16>,
+    ) -> Result<(), Error> {
+        let (_, path) = self.file_storage_path(inner_path, self.optional_file(inner_path));
+        if path.is_file() {
+            std::fs::remove_file(path)?;
+        }
+        if let Some(hash_id) = hash_id {
+            let mut hashfield = self.hashfield();
+            hashfield.remove_hash_id(hash_id);
+            self.set_hashfield(&hashfield);
+        }
+        Ok(())
+    }
+
+    /// Asks connected peers for their hashfield, recording which optional files they have.
+    pub async fn fetch_peer_hashfields(&mut self) -> Result<usize, Error> {
+        let site_addr = self.address().to_owned();
+        let tasks = self
+            .peers
+            .values_mut()
+            .filter(|peer| peer.connection().is_some())
+            .map(|peer| {
+                let site_addr = site_addr.clone();
+                async move {
+                    let res = peer
+                        .track(async |mut protocol| protocol.get_hashfield(&site_addr).await)
+                        .await;
+                    match res {
+                        Ok(res) => {
+                            peer.set_hashfield(Hashfield::from_raw(&res.hashfield_raw));
+                            true
+                        }
+                        Err(err) => {
+                            debug!("Failed to get hashfield from {:?}, Error : {err:?}", peer);
+                            false
+                        }
+                    }
+                }
+            });
+        let updated = join_all(tasks).await.into_iter().filter(|ok| *ok).count();
+        Ok(updated)
+    }
+
+    /// Downloads files of `inner_path` content which are new or changed compared to `old_content`,
+    /// changed files with a diff in `diffs` are patched locally when possible.
+    pub async fn download_changed_files(
+        &self,
+        inner_path: &str,
+        old_content: Option<&Content>,
+        diffs: &HashMap<String, Vec<Value>>,
+        pool: &PeerPool,
+    ) {
+        let Some(content) = self.content(Some(inner_path)) else {
+            return;
+        };
+        let content_dir = inner_path.strip_suffix("content.json").unwrap_or_default();
+        let tasks = content.files.iter().filter_map(|(path, file)| {
+            let old_file = old_content.and_then(|old_content| old_content.files.get(path));
+            if old_file.is_some_and(|old_file| old_file.sha512 == file.sha512) {
+                return None;
+            }
+            let inner_path = format!("{content_dir}{path}");
+            let diff = old_file.zip(diffs.get(path));
+            Some(async move {
+                if let Some((old_file, diff)) = diff {
+                    match self.patch_file(&inner_path, old_file, file, diff).await {
+                        Ok(()) => return Ok(true),
+                        Err(err) => debug!("Patching {inner_path} failed, Error : {err:?}"),
+                    }
+                }
+                self.need_file(inner_path, Some(file.clone()), pool).await
+            })
+        });
+        let mut res = join_all(tasks).await;
+        let errs = res.extract_if(.., |res| res.is_err()).collect::<Vec<_>>();
+        for err in errs {
+            error!("Downloading Changed Files Error: {err:?}");
+        }
+    }
+
+    /// Rebuilds file from its old local copy and `diff`, the result is only written
+    /// if it matches sha512 of the new `file`.
+    async fn patch_file(
+        &self,
+        inner_path: &str,
+        old_file: &ZFile,
+        file: &ZFile,
+        diff: &[Value],
+    ) -> Result<(), Error> {
+        let (_, old_path) = self.file_storage_path(inner_path, Some(old_file));
+        let old = fs::read(&old_path).await?;
+        let new = apply_diff(&old, diff)?;
+        let info = get_zfile_info_from_bytes(&new);
+        if info.sha512 != file.sha512 {
+            return Err(Error::Err(format!(
+                "Patched file hash mismatch for {inner_path}"
+            )));
+        }
+        let (parent, path) = self.file_storage_path(inner_path, Some(file));
+        if !parent.is_dir() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(path, new).await?;
+        Ok(())
+    }
+
+    /// Sends update of `inner_path` to at most `limit` peers of `pool`, skipping `exclude` peer,
+    /// returns number of peers which accepted the update.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn publish(
+        &self,
+        inner_path: &str,
+        body: ByteBuf,
+        diffs: HashMap<String, Vec<Value>>,
+        modified: usize,
+        limit: usize,
+        exclude: Option<&str>,
+        pool: &PeerPool,
+    ) -> usize {
+        let mut published = 0;
+        let mut tried = exclude
+            .map(String::from)
+            .into_iter()
+            .collect::<HashSet<_>>();
+        while published < limit
+            && let Some(mut peer) = pool.next(&tried).await
+        {
+            tried.insert(peer.address().to_string());
+            let res = peer
+                .track(async |mut protocol| {
+                    protocol
+                        .update(
+                            self.address(),
+                            inner_path,
+                            body.clone(),
+                            diffs.clone(),
+                            modified,
+                        )
+                        .await
+                })
+                .await;
+            match res {
+                Ok(_) => published += 1,
+                Err(err) => debug!("Publish to {:?} failed, Error : {err:?}", peer),
+            }
+            pool.update(peer);
+        }
+        published
     }
 
     pub async fn get_peers(&self) -> Result<Vec<Peer>, Error> {
@@ -371,57 +722,55 @@ impl Site {
         self.peers.insert(peer.address().to_string(), peer);
     }
 
-    pub async fn fetch_peers(&mut self) -> Result<Vec<String>, Error> {
-        let site_addr = self.address().to_owned();
-        let mut connections = self
-            .peers
-            .values_mut()
-            .take(5) //TODO!: Dynamic limit based on
-            .map(|peer| {
-                let connection = peer.connection_mut().unwrap();
-                Protocol::new(connection)
-            })
-            .collect::<Vec<Protocol>>();
-        let tasks = connections
-            .iter_mut()
-            .map(|protocol| protocol.pex(&site_addr));
+    /// Peers sorted by score, best first.
+    pub fn peers_by_score(&self) -> Vec<&Peer> {
+        let mut peers = self.peers.values().collect::<Vec<_>>();
+        peers.sort_by_key(|peer| std::cmp::Reverse(peer.score()));
+        peers
+    }
 
-        let res = join_all(tasks)
-            .await
-            .iter()
-            .filter_map(|r| {
-                if let Ok(r) = r {
-                    Some(r.peers.clone())
-                } else {
-                    None
-                }
-            })
-            .flatten()
-            .map(|bytes| {
-                let pair = IpPort::from_bytes(bytes.as_ref());
-                pair.first().unwrap().to_string()
-            })
-            .collect::<Vec<_>>();
-        Ok(res)
+    /// Replaces known peers with their copies carrying newer stats, unknown peers are ignored.
+    pub fn update_peer_stats(&mut self, peers: impl IntoIterator<Item = Peer>) {
+        for peer in peers {
+            if let Some(existing) = self.peers.get_mut(&peer.address().to_string()) {
+                *existing = peer;
+            }
+        }
+    }
+
+    /// Removes peers which keep failing or serving bad files, returns number of evicted peers.
+    pub fn evict_bad_peers(&mut self) -> usize {
+        let count = self.peers.len();
+        let site_addr = self.address().to_string();
+        self.peers.retain(|key, peer| {
+            if peer.is_bad() {
+                debug!("Evicting peer {key} of site {site_addr}");
+            }
+            !peer.is_bad()
+        });
+        count - self.peers.len()
     }
 
     pub async fn update(&mut self, inner_path: &str, diff: Option<HashMap<String, Vec<Value>>>) {
         let addr = self.address().to_string();
         let path = self.site_path().join(inner_path);
         let modified = self.content(None).unwrap().modified.clone();
-        let peer = self.peers.values_mut().next().unwrap();
         let content = fs::read(path).await.unwrap();
-        let res = Protocol::new(peer.connection_mut().unwrap())
-            .update(
-                &addr,
+        let pool = self.peer_pool();
+        let published = self
+            .publish(
                 inner_path,
                 ByteBuf::from(content),
                 diff.unwrap_or_default(),
                 modified.into(),
+                1,
+                None,
+                &pool,
             )
             .await;
-        if let Err(err) = res {
-            error!("{err:?}");
+        self.update_peer_stats(pool.peers());
+        if published == 0 {
+            error!("Failed to publish {inner_path} update of {addr} to any peer");
         }
     }
 }
@@ -475,17 +824,22 @@ impl SiteIO for Site {
         if !&self.site_path().is_dir() {
             fs::create_dir_all(self.site_path()).await?;
         }
+        let pool = self.peer_pool();
         let content_exists = self.content_path().is_file();
         if !content_exists {
-            Self::download_file(self, "content.json".into(), None, None).await?;
+            Self::download_file(self, "content.json".into(), None, &pool).await?;
         }
         let verified = self.load_content().await?;
         if verified {
-            let _ = self.download_site_files().await;
-            self.verify_files(false).await?;
+            let _ = self.download_site_files(&pool).await;
         } else {
             error!("Site content verification failed");
         }
+        self.update_peer_stats(pool.peers());
+        self.evict_bad_peers();
+        if verified {
+            self.verify_files(false).await?;
+        }
         Ok(verified)
     }
 
@@ -522,3 +876,29 @@ impl SiteIO for Site {
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Site;
+
+    #[tokio::test]
+    async fn test_list_modified_contents() {
+        let addr = "15UYrA7aXr2Nto1Gg4yWXpY3EAJwafMTNk";
+        let path = PathBuf::from(format!("tests/data/{addr}"));
+        let site = Site::new(addr, path).unwrap();
+        let res = site.list_modified_contents(0).await.unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res["content.json"], 1700868296);
+        assert_eq!(res["data/users/content.json"], 1700868291);
+
+        let res = site.list_modified_contents(1700868292).await.unwrap();
+        assert_eq!(res.len(), 2);
+        assert!(!res.contains_key("data/users/content.json"));
+        assert_eq!(
+            res["data/users/1AmeB7f5wBfJm6iR7MRZfFh65xkJzaVCX7/content.json"],
+            1701560574
+        );
+    }
+}
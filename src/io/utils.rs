@@ -44,10 +44,14 @@ pub async fn get_zfile_info(path: impl AsRef<Path>) -> Result<ZFile, Error> {
     }
     let mut buf = Vec::new();
     file.unwrap().read_to_end(&mut buf).await?;
+    Ok(get_zfile_info_from_bytes(&buf))
+}
+
+pub fn get_zfile_info_from_bytes(buf: &[u8]) -> ZFile {
     let size = buf.len();
     let digest = Sha512::digest(buf);
     let sha512 = format!("{digest:x}")[..64].to_string();
-    Ok(ZFile { size, sha512 })
+    ZFile { size, sha512 }
 }
 
 pub async fn check_file_integrity<'a>(
@@ -243,9 +247,15 @@ pub fn load_sites_file() -> HashMap<String, SiteStorage> {
                                 storage.stats.size_optional =
                                     value.as_u64().unwrap_or_default() as usize
                             }
-                            "autodownloadoptional"
-                            | "optional_downloaded"
-                            | "optional_help"
+                            "autodownloadoptional" => {
+                                storage.settings.autodownloadoptional =
+                                    value.as_bool().unwrap_or_default()
+                            }
+                            "optional_help" => {
+                                storage.settings.optional_help =
+                                    serde_json::from_value(value).unwrap_or_default()
+                            }
+                            "optional_downloaded"
                             | "has_bigfile"
                             | "autodownload_bigfile_size_limit" => {
                                 storage.plugin_storage.data.insert(key.to_string(), value);
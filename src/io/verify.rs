@@ -0,0 +1,193 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::ErrorKind,
+};
+
+use futures::future::join_all;
+use tokio::fs;
+use zerucontent::{Content, File as ZFile};
+
+use crate::{
+    core::{error::Error, io::*, site::Site},
+    io::utils::get_zfile_info,
+};
+
+/// Verification result of a single site file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileStatus {
+    Valid,
+    Missing,
+    /// File on disk doesn't match hash listed in its content.json.
+    HashMismatch,
+    /// content.json isn't signed by any of its valid signers.
+    InvalidSignature,
+    /// content.json breaks rules of its parent content, such as sizes, files or cert.
+    Rejected(String),
+    /// content.json couldn't be read or parsed.
+    InvalidContent(String),
+}
+
+impl FileStatus {
+    pub fn is_valid(&self) -> bool {
+        *self == FileStatus::Valid
+    }
+}
+
+/// Per file result of [`Site::verify_site`], keyed by inner path.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub files: BTreeMap<String, FileStatus>,
+}
+
+impl VerifyReport {
+    pub fn is_valid(&self) -> bool {
+        self.files.values().all(FileStatus::is_valid)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = (&String, &FileStatus)> {
+        self.files.iter().filter(|(_, status)| !status.is_valid())
+    }
+}
+
+impl Site {
+    async fn verify_file(&self, inner_path: &str, file: &ZFile) -> FileStatus {
+        let (_, path) = self.file_storage_path(inner_path, Some(file));
+        if !path.is_file() {
+            return FileStatus::Missing;
+        }
+        match get_zfile_info(path).await {
+            Ok(info) if info.sha512 == file.sha512 => FileStatus::Valid,
+            Ok(_) => FileStatus::HashMismatch,
+            Err(err) => FileStatus::InvalidContent(err.to_string()),
+        }
+    }
+
+    /// Loads content of `inner_path` into site if not loaded yet, so rules of its children resolve.
+    async fn load_inner_content(&mut self, inner_path: &str) -> Result<Content, FileStatus> {
+        if let Some(content) = self.content(Some(inner_path)) {
+            return Ok(content.clone());
+        }
+        if !self.site_path().join(inner_path).is_file() {
+            return Err(FileStatus::Missing);
+        }
+        let content = self
+            .load_content_from_path(inner_path)
+            .await
+            .map_err(|err| FileStatus::InvalidContent(err.to_string()))?;
+        self.modify_content(Some(inner_path), content.clone());
+        Ok(content)
+    }
+
+    /// content.json files included by, or held as user content of, content at `inner_path`.
+    async fn child_contents(&self, inner_path: &str, content: &Content) -> Vec<String> {
+        let content_dir = inner_path.strip_suffix("content.json").unwrap_or_default();
+        let mut children = content
+            .includes
+            .keys()
+            .map(|include| format!("{content_dir}{include}"))
+            .collect::<Vec<_>>();
+        if content.user_contents.is_some()
+            && let Ok(mut entries) = fs::read_dir(self.site_path().join(content_dir)).await
+        {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if entry.path().join("content.json").is_file() {
+                    let user_dir = entry.file_name();
+                    let user_dir = user_dir.to_string_lossy();
+                    children.push(format!("{content_dir}{user_dir}/content.json"));
+                }
+            }
+        }
+        children
+    }
+
+    /// Verifies root content.json and every content.json it includes or holds as user content,
+    /// checking signature of each against its valid signers, rules of its parent and,
+    /// unless `content_only`, hashes of files it lists. Optional files are only checked
+    /// if downloaded.
+    pub async fn verify_site(&mut self, content_only: bool) -> Result<VerifyReport, Error> {
+        if self.content(None).is_none() {
+            return Err(Error::Err("No content to verify".into()));
+        }
+        let mut report = VerifyReport::default();
+        let mut visited = HashSet::new();
+        let mut inner_paths = vec!["content.json".to_string()];
+        while let Some(inner_path) = inner_paths.pop() {
+            if !visited.insert(inner_path.clone()) {
+                continue;
+            }
+            let content = match self.load_inner_content(&inner_path).await {
+                Ok(content) => content,
+                Err(status) => {
+                    report.files.insert(inner_path, status);
+                    continue;
+                }
+            };
+            let valid_signers = self.get_valid_signers(&inner_path).into_iter().collect();
+            let status = match self.verify_signers(&inner_path, valid_signers) {
+                Err(_) => FileStatus::InvalidSignature,
+                Ok(()) if inner_path == "content.json" => FileStatus::Valid,
+                Ok(()) => match fs::metadata(self.site_path().join(&inner_path)).await {
+                    Ok(metadata) => match self.verify_rules(&inner_path, metadata.len() as usize) {
+                        Ok(()) => FileStatus::Valid,
+                        Err(err) => FileStatus::Rejected(err.to_string()),
+                    },
+                    Err(err) if err.kind() == ErrorKind::NotFound => FileStatus::Missing,
+                    Err(err) => FileStatus::InvalidContent(err.to_string()),
+                },
+            };
+            report.files.insert(inner_path.clone(), status);
+            if !content_only {
+                let content_dir = inner_path.strip_suffix("content.json").unwrap_or_default();
+                let optional = content.files_optional.iter().filter(|(path, file)| {
+                    let path = format!("{content_dir}{path}");
+                    self.has_file(&path, file)
+                });
+                let tasks = content.files.iter().chain(optional).map(|(path, file)| {
+                    let path = format!("{content_dir}{path}");
+                    async move {
+                        let status = self.verify_file(&path, file).await;
+                        (path, status)
+                    }
+                });
+                report.files.extend(join_all(tasks).await);
+            }
+            inner_paths.extend(self.child_contents(&inner_path, &content).await);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const SITE: &str = "15UYrA7aXr2Nto1Gg4yWXpY3EAJwafMTNk";
+    const USER_CONTENT: &str = "data/users/1AmeB7f5wBfJm6iR7MRZfFh65xkJzaVCX7/content.json";
+
+    #[tokio::test]
+    async fn test_verify_site() {
+        let mut site = Site::new(SITE, PathBuf::from(format!("tests/data/{SITE}"))).unwrap();
+        let content = site.load_content_from_path("content.json").await.unwrap();
+        site.modify_content(None, content);
+
+        let report = site.verify_site(true).await.unwrap();
+        assert_eq!(report.files["content.json"], FileStatus::Valid);
+        let verified = report.files.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(
+            verified,
+            vec!["content.json", "data/users/content.json", USER_CONTENT]
+        );
+        assert!(site.inner_content_exists(USER_CONTENT));
+
+        // Listed files aren't part of fixture
+        let report = site.verify_site(false).await.unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.files["data/users/1AmeB7f5wBfJm6iR7MRZfFh65xkJzaVCX7/data.json"],
+            FileStatus::Missing
+        );
+        assert_eq!(report.files["LICENSE"], FileStatus::Missing);
+    }
+}
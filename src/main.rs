@@ -218,19 +218,12 @@ async fn main() -> Result<(), Error> {
                 }
             }
         }
-    } else if false {
-        let conn = DbManager::connect_db_from_path(&ENV.data_path.join("content.db"))?;
-        db_manager.insert_connection("content_db", conn);
-        let mut controller = SitesController::new(db_manager);
-        controller
-            .extend_sites_from_sitedata(site_storage.clone())
-            .await;
-        let mut con = ConnectionController::new(controller).await?;
-        let _ = con.run().await;
     } else {
         info!("Loaded : {} Plugins.", plugins.len());
+        let listener = ConnectionController::bind()?;
         let user_controller = users::run().unwrap();
         let sites_controller = sites::run().await.unwrap();
+        ConnectionController::spawn(listener, sites_controller.clone())?;
         use plugins::site_server::server;
         let _ = server::run(sites_controller, user_controller).await;
     }
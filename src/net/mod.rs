@@ -1,5 +1,8 @@
 pub mod request;
 pub mod response;
+pub mod socks;
+pub mod tls;
+pub mod tor;
 
 use zeronet_protocol::ZeroConnection;
 
@@ -13,7 +16,7 @@ impl<'a> Protocol<'a> {
 
 use decentnet_protocol::templates::Handshake;
 
-use crate::{environment::ENV, io::utils::current_unix_epoch};
+use crate::{discovery::fileserver_port, environment::ENV, io::utils::current_unix_epoch};
 
 pub fn handshake<'a>() -> (&'a str, Handshake) {
     (
@@ -25,12 +28,17 @@ pub fn handshake<'a>() -> (&'a str, Handshake) {
             protocol: "v2".into(),
             use_bin_type: true,
             time: current_unix_epoch(),
-            fileserver_port: 26117,
+            fileserver_port: fileserver_port().unwrap_or(0),
             crypt: None,
-            crypt_supported: vec![],
-            onion: None,
-            port_opened: Some(true),
+            crypt_supported: tls::crypt_supported(),
+            onion: tor::global_onion(),
+            port_opened: Some(fileserver_port().is_some()),
             target_address: None,
         },
     )
 }
+
+/// Handshake sent by this node, received when connected to ourselves.
+pub fn is_own_handshake(handshake: &Handshake) -> bool {
+    handshake.peer_id == ENV.peer_id
+}
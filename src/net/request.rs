@@ -116,6 +116,95 @@ impl<'a> RequestImpl for Protocol<'a> {
     }
 }
 
+impl<'a> Protocol<'a> {
+    ///#getHashfield
+    pub async fn get_hashfield(&mut self, site: &str) -> Result<GetHashfieldResponse, Error> {
+        let res = self
+            .0
+            .request(
+                "getHashfield",
+                RequestType::GetHashfield(GetHashfield { site: site.into() }),
+            )
+            .await?;
+
+        Ok(res.body()?)
+    }
+
+    ///#setHashfield
+    pub async fn set_hashfield(
+        &mut self,
+        site: &str,
+        hashfield_raw: ByteBuf,
+    ) -> Result<OkResponse, Error> {
+        let res = self
+            .0
+            .request(
+                "setHashfield",
+                RequestType::SetHashfield(SetHashfield {
+                    site: site.into(),
+                    hashfield_raw,
+                }),
+            )
+            .await?;
+
+        Ok(res.body()?)
+    }
+
+    ///#findHashIds
+    pub async fn find_hash_ids(
+        &mut self,
+        site: &str,
+        hash_ids: Vec<u16>,
+    ) -> Result<FindHashIdsResponse, Error> {
+        let res = self
+            .0
+            .request(
+                "findHashIds",
+                RequestType::FindHashIds(FindHashIds {
+                    site: site.into(),
+                    hash_ids,
+                }),
+            )
+            .await?;
+
+        Ok(res.body()?)
+    }
+
+    ///#pex, sending our known peers along with request
+    pub async fn pex_with_peers(
+        &mut self,
+        site: &str,
+        (peers, peers_ipv6, peers_onion): (Vec<ByteBuf>, Vec<ByteBuf>, Vec<ByteBuf>),
+        need: usize,
+    ) -> Result<PexResponse, Error> {
+        let res = self
+            .0
+            .request(
+                "pex",
+                RequestType::Pex(Pex {
+                    site: site.into(),
+                    peers,
+                    peers_ipv6: Some(peers_ipv6),
+                    peers_onion: Some(peers_onion),
+                    need,
+                }),
+            )
+            .await?;
+
+        Ok(res.body()?)
+    }
+
+    ///#announce
+    pub async fn announce(&mut self, announce: Announce) -> Result<AnnounceResponse, Error> {
+        let res = self
+            .0
+            .request("announce", RequestType::Announce(announce))
+            .await?;
+
+        Ok(res.body()?)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use decentnet_protocol::{address::PeerAddr, interface::RequestImpl};
@@ -92,3 +92,17 @@ impl<'a> ResponseImpl for Protocol<'a> {
         Ok(true)
     }
 }
+
+impl<'a> Protocol<'a> {
+    ///#handshake, with crypt selected for requesting peer
+    pub async fn handshake_with_crypt(
+        &mut self,
+        id: usize,
+        crypt: Option<String>,
+    ) -> Result<bool, Error> {
+        let mut builder = handshake().1;
+        builder.crypt = crypt;
+        self.0.respond(id, ResponseType::Handshake(builder)).await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,249 @@
+// socks5 client for outbound connections, see https://www.rfc-editor.org/rfc/rfc1928
+
+use std::{
+    io::{self, Error, ErrorKind, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{core::error, environment::ENV};
+
+// connecting to proxy, and proxy connecting to target, must finish within this time
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Which outbound connections go through proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyMode {
+    Always,
+    /// Only .onion hosts, which can't be reached directly.
+    OnionOnly,
+    Disabled,
+}
+
+impl FromStr for ProxyMode {
+    type Err = error::Error;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "always" => Ok(ProxyMode::Always),
+            "onion-only" => Ok(ProxyMode::OnionOnly),
+            "disabled" => Ok(ProxyMode::Disabled),
+            _ => Err(error::Error::Err(format!("Invalid proxy mode {mode}"))),
+        }
+    }
+}
+
+impl ProxyMode {
+    pub fn proxies(&self, host: &str) -> bool {
+        match self {
+            ProxyMode::Always => true,
+            ProxyMode::OnionOnly => host.ends_with(".onion"),
+            ProxyMode::Disabled => false,
+        }
+    }
+}
+
+/// Splits `host:port` address, removing brackets of ipv6 hosts.
+pub fn split_host_port(addr: &str) -> Option<(&str, u16)> {
+    let (host, port) = addr.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Some((host, port.parse().ok()?))
+}
+
+fn reply_error(code: u8) -> Error {
+    let reason = match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    };
+    Error::other(format!("Proxy error: {reason}"))
+}
+
+/// Asks proxy over `stream` to connect to `host:port`, hostnames are resolved by proxy.
+pub fn handshake(stream: &mut (impl Read + Write), host: &str, port: u16) -> io::Result<()> {
+    stream.write_all(&[VERSION, 1, METHOD_NO_AUTH])?;
+    let mut res = [0_u8; 2];
+    stream.read_exact(&mut res)?;
+    if res != [VERSION, METHOD_NO_AUTH] {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Proxy requires authentication",
+        ));
+    }
+    let mut req = vec![VERSION, CMD_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.push(ATYP_IPV4);
+            req.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            req.push(ATYP_IPV6);
+            req.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "Host name too long"))?;
+            req.push(ATYP_DOMAIN);
+            req.push(len);
+            req.extend_from_slice(host.as_bytes());
+        }
+    }
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req)?;
+    let mut res = [0_u8; 4];
+    stream.read_exact(&mut res)?;
+    if res[0] != VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, "Invalid proxy response"));
+    }
+    if res[1] != 0 {
+        return Err(reply_error(res[1]));
+    }
+    // Skip address proxy bound to
+    let len = match res[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0_u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid proxy response")),
+    };
+    let mut bound = vec![0_u8; len + 2];
+    stream.read_exact(&mut bound)?;
+    Ok(())
+}
+
+/// Connects to `host:port` through socks5 proxy at `proxy`.
+pub fn socks5_connect(proxy: SocketAddr, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect_timeout(&proxy, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    handshake(&mut stream, host, port)?;
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    Ok(stream)
+}
+
+/// Async [`socks5_connect`], for use with tokio.
+pub async fn socks5_connect_async(
+    proxy: SocketAddr,
+    host: &str,
+    port: u16,
+) -> io::Result<tokio::net::TcpStream> {
+    let host = host.to_string();
+    let stream = tokio::task::spawn_blocking(move || socks5_connect(proxy, &host, port))
+        .await
+        .map_err(Error::other)??;
+    stream.set_nonblocking(true)?;
+    tokio::net::TcpStream::from_std(stream)
+}
+
+/// Whether connections to `host` go through configured proxy.
+pub fn use_proxy(host: &str) -> bool {
+    ENV.proxy_mode.proxies(host)
+}
+
+/// Connects to `host:port`, through configured proxy if [`use_proxy`].
+pub async fn connect(host: &str, port: u16) -> io::Result<tokio::net::TcpStream> {
+    if use_proxy(host) {
+        socks5_connect_async(ENV.proxy, host, port).await
+    } else if host.ends_with(".onion") {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Onion hosts can only be reached through proxy",
+        ))
+    } else {
+        tokio::net::TcpStream::connect((host, port)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// Minimal socks5 proxy accepting one connection, replying with `reply` code
+    /// and echoing data on success. Returns requested target.
+    fn fake_proxy(reply: u8) -> (SocketAddr, thread::JoinHandle<(u8, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0_u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[VERSION, METHOD_NO_AUTH]).unwrap();
+            let mut req = [0_u8; 4];
+            stream.read_exact(&mut req).unwrap();
+            let len = match req[3] {
+                ATYP_IPV4 => 4,
+                ATYP_IPV6 => 16,
+                _ => {
+                    let mut len = [0_u8; 1];
+                    stream.read_exact(&mut len).unwrap();
+                    len[0] as usize
+                }
+            };
+            let mut target = vec![0_u8; len + 2];
+            stream.read_exact(&mut target).unwrap();
+            stream
+                .write_all(&[VERSION, reply, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+            if reply == 0 {
+                let mut buf = [0_u8; 4];
+                stream.read_exact(&mut buf).unwrap();
+                stream.write_all(&buf).unwrap();
+            }
+            (req[3], target)
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_socks5_connect() {
+        let (proxy, handle) = fake_proxy(0);
+        let host = "exampleexampleexampleexampleexampleexampleexampleexample.onion";
+        let mut stream = socks5_connect(proxy, host, 15441).unwrap();
+        stream.write_all(b"ping").unwrap();
+        let mut buf = [0_u8; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        let (atyp, target) = handle.join().unwrap();
+        assert_eq!(atyp, ATYP_DOMAIN);
+        assert_eq!(&target[..host.len()], host.as_bytes());
+        assert_eq!(&target[host.len()..], &15441_u16.to_be_bytes());
+
+        let (proxy, handle) = fake_proxy(5);
+        let err = socks5_connect(proxy, "127.0.0.1", 15441).unwrap_err();
+        assert_eq!(err.to_string(), "Proxy error: connection refused");
+        let (atyp, target) = handle.join().unwrap();
+        assert_eq!(atyp, ATYP_IPV4);
+        assert_eq!(target, vec![127, 0, 0, 1, 0x3c, 0x51]);
+    }
+
+    #[test]
+    fn test_proxy_mode() {
+        let mode: ProxyMode = "onion-only".parse().unwrap();
+        assert!(mode.proxies("example.onion"));
+        assert!(!mode.proxies("127.0.0.1"));
+        assert!("always".parse::<ProxyMode>().unwrap().proxies("127.0.0.1"));
+        assert!(!ProxyMode::Disabled.proxies("example.onion"));
+        assert!("tor".parse::<ProxyMode>().is_err());
+        assert_eq!(split_host_port("[::1]:15441"), Some(("::1", 15441)));
+    }
+}
@@ -0,0 +1,267 @@
+// tls-rsa encryption of peer connections, compatible with CryptConnection of ZeroNet
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Error, ErrorKind, Read, Write},
+    net::{IpAddr, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use lazy_static::lazy_static;
+use log::*;
+use rcgen::{Certificate as CertificateBuilder, CertificateParams, DnType, KeyPair};
+use rsa::{
+    pkcs8::{EncodePrivateKey, LineEnding},
+    RsaPrivateKey,
+};
+use tokio_rustls::{
+    rustls::{
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, ClientConnection, PrivateKey, ServerConfig, ServerConnection,
+        ServerName, StreamOwned,
+    },
+    TlsAcceptor, TlsConnector,
+};
+use zeronet_protocol::ZeroConnection;
+
+use crate::{core::error, environment::ENV};
+
+pub const CRYPT_TLS_RSA: &str = "tls-rsa";
+const CERT_FILE: &str = "cert-rsa.pem";
+const KEY_FILE: &str = "key-rsa.pem";
+const KEY_BITS: usize = 2048;
+
+lazy_static! {
+    static ref SERVER_CONFIG: Option<Arc<ServerConfig>> = match server_config(&ENV.data_path) {
+        Ok(config) => Some(Arc::new(config)),
+        Err(err) => {
+            error!(
+                "Failed to load tls certificate, inbound connections won't be encrypted : {err:?}"
+            );
+            None
+        }
+    };
+    static ref CLIENT_CONFIG: Arc<ClientConfig> = Arc::new(client_config());
+}
+
+/// Peers use self-signed certificates, which only protect against passive eavesdropping.
+struct NoCertVerification;
+
+impl ServerCertVerifier for NoCertVerification {
+    fn verify_server_cert(
+        &self,
+        _: &Certificate,
+        _: &[Certificate],
+        _: &ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: SystemTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn client_config() -> ClientConfig {
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(NoCertVerification))
+        .with_no_client_auth()
+}
+
+fn create_cert(cert_path: &Path, key_path: &Path) -> Result<(), error::Error> {
+    let crypt_error = |err: &dyn std::fmt::Display| error::Error::CryptError(err.to_string());
+    let key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS).map_err(|e| crypt_error(&e))?;
+    let key_pem = key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| crypt_error(&e))?;
+    let mut params = CertificateParams::new(vec![]);
+    params.alg = &rcgen::PKCS_RSA_SHA256;
+    params.key_pair = Some(KeyPair::from_pem(&key_pem).map_err(|e| crypt_error(&e))?);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "zeronet.io");
+    let cert = CertificateBuilder::from_params(params).map_err(|e| crypt_error(&e))?;
+    fs::write(
+        cert_path,
+        cert.serialize_pem().map_err(|e| crypt_error(&e))?,
+    )?;
+    fs::write(key_path, key_pem.as_bytes())?;
+    Ok(())
+}
+
+/// Tls config of fileserver, with certificate of `data_path`, created on first use.
+fn server_config(data_path: &Path) -> Result<ServerConfig, error::Error> {
+    let cert_path = data_path.join(CERT_FILE);
+    let key_path = data_path.join(KEY_FILE);
+    if !cert_path.is_file() || !key_path.is_file() {
+        info!("Creating tls certificate in {data_path:?}");
+        create_cert(&cert_path, &key_path)?;
+    }
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))?
+        .pop()
+        .ok_or_else(|| error::Error::CryptError("No private key in key file".into()))?;
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, PrivateKey(key))
+        .map_err(|err| error::Error::CryptError(err.to_string()))
+}
+
+/// Crypt methods advertised in handshakes.
+pub fn crypt_supported() -> Vec<String> {
+    vec![CRYPT_TLS_RSA.into()]
+}
+
+/// Crypt to use for inbound connection from `ip`, which supports `crypt_supported`.
+/// Local and onion connections, which come from local Tor, aren't encrypted.
+pub fn select_crypt(crypt_supported: &[String], ip: IpAddr) -> Option<String> {
+    let supported = crypt_supported.iter().any(|crypt| crypt == CRYPT_TLS_RSA);
+    (supported && !ip.is_loopback() && SERVER_CONFIG.is_some()).then(|| CRYPT_TLS_RSA.into())
+}
+
+/// Tls stream shared by reader and writer of [`ZeroConnection`].
+pub struct SharedTlsStream<C>(Arc<Mutex<StreamOwned<C, TcpStream>>>);
+
+impl<C> Clone for SharedTlsStream<C> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<C> Read for SharedTlsStream<C>
+where
+    StreamOwned<C, TcpStream>: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl<C> Write for SharedTlsStream<C>
+where
+    StreamOwned<C, TcpStream>: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+impl<C> SharedTlsStream<C>
+where
+    C: Send + 'static,
+    StreamOwned<C, TcpStream>: Read + Write,
+{
+    pub fn into_connection(self) -> Result<ZeroConnection, error::Error> {
+        Ok(ZeroConnection::new(Box::new(self.clone()), Box::new(self))?)
+    }
+}
+
+fn to_tokio(socket: TcpStream) -> io::Result<tokio::net::TcpStream> {
+    socket.set_nonblocking(true)?;
+    tokio::net::TcpStream::from_std(socket)
+}
+
+async fn connect_with(
+    config: Arc<ClientConfig>,
+    socket: TcpStream,
+    host: &str,
+) -> io::Result<SharedTlsStream<ClientConnection>> {
+    let server_name =
+        ServerName::try_from(host).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    let stream = TlsConnector::from(config)
+        .connect(server_name, to_tokio(socket)?)
+        .await?;
+    let (socket, connection) = stream.into_inner();
+    let stream = StreamOwned::new(connection, socket.into_std()?);
+    Ok(SharedTlsStream(Arc::new(Mutex::new(stream))))
+}
+
+async fn accept_with(
+    config: Arc<ServerConfig>,
+    socket: TcpStream,
+) -> io::Result<SharedTlsStream<ServerConnection>> {
+    let stream = TlsAcceptor::from(config).accept(to_tokio(socket)?).await?;
+    let (socket, connection) = stream.into_inner();
+    let stream = StreamOwned::new(connection, socket.into_std()?);
+    Ok(SharedTlsStream(Arc::new(Mutex::new(stream))))
+}
+
+/// Upgrades outbound `socket` to `host` to tls, once handshake agreed on [`CRYPT_TLS_RSA`].
+pub async fn connect(socket: TcpStream, host: &str) -> Result<ZeroConnection, error::Error> {
+    connect_with(CLIENT_CONFIG.clone(), socket, host)
+        .await?
+        .into_connection()
+}
+
+/// Upgrades inbound `socket` to tls, once handshake response selected [`CRYPT_TLS_RSA`].
+pub async fn accept(socket: TcpStream) -> Result<ZeroConnection, error::Error> {
+    let Some(config) = SERVER_CONFIG.clone() else {
+        return Err(error::Error::CryptError("No tls certificate".into()));
+    };
+    accept_with(config, socket).await?.into_connection()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, time::Duration};
+
+    use super::*;
+
+    fn read_exact(stream: &mut impl Read, buf: &mut [u8]) {
+        let mut read = 0;
+        while read < buf.len() {
+            match stream.read(&mut buf[read..]) {
+                Ok(len) => read += len,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Err(err) => panic!("{err:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_connection() {
+        let data_path = std::env::temp_dir().join(format!("zeronet-tls-{}", rand::random::<u64>()));
+        fs::create_dir_all(&data_path).unwrap();
+        let config = Arc::new(server_config(&data_path).unwrap());
+        let cert = fs::read(data_path.join(CERT_FILE)).unwrap();
+        // Certificate is created once and reused after
+        server_config(&data_path).unwrap();
+        assert_eq!(fs::read(data_path.join(CERT_FILE)).unwrap(), cert);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let (server, client) = tokio::join!(
+            accept_with(config, server),
+            connect_with(Arc::new(client_config()), client, "127.0.0.1"),
+        );
+        let (mut server, mut client) = (server.unwrap(), client.unwrap());
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0_u8; 4];
+        read_exact(&mut server, &mut buf);
+        assert_eq!(&buf, b"ping");
+        server.write_all(b"pong").unwrap();
+        read_exact(&mut client, &mut buf);
+        assert_eq!(&buf, b"pong");
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn test_select_crypt() {
+        let ip = "127.0.0.1".parse().unwrap();
+        assert_eq!(select_crypt(&[], ip), None);
+        assert_eq!(select_crypt(&crypt_supported(), ip), None);
+    }
+}
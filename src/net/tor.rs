@@ -0,0 +1,316 @@
+// tor control port client, see https://spec.torproject.org/control-spec
+
+use std::{
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+    str::FromStr,
+    sync::RwLock,
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use log::*;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+use crate::{core::error, environment::ENV};
+
+/// How often control connection is checked, onion services are gone once it closes.
+const CONTROL_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Which onion services are created for fileserver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnionMode {
+    Disabled,
+    /// Single onion shared by all sites, sent in handshakes.
+    Global,
+}
+
+impl FromStr for OnionMode {
+    type Err = error::Error;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "disabled" => Ok(OnionMode::Disabled),
+            "global" => Ok(OnionMode::Global),
+            _ => Err(error::Error::Err(format!("Invalid onion mode {mode}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TorState {
+    pub status: String,
+    /// Onion address without `.onion` suffix, once onion service is running.
+    pub onion: Option<String>,
+}
+
+lazy_static! {
+    pub static ref TOR_STATE: RwLock<TorState> = RwLock::new(TorState {
+        status: "Disabled".into(),
+        onion: None,
+    });
+}
+
+fn set_status(status: impl Into<String>) {
+    TOR_STATE.write().unwrap().status = status.into();
+}
+
+pub fn tor_status() -> String {
+    TOR_STATE.read().unwrap().status.clone()
+}
+
+/// Onion sent in handshakes, set once onion service is running.
+pub fn global_onion() -> Option<String> {
+    TOR_STATE.read().unwrap().onion.clone()
+}
+
+pub struct TorController {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl TorController {
+    pub async fn connect(addr: SocketAddr) -> Result<Self, Error> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    /// Sends `command`, returning reply lines without status codes.
+    /// Replies other than `250` are returned as errors.
+    pub async fn command(&mut self, command: &str) -> Result<Vec<String>, Error> {
+        self.writer
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Tor control connection closed",
+                ));
+            }
+            let line = line.trim_end();
+            if line.len() < 4 || !line.is_char_boundary(4) {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid control reply"));
+            }
+            let (status, separator, content) = (&line[..3], &line[3..4], &line[4..]);
+            if status != "250" {
+                return Err(Error::other(format!("Tor error: {line}")));
+            }
+            lines.push(content.to_string());
+            match separator {
+                " " => return Ok(lines),
+                // Data reply, continues until a single dot line
+                "+" => loop {
+                    let mut data = String::new();
+                    if self.reader.read_line(&mut data).await? == 0 || data.trim_end() == "." {
+                        break;
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// Authenticates with first usable method of NULL, cookie or `password`.
+    pub async fn authenticate(&mut self, password: Option<&str>) -> Result<(), Error> {
+        let info = self.command("PROTOCOLINFO 1").await?;
+        let auth = info
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTH "))
+            .unwrap_or_default();
+        let methods = auth
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("METHODS="))
+            .unwrap_or_default()
+            .split(',')
+            .collect::<Vec<_>>();
+        let cookie_file = auth
+            .split_once("COOKIEFILE=\"")
+            .and_then(|(_, path)| path.split_once('"'))
+            .map(|(path, _)| path);
+        let command = if methods.contains(&"NULL") {
+            "AUTHENTICATE".to_string()
+        } else if methods.contains(&"COOKIE")
+            && let Some(cookie_file) = cookie_file
+        {
+            let cookie = tokio::fs::read(cookie_file).await?;
+            format!("AUTHENTICATE {}", hex::encode(cookie))
+        } else if methods.contains(&"HASHEDPASSWORD")
+            && let Some(password) = password
+        {
+            let password = password.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("AUTHENTICATE \"{password}\"")
+        } else {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("No usable tor auth method in {methods:?}"),
+            ));
+        };
+        self.command(&command).await?;
+        Ok(())
+    }
+
+    /// Creates onion service forwarding `port` to `target`, with new v3 key unless `key` is given.
+    /// Returns service id, which is onion address without `.onion`, and private key.
+    pub async fn add_onion(
+        &mut self,
+        port: u16,
+        target: SocketAddr,
+        key: Option<&str>,
+    ) -> Result<(String, Option<String>), Error> {
+        let key = key.map_or("NEW:ED25519-V3".to_string(), |key| {
+            format!("ED25519-V3:{key}")
+        });
+        let reply = self
+            .command(&format!("ADD_ONION {key} Port={port},{target}"))
+            .await?;
+        let field = |name: &str| {
+            reply
+                .iter()
+                .find_map(|line| line.strip_prefix(name))
+                .map(String::from)
+        };
+        let service_id = field("ServiceID=")
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "No ServiceID in reply"))?;
+        let private_key = field("PrivateKey=ED25519-V3:");
+        Ok((service_id, private_key))
+    }
+}
+
+async fn add_onion(controller: &mut TorController) -> Result<String, Error> {
+    let port = ENV.fileserver_port;
+    let target = format!("{}:{}", ENV.fileserver_ip, port)
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid fileserver address"))?;
+    let (onion, _) = controller.add_onion(port, target, None).await?;
+    debug!("Created onion {onion}.onion");
+    Ok(onion)
+}
+
+/// Creates onion service for fileserver, unless [`OnionMode::Disabled`], and keeps control
+/// connection open for as long as it lives, as ephemeral onion services are removed once it closes.
+pub async fn run_onion() {
+    if ENV.tor_onions == OnionMode::Disabled {
+        return;
+    }
+    set_status("Connecting");
+    let res = async {
+        let mut controller = TorController::connect(ENV.tor_controller).await?;
+        controller.authenticate(ENV.tor_password.as_deref()).await?;
+        let onion = add_onion(&mut controller).await?;
+        Ok::<_, Error>((controller, onion))
+    }
+    .await;
+    let mut controller = match res {
+        Ok((controller, onion)) => {
+            info!("Running onion service {onion}.onion");
+            let mut state = TOR_STATE.write().unwrap();
+            state.status = "OK".into();
+            state.onion = Some(onion);
+            controller
+        }
+        Err(err) => {
+            error!("Failed to create onion service, Error : {err:?}");
+            set_status(format!("Error: {err}"));
+            return;
+        }
+    };
+    loop {
+        tokio::time::sleep(CONTROL_CHECK_INTERVAL).await;
+        if let Err(err) = controller.command("GETINFO version").await {
+            error!("Tor control connection lost, Error : {err:?}");
+            let mut state = TOR_STATE.write().unwrap();
+            state.status = format!("Error: {err}");
+            state.onion = None;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const SERVICE_ID: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd";
+
+    /// Scripted control port, replying to each received command with next of `replies`.
+    async fn fake_control_port(
+        replies: Vec<&'static str>,
+    ) -> (SocketAddr, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let mut commands = vec![];
+            for reply in replies {
+                let mut command = String::new();
+                reader.read_line(&mut command).await.unwrap();
+                commands.push(command.trim_end().to_string());
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+            commands
+        });
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn test_add_onion() {
+        let (addr, handle) = fake_control_port(vec![
+            "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+            "250 OK\r\n",
+            "250-ServiceID=vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd\r\n250-PrivateKey=ED25519-V3:secret\r\n250 OK\r\n",
+            "552 Unknown onion service id\r\n",
+        ])
+        .await;
+        let mut controller = TorController::connect(addr).await.unwrap();
+        controller.authenticate(None).await.unwrap();
+        let target = "127.0.0.1:15441".parse().unwrap();
+        let (service_id, key) = controller.add_onion(15441, target, None).await.unwrap();
+        assert_eq!(service_id, SERVICE_ID);
+        assert_eq!(key.as_deref(), Some("secret"));
+        let err = controller.command("DEL_ONION unknown").await.unwrap_err();
+        assert_eq!(err.to_string(), "Tor error: 552 Unknown onion service id");
+
+        let commands = handle.await.unwrap();
+        assert_eq!(commands[1], "AUTHENTICATE");
+        assert_eq!(
+            commands[2],
+            "ADD_ONION NEW:ED25519-V3 Port=15441,127.0.0.1:15441"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_auth() {
+        let (addr, handle) = fake_control_port(vec![
+            "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=HASHEDPASSWORD\r\n250 OK\r\n",
+            "515 Authentication failed\r\n",
+        ])
+        .await;
+        let mut controller = TorController::connect(addr).await.unwrap();
+        assert!(controller.authenticate(Some("pass\"word")).await.is_err());
+        assert_eq!(handle.await.unwrap()[1], "AUTHENTICATE \"pass\\\"word\"");
+
+        let (addr, _) = fake_control_port(vec![
+            "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=HASHEDPASSWORD\r\n250 OK\r\n",
+        ])
+        .await;
+        let mut controller = TorController::connect(addr).await.unwrap();
+        let err = controller.authenticate(None).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+}
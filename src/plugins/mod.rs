@@ -1,11 +1,13 @@
 pub mod core;
 pub mod utils;
 
+pub mod optional_manager;
 pub mod path_provider;
+pub mod peer_db;
 pub mod site_server;
+pub mod tracker;
 
 mod auth_wrapper;
-mod peer_db;
 mod websocket;
 
 wit_bindgen_wasmer::import!("assets/plugins/manifest.wit");
@@ -0,0 +1,590 @@
+// Optional files downloaded on demand, tracked in content.db so that total size of them
+// can be kept within optional limit, by evicting least recently accessed unpinned ones.
+
+use std::collections::HashSet;
+
+use log::*;
+use regex::Regex;
+use rusqlite::{params, params_from_iter, OptionalExtension, Row};
+use serde_json::{json, Value};
+use zerucontent::File as ZFile;
+
+use crate::{
+    controllers::sites::SitesController,
+    core::{error::Error, hashfield::Hashfield},
+    io::utils::current_unix_epoch,
+};
+
+pub const GB: u64 = 1024 * 1024 * 1024;
+/// Optional limit in GB, unless set with optionalLimitSet or `--optional_limit`.
+pub const DEFAULT_OPTIONAL_LIMIT: usize = 10;
+
+/// Same layout as tables of ZeroNet's content.db, so files and settings it saved are kept.
+pub(crate) const OPTIONAL_DB_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS file_optional (
+    file_id INTEGER PRIMARY KEY UNIQUE NOT NULL,
+    site_id INTEGER REFERENCES site (site_id) ON DELETE CASCADE,
+    inner_path TEXT,
+    hash_id INTEGER,
+    size INTEGER,
+    peer INTEGER DEFAULT 0,
+    uploaded INTEGER DEFAULT 0,
+    is_downloaded INTEGER DEFAULT 0,
+    is_pinned INTEGER DEFAULT 0,
+    time_added INTEGER DEFAULT 0,
+    time_downloaded INTEGER DEFAULT 0,
+    time_accessed INTEGER DEFAULT 0
+);
+CREATE UNIQUE INDEX IF NOT EXISTS file_optional_key ON file_optional (site_id, inner_path);
+CREATE INDEX IF NOT EXISTS is_downloaded ON file_optional (is_downloaded);
+CREATE TABLE IF NOT EXISTS keyvalue (
+    keyvalue_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    key TEXT,
+    value INTEGER,
+    json_id INTEGER
+);
+CREATE UNIQUE INDEX IF NOT EXISTS key_id ON keyvalue (json_id, key);
+";
+
+const UPSERT_DOWNLOADED: &str = "
+INSERT INTO file_optional
+    (site_id, inner_path, hash_id, size, is_downloaded, time_added, time_downloaded, time_accessed)
+    VALUES (?1, ?2, ?3, ?4, 1, ?5, ?5, ?5)
+ON CONFLICT (site_id, inner_path) DO UPDATE SET
+    hash_id = ?3, size = ?4, is_downloaded = 1, time_downloaded = ?5, time_accessed = ?5
+";
+
+/// Marks optional file found on disk as downloaded, keeping times of already known files.
+const SYNC_DOWNLOADED: &str = "
+INSERT INTO file_optional
+    (site_id, inner_path, hash_id, size, is_downloaded, time_added, time_downloaded, time_accessed)
+    VALUES (?1, ?2, ?3, ?4, 1, ?5, ?5, ?5)
+ON CONFLICT (site_id, inner_path) DO UPDATE SET
+    hash_id = ?3, size = ?4, is_downloaded = 1
+";
+
+const FILE_COLUMNS: &str = "address, inner_path, hash_id, size, is_downloaded, is_pinned, time_added, time_downloaded, time_accessed";
+
+/// Optional limit in bytes, from limit given in GB.
+pub fn optional_limit_bytes(limit_gb: usize) -> u64 {
+    limit_gb as u64 * GB
+}
+
+fn file_row(row: &Row) -> rusqlite::Result<Value> {
+    Ok(json!({
+        "address": row.get::<_, String>(0)?,
+        "inner_path": row.get::<_, String>(1)?,
+        "hash_id": row.get::<_, Option<u16>>(2)?,
+        "size": row.get::<_, i64>(3)?,
+        "is_downloaded": row.get::<_, i64>(4)?,
+        "is_pinned": row.get::<_, i64>(5)?,
+        "time_added": row.get::<_, i64>(6)?,
+        "time_downloaded": row.get::<_, i64>(7)?,
+        "time_accessed": row.get::<_, i64>(8)?,
+    }))
+}
+
+impl SitesController {
+    /// Optional limit in bytes saved with [`SitesController::save_optional_limit`], or default one.
+    pub fn saved_optional_limit(&mut self) -> u64 {
+        let saved = self.content_db().and_then(|conn| {
+            let limit = conn
+                .query_row(
+                    "SELECT value FROM keyvalue WHERE json_id = 0 AND key = 'optional_limit'",
+                    [],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?;
+            Ok(limit)
+        });
+        match saved {
+            Ok(Some(limit)) => optional_limit_bytes(limit as usize),
+            Ok(None) => optional_limit_bytes(DEFAULT_OPTIONAL_LIMIT),
+            Err(err) => {
+                error!("Failed to load optional limit, Error : {err:?}");
+                optional_limit_bytes(DEFAULT_OPTIONAL_LIMIT)
+            }
+        }
+    }
+
+    /// Sets optional limit in GB, keeping it across restarts.
+    pub fn save_optional_limit(&mut self, limit_gb: usize) -> Result<(), Error> {
+        self.content_db()?.execute(
+            "INSERT INTO keyvalue (json_id, key, value) VALUES (0, 'optional_limit', ?)
+            ON CONFLICT (json_id, key) DO UPDATE SET value = excluded.value",
+            params![limit_gb as i64],
+        )?;
+        self.optional_limit = optional_limit_bytes(limit_gb);
+        Ok(())
+    }
+
+    /// Records optional file of site as downloaded, so it counts towards optional limit.
+    pub fn optional_file_downloaded(
+        &mut self,
+        site_addr: &str,
+        inner_path: &str,
+        file: &ZFile,
+    ) -> Result<(), Error> {
+        let now = current_unix_epoch() as i64;
+        let conn = self.content_db()?;
+        let site_id = Self::site_id(conn, site_addr)?;
+        conn.execute(
+            UPSERT_DOWNLOADED,
+            params![
+                site_id,
+                inner_path,
+                Hashfield::hash_id(&file.sha512),
+                file.size as i64,
+                now
+            ],
+        )?;
+        if let Some(site) = self.sites.get_mut(site_addr) {
+            let mut hashfield = site.hashfield();
+            hashfield.append_hash(&file.sha512);
+            site.set_hashfield(&hashfield);
+        }
+        self.update_size_optional(site_addr)
+    }
+
+    /// Updates last access time of optional file, least recently accessed ones are evicted first.
+    pub fn optional_file_accessed(
+        &mut self,
+        site_addr: &str,
+        inner_path: &str,
+    ) -> Result<(), Error> {
+        let now = current_unix_epoch() as i64;
+        let conn = self.content_db()?;
+        let site_id = Self::site_id(conn, site_addr)?;
+        conn.execute(
+            "UPDATE file_optional SET time_accessed = ? WHERE site_id = ? AND inner_path = ?",
+            params![now, site_id, inner_path],
+        )?;
+        Ok(())
+    }
+
+    /// Optional files of site, or of all sites if `site_addr` is None, matching comma separated
+    /// `filter` of `downloaded` and `pinned` and, if given, `inner_path` LIKE pattern.
+    pub fn optional_file_list(
+        &mut self,
+        site_addr: Option<&str>,
+        filter: &str,
+        filter_inner_path: Option<&str>,
+        order_by: &str,
+        limit: usize,
+    ) -> Result<Vec<Value>, Error> {
+        let order_regex = Regex::new("^[a-z_]+( DESC| ASC)?$").unwrap();
+        if !order_regex.is_match(order_by) {
+            return Err(Error::Err(format!("Invalid order_by : {order_by}")));
+        }
+        let mut wheres = vec![];
+        let mut values = vec![];
+        for filter in filter.split(',').filter(|filter| !filter.is_empty()) {
+            match filter {
+                "downloaded" => wheres.push("is_downloaded = 1"),
+                "pinned" => wheres.push("is_pinned = 1"),
+                _ => return Err(Error::Err(format!("Invalid filter : {filter}"))),
+            }
+        }
+        if let Some(site_addr) = site_addr {
+            wheres.push("address = ?");
+            values.push(site_addr);
+        }
+        if let Some(inner_path) = filter_inner_path {
+            wheres.push("inner_path LIKE ?");
+            values.push(inner_path);
+        }
+        let mut query =
+            format!("SELECT {FILE_COLUMNS} FROM file_optional JOIN site USING (site_id)");
+        if !wheres.is_empty() {
+            query += &format!(" WHERE {}", wheres.join(" AND "));
+        }
+        query += &format!(" ORDER BY {order_by} LIMIT {limit}");
+        let conn = self.content_db()?;
+        let mut stmt = conn.prepare(&query)?;
+        let files = stmt
+            .query_map(params_from_iter(values), file_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(files)
+    }
+
+    pub fn optional_file_info(
+        &mut self,
+        site_addr: &str,
+        inner_path: &str,
+    ) -> Result<Option<Value>, Error> {
+        let conn = self.content_db()?;
+        let info = conn
+            .query_row(
+                &format!("SELECT {FILE_COLUMNS} FROM file_optional JOIN site USING (site_id) WHERE address = ? AND inner_path = ?"),
+                params![site_addr, inner_path],
+                file_row,
+            )
+            .optional()?;
+        Ok(info)
+    }
+
+    /// Pins or unpins optional files of site, pinned files are never evicted over optional limit.
+    pub fn set_optional_files_pinned(
+        &mut self,
+        site_addr: &str,
+        inner_paths: &[String],
+        pinned: bool,
+    ) -> Result<usize, Error> {
+        let conn = self.content_db()?;
+        let site_id = Self::site_id(conn, site_addr)?;
+        let mut updated = 0;
+        for inner_path in inner_paths {
+            updated += conn.execute(
+                "UPDATE file_optional SET is_pinned = ? WHERE site_id = ? AND inner_path = ?",
+                params![pinned, site_id, inner_path],
+            )?;
+        }
+        Ok(updated)
+    }
+
+    /// Deletes downloaded optional file of site, returns false if it isn't downloaded.
+    pub fn remove_optional_file(
+        &mut self,
+        site_addr: &str,
+        inner_path: &str,
+    ) -> Result<bool, Error> {
+        let conn = self.content_db()?;
+        let site_id = Self::site_id(conn, site_addr)?;
+        let hash_id = conn
+            .query_row(
+                "SELECT hash_id FROM file_optional WHERE site_id = ? AND inner_path = ? AND is_downloaded = 1",
+                params![site_id, inner_path],
+                |row| row.get::<_, Option<u16>>(0),
+            )
+            .optional()?;
+        let Some(hash_id) = hash_id else {
+            return Ok(false);
+        };
+        if let Some(site) = self.sites.get_mut(site_addr) {
+            site.delete_optional_file(inner_path, hash_id)?;
+        }
+        let conn = self.content_db()?;
+        conn.execute(
+            "UPDATE file_optional SET is_downloaded = 0, is_pinned = 0 WHERE site_id = ? AND inner_path = ?",
+            params![site_id, inner_path],
+        )?;
+        self.update_size_optional(site_addr)?;
+        Ok(true)
+    }
+
+    /// Total size of downloaded optional files of all sites.
+    pub fn optional_used(&mut self) -> Result<u64, Error> {
+        let conn = self.content_db()?;
+        let used: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM file_optional WHERE is_downloaded = 1",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(used as u64)
+    }
+
+    /// Sets `size_optional` of site to total size of its downloaded optional files.
+    fn update_size_optional(&mut self, site_addr: &str) -> Result<(), Error> {
+        let conn = self.content_db()?;
+        let site_id = Self::site_id(conn, site_addr)?;
+        let size: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM file_optional WHERE site_id = ? AND is_downloaded = 1",
+            params![site_id],
+            |row| row.get(0),
+        )?;
+        if let Some(site) = self.sites.get_mut(site_addr) {
+            site.storage.stats.size_optional = size as usize;
+        }
+        Ok(())
+    }
+
+    /// Marks optional files of site we have on disk as downloaded, and ones we don't as not.
+    pub async fn sync_optional_files(&mut self, site_addr: &str) -> Result<usize, Error> {
+        let Some(site) = self.sites.get(site_addr) else {
+            return Err(Error::SiteNotFound);
+        };
+        let files = site.downloaded_optional_files().await?;
+        let now = current_unix_epoch() as i64;
+        let conn = self.content_db()?;
+        let site_id = Self::site_id(conn, site_addr)?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "UPDATE file_optional SET is_downloaded = 0 WHERE site_id = ?",
+            params![site_id],
+        )?;
+        {
+            let mut stmt = tx.prepare(SYNC_DOWNLOADED)?;
+            for (inner_path, file) in &files {
+                stmt.execute(params![
+                    site_id,
+                    inner_path,
+                    Hashfield::hash_id(&file.sha512),
+                    file.size as i64,
+                    now
+                ])?;
+            }
+        }
+        tx.commit()?;
+        self.update_size_optional(site_addr)?;
+        Ok(files.len())
+    }
+
+    pub async fn sync_all_optional_files(&mut self) {
+        let sites = self.sites.keys().cloned().collect::<Vec<_>>();
+        for site in sites {
+            match self.sync_optional_files(&site).await {
+                Ok(count) => debug!("Found {count} downloaded optional files of {site}"),
+                Err(err) => error!("Failed to sync optional files of {site}, Error : {err:?}"),
+            }
+        }
+    }
+
+    /// Deletes least recently accessed unpinned optional files of all sites,
+    /// until downloaded optional files fit within optional limit.
+    pub fn check_optional_limit(&mut self) -> Result<usize, Error> {
+        let limit = i64::try_from(self.optional_limit).unwrap_or(i64::MAX);
+        let mut need_delete = self.optional_used()? as i64 - limit;
+        if need_delete <= 0 {
+            return Ok(0);
+        }
+        let conn = self.content_db()?;
+        let mut evicted = vec![];
+        {
+            let mut stmt = conn.prepare(
+                "SELECT file_id, address, inner_path, hash_id, size FROM file_optional JOIN site USING (site_id) WHERE is_downloaded = 1 AND is_pinned = 0 ORDER BY time_accessed ASC",
+            )?;
+            let mut rows = stmt.query([])?;
+            while need_delete > 0
+                && let Some(row) = rows.next()?
+            {
+                let size = row.get::<_, i64>(4)?;
+                need_delete -= size;
+                evicted.push((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<u16>>(3)?,
+                ));
+            }
+        }
+        let mut sites = HashSet::new();
+        for (_, site_addr, inner_path, hash_id) in &evicted {
+            if let Some(site) = self.sites.get_mut(site_addr) {
+                if let Err(err) = site.delete_optional_file(inner_path, *hash_id) {
+                    error!("Failed to delete optional file {inner_path}, Error : {err:?}");
+                }
+                sites.insert(site_addr.clone());
+            }
+        }
+        let conn = self.content_db()?;
+        for (file_id, ..) in &evicted {
+            conn.execute(
+                "UPDATE file_optional SET is_downloaded = 0 WHERE file_id = ?",
+                params![file_id],
+            )?;
+        }
+        for site_addr in sites {
+            self.update_size_optional(&site_addr)?;
+        }
+        info!(
+            "Deleted {} optional files over optional limit of {} bytes",
+            evicted.len(),
+            self.optional_limit
+        );
+        Ok(evicted.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::{controllers::sites::SitesController, core::site::Site, io::db::DbManager};
+
+    const SITE: &str = "1HELLoE3sFD9569CLCbHEAVqvqV7U2Ri9d";
+
+    fn file(size: usize) -> zerucontent::File {
+        zerucontent::File {
+            sha512: "ea2c2a92a0b7e3c6cde0c4e8d2aa6f5e3b6a4d3ba6e71e5d8ab2b3e4a3ba3f21".into(),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_optional_limit() {
+        let mut controller = SitesController::new(DbManager::new());
+        controller
+            .insert_content_db(Connection::open_in_memory().unwrap())
+            .unwrap();
+        let data_path =
+            std::env::temp_dir().join(format!("zeronet-optional-{}", rand::random::<u64>()));
+        controller
+            .sites
+            .insert(SITE.into(), Site::new(SITE, data_path).unwrap());
+        controller.optional_limit = 250;
+
+        for inner_path in ["pinned.jpg", "old.jpg", "new.jpg"] {
+            controller
+                .optional_file_downloaded(SITE, inner_path, &file(100))
+                .unwrap();
+        }
+        // Pinned file is least recently accessed, but is kept
+        let conn = controller.content_db().unwrap();
+        conn.execute_batch(
+            "UPDATE file_optional SET is_pinned = 1, time_accessed = 1 WHERE inner_path = 'pinned.jpg';
+            UPDATE file_optional SET time_accessed = 2 WHERE inner_path = 'old.jpg';",
+        )
+        .unwrap();
+        assert_eq!(controller.sites[SITE].storage.stats.size_optional, 300);
+
+        assert_eq!(controller.check_optional_limit().unwrap(), 1);
+        assert_eq!(controller.sites[SITE].storage.stats.size_optional, 200);
+        let downloaded = controller
+            .content_db()
+            .unwrap()
+            .prepare(
+                "SELECT inner_path FROM file_optional WHERE is_downloaded = 1 ORDER BY inner_path",
+            )
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(downloaded, vec!["new.jpg", "pinned.jpg"]);
+        assert_eq!(controller.check_optional_limit().unwrap(), 0);
+
+        let list = |controller: &mut SitesController, filter| {
+            controller
+                .optional_file_list(Some(SITE), filter, None, "inner_path DESC", 10)
+                .unwrap()
+                .into_iter()
+                .map(|file| file["inner_path"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(list(&mut controller, "pinned"), vec!["pinned.jpg"]);
+        assert!(controller
+            .optional_file_list(None, "", None, "size; DROP TABLE site", 10)
+            .is_err());
+        assert!(controller.remove_optional_file(SITE, "new.jpg").unwrap());
+        assert!(!controller.remove_optional_file(SITE, "old.jpg").unwrap());
+        assert_eq!(list(&mut controller, "downloaded"), vec!["pinned.jpg"]);
+        assert_eq!(controller.optional_used().unwrap(), 100);
+        let info = controller
+            .optional_file_info(SITE, "new.jpg")
+            .unwrap()
+            .unwrap();
+        assert_eq!(info["is_downloaded"], 0);
+
+        assert_eq!(controller.saved_optional_limit(), 10 * super::GB);
+        controller.save_optional_limit(2).unwrap();
+        assert_eq!(controller.optional_limit, 2 * super::GB);
+        assert_eq!(controller.saved_optional_limit(), 2 * super::GB);
+    }
+
+    /// Tables of content.db, as created by ZeroNet.
+    const ZERONET_CONTENT_DB: &str = "
+CREATE TABLE keyvalue (keyvalue_id INTEGER PRIMARY KEY AUTOINCREMENT, key TEXT, value INTEGER, json_id INTEGER);
+CREATE UNIQUE INDEX key_id ON keyvalue(json_id, key);
+CREATE TABLE site (site_id INTEGER PRIMARY KEY ASC NOT NULL UNIQUE, address TEXT NOT NULL);
+CREATE UNIQUE INDEX site_address ON site (address);
+CREATE TABLE file_optional (file_id INTEGER PRIMARY KEY UNIQUE NOT NULL, site_id INTEGER REFERENCES site (site_id) ON DELETE CASCADE, inner_path TEXT, hash_id INTEGER, size INTEGER, peer INTEGER DEFAULT 0, uploaded INTEGER DEFAULT 0, is_downloaded INTEGER DEFAULT 0, is_pinned INTEGER DEFAULT 0, time_added INTEGER DEFAULT 0, time_downloaded INTEGER DEFAULT 0, time_accessed INTEGER DEFAULT 0);
+CREATE UNIQUE INDEX file_optional_key ON file_optional (site_id, inner_path);
+CREATE INDEX is_downloaded ON file_optional (is_downloaded);
+INSERT INTO keyvalue (key, value, json_id) VALUES ('table.file_optional.version', 11, 0);
+INSERT INTO site (address) VALUES ('1HELLoE3sFD9569CLCbHEAVqvqV7U2Ri9d');
+INSERT INTO file_optional (site_id, inner_path, hash_id, size, is_downloaded, time_added, time_downloaded, time_accessed)
+    VALUES (1, 'zeronet.jpg', 60004, 100, 1, 1, 1, 1);
+";
+
+    #[test]
+    fn test_zeronet_content_db() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(ZERONET_CONTENT_DB).unwrap();
+        let mut controller = SitesController::new(DbManager::new());
+        controller.insert_content_db(conn).unwrap();
+        let data_path =
+            std::env::temp_dir().join(format!("zeronet-optional-{}", rand::random::<u64>()));
+        controller
+            .sites
+            .insert(SITE.into(), Site::new(SITE, data_path).unwrap());
+
+        controller
+            .optional_file_downloaded(SITE, "new.jpg", &file(50))
+            .unwrap();
+        assert_eq!(controller.optional_used().unwrap(), 150);
+        let info = controller
+            .optional_file_info(SITE, "new.jpg")
+            .unwrap()
+            .unwrap();
+        assert_eq!(info["hash_id"], 0xea2c);
+        assert!(controller
+            .remove_optional_file(SITE, "zeronet.jpg")
+            .unwrap());
+        assert_eq!(controller.optional_used().unwrap(), 50);
+        assert_eq!(controller.sites[SITE].storage.stats.size_optional, 50);
+
+        assert_eq!(controller.saved_optional_limit(), 10 * super::GB);
+        controller.save_optional_limit(2).unwrap();
+        controller.save_optional_limit(3).unwrap();
+        assert_eq!(controller.saved_optional_limit(), 3 * super::GB);
+    }
+
+    #[tokio::test]
+    async fn test_sync_keeps_access_order() {
+        let mut controller = SitesController::new(DbManager::new());
+        controller
+            .insert_content_db(Connection::open_in_memory().unwrap())
+            .unwrap();
+        let data_path =
+            std::env::temp_dir().join(format!("zeronet-optional-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&data_path).unwrap();
+        let mut site = Site::new(SITE, data_path.clone()).unwrap();
+        let mut content = zerucontent::Content::default();
+        for inner_path in ["old.jpg", "new.jpg"] {
+            content.files_optional.insert(inner_path.into(), file(100));
+            std::fs::write(data_path.join(inner_path), [0; 100]).unwrap();
+        }
+        site.modify_content(None, content);
+        controller.sites.insert(SITE.into(), site);
+
+        for inner_path in ["old.jpg", "new.jpg"] {
+            controller
+                .optional_file_downloaded(SITE, inner_path, &file(100))
+                .unwrap();
+        }
+        controller
+            .content_db()
+            .unwrap()
+            .execute_batch(
+                "UPDATE file_optional SET time_accessed = 1, time_downloaded = 1 WHERE inner_path = 'old.jpg';
+                UPDATE file_optional SET time_accessed = 2, time_downloaded = 2 WHERE inner_path = 'new.jpg';",
+            )
+            .unwrap();
+        std::fs::remove_file(data_path.join("new.jpg")).unwrap();
+        assert_eq!(controller.sync_optional_files(SITE).await.unwrap(), 1);
+
+        let files = controller
+            .content_db()
+            .unwrap()
+            .prepare(
+                "SELECT inner_path, is_downloaded, time_downloaded FROM file_optional ORDER BY time_accessed",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            files,
+            vec![("old.jpg".into(), 1, 1), ("new.jpg".into(), 0, 2)]
+        );
+        assert_eq!(controller.sites[SITE].storage.stats.size_optional, 100);
+        std::fs::remove_dir_all(data_path).unwrap();
+    }
+}
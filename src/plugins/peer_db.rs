@@ -1,35 +1,225 @@
+use std::time::Duration;
+
 use decentnet_protocol::address::PeerAddr;
-use rusqlite::params;
+use log::*;
+use rusqlite::{params, Connection};
+use time::OffsetDateTime;
 
 use crate::{
     controllers::sites::SitesController,
-    core::{peer::Peer, site::Site},
+    core::{error::Error, hashfield::Hashfield, peer::Peer},
+    environment::ENV,
+    io::db::DbManager,
+    plugins::optional_manager::OPTIONAL_DB_SCHEMA,
 };
 
+/// How often known peers of all sites are written to content.db.
+pub const PEER_SAVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub(crate) const CONTENT_DB: &str = "content_db";
+
+const PEER_DB_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS site (
+    site_id INTEGER PRIMARY KEY ASC NOT NULL UNIQUE,
+    address TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS site_address ON site (address);
+CREATE TABLE IF NOT EXISTS peer (
+    site_id INTEGER REFERENCES site (site_id) ON DELETE CASCADE,
+    address TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    hashfield BLOB,
+    reputation INTEGER NOT NULL,
+    time_added INTEGER NOT NULL,
+    time_found INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS peer_key ON peer (site_id, address, port);
+";
+
 impl SitesController {
-    pub fn load_peers(&mut self, site: &Site) {
-        let addr = site.address();
-        let conn = self.db_manager.get_db("content_db").unwrap();
-        let site_id = conn
-            .query_row("SELECT * FROM site WHERE address = ?", params![addr], |a| {
-                a.get::<_, i32>(0)
-            })
-            .unwrap();
-        let mut stmt = conn
-            .prepare(
-                "SELECT address, port FROM peer WHERE site_id = ? AND address NOT LIKE '%.onion' AND reputation > 0", //TODO! Don't filter out onion peers
-            )
-            .unwrap();
-        let peers = stmt
-            .query_map(params![site_id], |row| {
-                Ok((row.get::<_, String>(0)?, row.get::<_, i32>(1)?))
+    /// Uses `conn` as content.db, creating tables of peers and optional files in it.
+    pub(crate) fn insert_content_db(&mut self, conn: Connection) -> Result<(), Error> {
+        conn.execute_batch(PEER_DB_SCHEMA)?;
+        conn.execute_batch(OPTIONAL_DB_SCHEMA)?;
+        self.db_manager.insert_connection(CONTENT_DB, conn);
+        Ok(())
+    }
+
+    /// Returns content.db connection, opening it on first use.
+    pub(crate) fn content_db(&mut self) -> Result<&mut Connection, Error> {
+        if self.db_manager.get_db(CONTENT_DB).is_none() {
+            let conn = DbManager::connect_db_from_path(&ENV.data_path.join("content.db"))?;
+            self.insert_content_db(conn)?;
+        }
+        Ok(self.db_manager.get_db(CONTENT_DB).unwrap())
+    }
+
+    pub(crate) fn site_id(conn: &Connection, address: &str) -> Result<i64, Error> {
+        conn.execute(
+            "INSERT OR IGNORE INTO site (address) VALUES (?)",
+            params![address],
+        )?;
+        let site_id = conn.query_row(
+            "SELECT site_id FROM site WHERE address = ?",
+            params![address],
+            |row| row.get(0),
+        )?;
+        Ok(site_id)
+    }
+
+    /// Splits peer address into host and port, as stored in peer table.
+    fn split_peer_addr(peer: &Peer) -> Option<(String, u16)> {
+        let addr = peer.address().to_string();
+        let (host, port) = addr.rsplit_once(':')?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        Some((host.to_string(), port.parse().ok()?))
+    }
+
+    pub fn load_peers(&mut self, site_addr: &str) -> Result<usize, Error> {
+        let conn = self.content_db()?;
+        let site_id = Self::site_id(conn, site_addr)?;
+        let mut stmt = conn.prepare(
+            "SELECT address, port, hashfield, reputation, time_added, time_found FROM peer WHERE site_id = ?",
+        )?;
+        let rows = stmt.query_map(params![site_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u16>(1)?,
+                row.get::<_, Option<Vec<u8>>>(2)?,
+                row.get::<_, isize>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+            ))
+        })?;
+        let mut peers = vec![];
+        for row in rows {
+            let (host, port, hashfield, reputation, time_added, time_found) = row?;
+            let addr = if host.contains(':') {
+                format!("[{host}]:{port}")
+            } else {
+                format!("{host}:{port}")
+            };
+            let Ok(addr) = PeerAddr::parse(addr) else {
+                debug!("Skipping invalid peer address {host}:{port} of site {site_addr}");
+                continue;
+            };
+            let mut peer = Peer::new(addr);
+            peer.set_reputation(reputation);
+            let now = OffsetDateTime::now_utc();
+            peer.set_time_added(OffsetDateTime::from_unix_timestamp(time_added).unwrap_or(now));
+            peer.set_time_found(OffsetDateTime::from_unix_timestamp(time_found).unwrap_or(now));
+            if let Some(hashfield) = hashfield {
+                peer.set_hashfield(Hashfield::from_raw(&hashfield));
+            }
+            if !peer.is_bad() {
+                peers.push(peer);
+            }
+        }
+        drop(stmt);
+        let count = peers.len();
+        if let Some(site) = self.sites.get_mut(site_addr) {
+            site.add_peers(peers);
+        }
+        Ok(count)
+    }
+
+    /// Replaces stored peers of site with currently known ones.
+    pub fn save_peers(&mut self, site_addr: &str) -> Result<usize, Error> {
+        let Some(site) = self.sites.get(site_addr) else {
+            return Err(Error::SiteNotFound);
+        };
+        let peers = site
+            .peers
+            .values()
+            .filter_map(|peer| {
+                let (host, port) = Self::split_peer_addr(peer)?;
+                // Last time peer responded or was found, whichever is later
+                let time_found = peer.time_found().max(peer.time_response());
+                Some((
+                    host,
+                    port,
+                    peer.hashfield().to_raw(),
+                    peer.reputation(),
+                    peer.time_added(),
+                    time_found,
+                ))
             })
-            .unwrap();
-        for peer in peers {
-            let (ip, port) = peer.unwrap();
-            let peer = format!("{ip}:{port}");
-            let peer = Peer::new(PeerAddr::parse(peer).unwrap());
-            self.sites.get_mut(addr).unwrap().add_peer(peer)
+            .collect::<Vec<_>>();
+        let conn = self.content_db()?;
+        let site_id = Self::site_id(conn, site_addr)?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM peer WHERE site_id = ?", params![site_id])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO peer (site_id, address, port, hashfield, reputation, time_added, time_found) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            for (host, port, hashfield, reputation, time_added, time_found) in &peers {
+                let hashfield = (!hashfield.is_empty()).then_some(hashfield);
+                stmt.execute(params![
+                    site_id,
+                    host,
+                    port,
+                    hashfield,
+                    *reputation as i64,
+                    time_added.unix_timestamp(),
+                    time_found.unix_timestamp(),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(peers.len())
+    }
+
+    pub fn load_all_peers(&mut self) {
+        let sites = self.sites.keys().cloned().collect::<Vec<_>>();
+        for site in sites {
+            match self.load_peers(&site) {
+                Ok(count) => debug!("Loaded {count} peers of {site} from content.db"),
+                Err(err) => error!("Failed to load peers of {site}, Error : {err:?}"),
+            }
+        }
+    }
+
+    pub fn save_all_peers(&mut self) {
+        let sites = self.sites.keys().cloned().collect::<Vec<_>>();
+        for site in sites {
+            match self.save_peers(&site) {
+                Ok(count) => debug!("Saved {count} peers of {site} to content.db"),
+                Err(err) => error!("Failed to save peers of {site}, Error : {err:?}"),
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use decentnet_protocol::address::PeerAddr;
+    use rusqlite::Connection;
+
+    use crate::{
+        controllers::sites::SitesController,
+        core::{peer::Peer, site::Site},
+        io::db::DbManager,
+    };
+
+    const SITE: &str = "1HELLoE3sFD9569CLCbHEAVqvqV7U2Ri9d";
+
+    #[test]
+    fn test_save_and_load_peers() {
+        let mut controller = SitesController::new(DbManager::new());
+        controller
+            .insert_content_db(Connection::open_in_memory().unwrap())
+            .unwrap();
+        let mut site = Site::new(SITE, Default::default()).unwrap();
+        let mut peer = Peer::new(PeerAddr::parse("127.0.0.1:15441".to_string()).unwrap());
+        peer.set_reputation(7);
+        site.add_peer(peer);
+        controller.sites.insert(SITE.into(), site);
+        assert_eq!(controller.save_peers(SITE).unwrap(), 1);
+
+        controller.sites.get_mut(SITE).unwrap().peers.clear();
+        assert_eq!(controller.load_peers(SITE).unwrap(), 1);
+        let peers = &controller.sites[SITE].peers;
+        assert_eq!(peers["127.0.0.1:15441"].reputation(), 7);
+    }
+}
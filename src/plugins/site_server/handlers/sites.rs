@@ -1,19 +1,26 @@
-use actix::{Actor, Addr, Context, Handler, Message, ResponseActFuture};
+use std::collections::BTreeMap;
+
+use actix::{
+    Actor, Addr, AsyncContext, Context, Handler, Message, ResponseActFuture, ResponseFuture,
+};
 use bitcoin::hashes::hex::ToHex;
 use futures::{executor::block_on, future::join_all, FutureExt};
-use log::info;
+use log::{error, info};
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Number, Value};
-use zerucontent::Content;
+use zerucontent::{Content, File as ZFile};
 
 use crate::{
     controllers::sites::SitesController,
     core::{
         address::Address,
         error::Error,
+        peer::Peer,
         site::{models::SiteInfo, Site},
     },
+    discovery::{announcer::ANNOUNCE_TICK, pex::PEX_INTERVAL},
+    plugins::peer_db::PEER_SAVE_INTERVAL,
 };
 
 impl Actor for Site {
@@ -22,6 +29,17 @@ impl Actor for Site {
 
 impl Actor for SitesController {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(PEER_SAVE_INTERVAL, |act, _| act.save_all_peers());
+        ctx.run_interval(ANNOUNCE_TICK, |act, ctx| act.announce_due_sites(ctx));
+        ctx.run_interval(PEX_INTERVAL, |act, ctx| act.exchange_peers(ctx));
+        self.start_local_discovery(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.save_all_peers();
+    }
 }
 
 #[derive(Message)]
@@ -125,7 +143,9 @@ impl Handler<FileGetRequest> for Site {
     type Result = Result<bool, Error>;
 
     fn handle(&mut self, msg: FileGetRequest, _ctx: &mut Context<Self>) -> Self::Result {
-        let res = block_on(self.need_file(msg.inner_path, None, None));
+        let pool = self.peer_pool();
+        let res = block_on(self.need_file(msg.inner_path, None, &pool));
+        self.update_peer_stats(pool.peers());
         res
     }
 }
@@ -144,11 +164,117 @@ impl Handler<FileNeedRequest> for Site {
     type Result = Result<bool, Error>;
 
     fn handle(&mut self, msg: FileNeedRequest, _ctx: &mut Context<Self>) -> Self::Result {
-        let res = block_on(self.need_file(msg.inner_path, None, None));
+        let pool = self.peer_pool();
+        let res = block_on(self.need_file(msg.inner_path, None, &pool));
+        self.update_peer_stats(pool.peers());
         res
     }
 }
 
+/// Downloads optional file of site on demand and records it as downloaded,
+/// resolves to false if `inner_path` isn't an optional file.
+#[derive(Message)]
+#[rtype(result = "Result<bool, Error>")]
+pub struct OptionalFileNeedRequest {
+    pub address: String,
+    pub inner_path: String,
+}
+
+impl Handler<OptionalFileNeedRequest> for SitesController {
+    type Result = ResponseFuture<Result<bool, Error>>;
+
+    fn handle(&mut self, msg: OptionalFileNeedRequest, ctx: &mut Context<Self>) -> Self::Result {
+        let Some(mut site) = self.sites.get(&msg.address).cloned() else {
+            return Box::pin(async { Err(Error::SiteNotFound) });
+        };
+        let sites_controller = ctx.address();
+        // Downloaded in a task of its own, so actor isn't held up by peer I/O
+        let download = tokio::spawn(async move {
+            let OptionalFileNeedRequest {
+                address,
+                inner_path,
+            } = msg;
+            let pool = site.peer_pool();
+            let res = async {
+                // Optional files of user contents are only known once their content is loaded
+                site.load_parent_contents(&inner_path).await?;
+                let Some(file) = site.optional_file(&inner_path).cloned() else {
+                    return Ok(None);
+                };
+                if !site
+                    .need_file(inner_path.clone(), Some(file.clone()), &pool)
+                    .await?
+                {
+                    return Err(Error::Err(format!("Failed to download {inner_path}")));
+                }
+                Ok::<_, Error>(Some(file))
+            }
+            .await;
+            let downloaded = OptionalFileDownloaded {
+                address,
+                inner_path,
+                res,
+                peers: pool.peers(),
+            };
+            sites_controller.send(downloaded).await?
+        });
+        Box::pin(async move {
+            download
+                .await
+                .map_err(|err| Error::Err(format!("Optional file download failed : {err}")))?
+        })
+    }
+}
+
+/// Result of download started by [`OptionalFileNeedRequest`], with peers used for it.
+#[derive(Message)]
+#[rtype(result = "Result<bool, Error>")]
+struct OptionalFileDownloaded {
+    address: String,
+    inner_path: String,
+    /// Downloaded file, None if `inner_path` isn't an optional file.
+    res: Result<Option<ZFile>, Error>,
+    peers: Vec<Peer>,
+}
+
+impl Handler<OptionalFileDownloaded> for SitesController {
+    type Result = Result<bool, Error>;
+
+    fn handle(&mut self, msg: OptionalFileDownloaded, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(site) = self.sites.get_mut(&msg.address) {
+            site.update_peer_stats(msg.peers);
+        }
+        let Some(file) = msg.res? else {
+            return Ok(false);
+        };
+        self.optional_file_downloaded(&msg.address, &msg.inner_path, &file)?;
+        if let Err(err) = self.check_optional_limit() {
+            error!("Failed to check optional limit, Error : {err:?}");
+        }
+        Ok(true)
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OptionalFileAccessedRequest {
+    pub address: String,
+    pub inner_path: String,
+}
+
+impl Handler<OptionalFileAccessedRequest> for SitesController {
+    type Result = ();
+
+    fn handle(&mut self, msg: OptionalFileAccessedRequest, _ctx: &mut Context<Self>) {
+        if let Err(err) = self.optional_file_accessed(&msg.address, &msg.inner_path) {
+            error!(
+                "Failed to update access time of {}, Error : {err:?}",
+                msg.inner_path
+            );
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone, Message)]
 #[rtype(result = "Option<Value>")]
 pub struct FileRulesRequest {
@@ -164,6 +290,28 @@ impl Handler<FileRulesRequest> for Site {
     }
 }
 
+#[derive(Message)]
+#[rtype(result = "Result<(), Error>")]
+pub struct SiteVerifyCertRequest {
+    pub auth_address: String,
+    pub auth_type: String,
+    pub cert_user_id: String,
+    pub cert_sign: String,
+}
+
+impl Handler<SiteVerifyCertRequest> for Site {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: SiteVerifyCertRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        block_on(self.verify_user_cert(
+            &msg.auth_address,
+            &msg.auth_type,
+            &msg.cert_user_id,
+            &msg.cert_sign,
+        ))
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "Result<Content, Error>")]
 pub struct SiteContent(pub Option<String>);
@@ -285,25 +433,24 @@ impl Handler<DBQueryRequest> for SitesController {
     }
 }
 
+/// Bad files of site, along with why they were rejected if known.
 #[derive(Message)]
-#[rtype(result = "Vec<String>")]
+#[rtype(result = "BTreeMap<String, Option<String>>")]
 pub struct SiteBadFilesRequest {
     pub address: String,
 }
 
 impl Handler<SiteBadFilesRequest> for SitesController {
-    type Result = Vec<String>;
+    type Result = BTreeMap<String, Option<String>>;
 
     fn handle(&mut self, msg: SiteBadFilesRequest, _ctx: &mut Context<Self>) -> Self::Result {
         let site = self.sites.get(&msg.address).unwrap();
-        let res = site
-            .storage
-            .cache
+        let cache = &site.storage.cache;
+        cache
             .bad_files
             .keys()
-            .map(|x| x.to_string())
-            .collect();
-        res
+            .map(|x| (x.to_string(), cache.bad_file_errors.get(x).cloned()))
+            .collect()
     }
 }
 
@@ -2,6 +2,7 @@ use std::path::Path;
 
 use actix_web::{
     http::{header::HeaderMap, ConnectionType::KeepAlive},
+    web::Data,
     HttpRequest, HttpResponse, Responder,
 };
 use log::*;
@@ -10,7 +11,13 @@ use regex::Regex;
 use crate::{
     core::error::Error,
     environment::{DEF_MEDIA_PATH, ENV},
-    plugins::site_server::{common::*, error::*, file::serve_file},
+    plugins::site_server::{
+        common::*,
+        error::*,
+        file::serve_file,
+        handlers::sites::{OptionalFileAccessedRequest, OptionalFileNeedRequest},
+        server::ZeroServer,
+    },
 };
 
 pub async fn serve_sitemedia(
@@ -34,11 +41,45 @@ pub async fn serve_sitemedia(
         }
     }
     let (address, inner_path) = res.unwrap();
-    let file_path = &ENV.data_path.join(address).join(&inner_path);
+    let file_path = &ENV.data_path.join(&address).join(&inner_path);
+    let site_controller = req
+        .app_data::<Data<ZeroServer>>()
+        .map(|data| data.site_controller.clone());
+
+    if !file_path.exists() {
+        if file_path.ends_with("favicon.ico") || file_path.ends_with("apple-touch-icon.png") {
+            return serve_uimedia(req).await;
+        }
+        let Some(site_controller) = &site_controller else {
+            return error404(&req, Some(path));
+        };
+        let msg = OptionalFileNeedRequest {
+            address: address.clone(),
+            inner_path: inner_path.clone(),
+        };
+        match site_controller.send(msg).await {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => return error404(&req, Some(path)),
+            Ok(Err(err)) => {
+                error!("Failed to download optional file {inner_path}, Error : {err:?}");
+                return error404(&req, Some(path));
+            }
+            Err(err) => {
+                error!("{err:?}");
+                return error404(&req, Some(path));
+            }
+        }
+    }
 
     if file_path.is_dir() {
         redirect(&inner_path)
     } else if file_path.is_file() {
+        if let Some(site_controller) = site_controller {
+            site_controller.do_send(OptionalFileAccessedRequest {
+                address,
+                inner_path,
+            });
+        }
         return match serve_file(
             &req,
             file_path,
@@ -60,11 +101,7 @@ pub async fn serve_sitemedia(
             Err(_) => HttpResponse::BadRequest().finish(),
         };
     } else {
-        if file_path.ends_with("favicon.ico") || file_path.ends_with("apple-touch-icon.png") {
-            return serve_uimedia(req).await;
-        }
-        //TODO! Handle Missing Files
-        unimplemented!("Site Media File Not Exist")
+        error404(&req, Some(path))
     }
 }
 
@@ -0,0 +1,191 @@
+use std::{path::Path, time::Duration};
+
+use decentnet_protocol::{address::PeerAddr, templates::AnnouncePeers};
+use rusqlite::{params, Connection};
+use serde_bytes::ByteBuf;
+
+use crate::{
+    core::error::Error,
+    io::{db::DbManager, utils::current_unix_epoch},
+};
+
+/// Announced peers not re-announcing within this time are dropped.
+pub const TRACKER_PEER_EXPIRY: Duration = Duration::from_secs(40 * 60);
+/// How often expired peers are removed from tracker db.
+pub const TRACKER_CLEANUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Max peers of each address type returned per hash.
+pub const MAX_ANNOUNCE_PEERS: usize = 30;
+
+const TRACKER_DB_SCHEMA: &str = "
+PRAGMA foreign_keys = ON;
+CREATE TABLE IF NOT EXISTS peer (
+    peer_id INTEGER PRIMARY KEY ASC NOT NULL UNIQUE,
+    type TEXT NOT NULL,
+    address TEXT NOT NULL,
+    date_added INTEGER NOT NULL,
+    date_announced INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS peer_key ON peer (address);
+CREATE TABLE IF NOT EXISTS hash (
+    hash_id INTEGER PRIMARY KEY ASC NOT NULL UNIQUE,
+    hash BLOB NOT NULL,
+    date_added INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS hash_key ON hash (hash);
+CREATE TABLE IF NOT EXISTS peer_to_hash (
+    peer_id INTEGER REFERENCES peer (peer_id) ON DELETE CASCADE,
+    hash_id INTEGER REFERENCES hash (hash_id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX IF NOT EXISTS peer_to_hash_key ON peer_to_hash (peer_id, hash_id);
+CREATE INDEX IF NOT EXISTS peer_to_hash_hash ON peer_to_hash (hash_id);
+";
+
+/// Bootstrapper storage answering `announce` requests of other peers,
+/// keeps which peers announced which site hashes.
+pub struct Tracker {
+    conn: Connection,
+}
+
+impl Tracker {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::new(DbManager::connect_db_from_path(path)?)
+    }
+
+    pub fn new(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(TRACKER_DB_SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Address type as used in `need_types` and `add` fields of `announce`.
+    pub fn addr_type(addr: &PeerAddr) -> &'static str {
+        match addr {
+            PeerAddr::IPV4(_, _) => "ip4",
+            PeerAddr::IPV6(_, _) => "ipv6",
+            PeerAddr::OnionV2(_, _) | PeerAddr::OnionV3(_, _) => "onion",
+        }
+    }
+
+    /// Registers `add` peers for each of `hashes`, and returns up to `need_num` peers of each
+    /// of `need_types` per hash, in order of `hashes`. Added peers are not returned to themselves.
+    pub fn announce(
+        &mut self,
+        hashes: &[ByteBuf],
+        add: &[PeerAddr],
+        need_types: &[String],
+        need_num: usize,
+    ) -> Result<Vec<AnnouncePeers>, Error> {
+        let need_num = need_num.min(MAX_ANNOUNCE_PEERS);
+        let now = current_unix_epoch() as i64;
+        let expired = now - TRACKER_PEER_EXPIRY.as_secs() as i64;
+        let added = add.iter().map(|addr| addr.to_string()).collect::<Vec<_>>();
+        let tx = self.conn.transaction()?;
+        let mut peer_ids = vec![];
+        for (addr, key) in add.iter().zip(&added) {
+            tx.execute(
+                "INSERT INTO peer (type, address, date_added, date_announced) VALUES (?, ?, ?, ?)
+                ON CONFLICT (address) DO UPDATE SET date_announced = excluded.date_announced",
+                params![Self::addr_type(addr), key, now, now],
+            )?;
+            let peer_id: i64 = tx.query_row(
+                "SELECT peer_id FROM peer WHERE address = ?",
+                params![key],
+                |row| row.get(0),
+            )?;
+            peer_ids.push(peer_id);
+        }
+        let mut res = vec![];
+        for hash in hashes {
+            tx.execute(
+                "INSERT OR IGNORE INTO hash (hash, date_added) VALUES (?, ?)",
+                params![hash.as_slice(), now],
+            )?;
+            let hash_id: i64 = tx.query_row(
+                "SELECT hash_id FROM hash WHERE hash = ?",
+                params![hash.as_slice()],
+                |row| row.get(0),
+            )?;
+            for peer_id in &peer_ids {
+                tx.execute(
+                    "INSERT OR IGNORE INTO peer_to_hash (peer_id, hash_id) VALUES (?, ?)",
+                    params![peer_id, hash_id],
+                )?;
+            }
+            let mut peers = AnnouncePeers::default();
+            let mut stmt = tx.prepare(
+                "SELECT address FROM peer JOIN peer_to_hash USING (peer_id)
+                WHERE hash_id = ? AND type = ? AND date_announced > ? ORDER BY RANDOM() LIMIT ?",
+            )?;
+            for need_type in need_types {
+                let packed = match need_type.as_str() {
+                    "ip4" => &mut peers.ip4,
+                    "ipv6" => &mut peers.ipv6,
+                    "onion" => &mut peers.onion,
+                    _ => continue,
+                };
+                // Fetch extra rows in case requester itself is among results
+                let limit = need_num + added.len();
+                let rows = stmt
+                    .query_map(params![hash_id, need_type, expired, limit as i64], |row| {
+                        row.get::<_, String>(0)
+                    })?;
+                for address in rows {
+                    let address = address?;
+                    if packed.len() >= need_num || added.contains(&address) {
+                        continue;
+                    }
+                    if let Ok(addr) = PeerAddr::parse(address) {
+                        packed.push(ByteBuf::from(addr.pack()));
+                    }
+                }
+            }
+            res.push(peers);
+        }
+        tx.commit()?;
+        Ok(res)
+    }
+
+    /// Removes peers which didn't re-announce in time, along with hashes no peer announces.
+    pub fn cleanup(&mut self) -> Result<usize, Error> {
+        let expired = current_unix_epoch() as i64 - TRACKER_PEER_EXPIRY.as_secs() as i64;
+        let removed = self.conn.execute(
+            "DELETE FROM peer WHERE date_announced < ?",
+            params![expired],
+        )?;
+        self.conn.execute(
+            "DELETE FROM hash WHERE hash_id NOT IN (SELECT hash_id FROM peer_to_hash)",
+            [],
+        )?;
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use decentnet_protocol::address::PeerAddr;
+    use rusqlite::Connection;
+    use serde_bytes::ByteBuf;
+
+    use super::Tracker;
+
+    #[test]
+    fn test_announce() {
+        let mut tracker = Tracker::new(Connection::open_in_memory().unwrap()).unwrap();
+        let hashes = vec![ByteBuf::from(vec![1; 32])];
+        let need_types = vec!["ip4".to_string(), "ipv6".to_string()];
+        let first = PeerAddr::parse("127.0.0.1:15441".to_string()).unwrap();
+        let second = PeerAddr::parse("127.0.0.2:15441".to_string()).unwrap();
+
+        let res = tracker
+            .announce(&hashes, &[first.clone()], &need_types, 10)
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(res[0].ip4.is_empty());
+
+        let res = tracker
+            .announce(&hashes, &[second], &need_types, 10)
+            .unwrap();
+        assert_eq!(res[0].ip4, vec![ByteBuf::from(first.pack())]);
+        assert!(res[0].ipv6.is_empty());
+        assert_eq!(tracker.cleanup().unwrap(), 0);
+    }
+}
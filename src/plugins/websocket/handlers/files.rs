@@ -8,9 +8,20 @@ use serde_json::{json, Value};
 use super::super::{error::Error, request::Command, response::Message, ZeruWebsocket};
 use crate::{
     environment::ENV,
-    plugins::site_server::handlers::sites::{FileGetRequest, FileNeedRequest, FileRulesRequest},
+    plugins::site_server::handlers::sites::{
+        FileGetRequest, FileNeedRequest, FileRulesRequest, OptionalFileNeedRequest,
+    },
 };
 
+/// Downloads `inner_path` if it's an optional file, returns false for other files.
+fn need_optional_file(ws: &ZeruWebsocket, inner_path: &str) -> Result<bool, Error> {
+    let msg = OptionalFileNeedRequest {
+        address: ws.address.address.clone(),
+        inner_path: inner_path.to_string(),
+    };
+    Ok(block_on(ws.site_controller.send(msg))??)
+}
+
 pub fn handle_file_need(ws: &ZeruWebsocket, cmd: &Command) -> Result<Message, Error> {
     trace!("Handling FileNeed request");
     let msg: FileNeedRequest = match serde_json::from_value(cmd.params.clone()) {
@@ -20,7 +31,9 @@ pub fn handle_file_need(ws: &ZeruWebsocket, cmd: &Command) -> Result<Message, Er
             FileNeedRequest::default()
         }
     };
-    let _ = block_on(ws.site_addr.send(msg))?;
+    if !need_optional_file(ws, &msg.inner_path)? {
+        let _ = block_on(ws.site_addr.send(msg))?;
+    }
     cmd.respond("ok")
 }
 
@@ -39,8 +52,11 @@ pub fn handle_file_get(ws: &ZeruWebsocket, command: &Command) -> Result<Message,
     assert!(msg.format.is_empty() || msg.format == "text");
     assert!(msg.format != "base64");
     if !path.is_file() {
-        let res = block_on(ws.site_addr.send(msg))?;
-        if res.is_err() {
+        let downloaded = match need_optional_file(ws, &msg.inner_path) {
+            Ok(false) => block_on(ws.site_addr.send(msg))?.is_ok(),
+            res => res.is_ok(),
+        };
+        if !downloaded {
             return Err(Error {
                 error: String::from("File not found"),
             });
@@ -1,4 +1,5 @@
 pub mod files;
+pub mod optional;
 pub mod sites;
 pub mod tracker;
 pub mod users;
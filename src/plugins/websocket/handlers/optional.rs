@@ -0,0 +1,575 @@
+use std::collections::{BTreeMap, HashSet};
+
+use actix::{ActorFutureExt, AsyncContext, Context, Handler, ResponseActFuture};
+use futures::executor::block_on;
+use log::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::super::{error::Error, request::Command, response::Message, ZeruWebsocket};
+use crate::{
+    controllers::sites::SitesController,
+    core::error::Error as CoreError,
+    plugins::{optional_manager::GB, site_server::handlers::sites::OptionalFileNeedRequest},
+};
+
+/// Params are given as list or object of named params, or as single param.
+fn parse_params<T: DeserializeOwned>(params: &Value) -> Result<T, Error> {
+    let params = match params {
+        Value::Array(_) | Value::Object(_) => params.clone(),
+        param => json!([param]),
+    };
+    Ok(serde_json::from_value(params)?)
+}
+
+/// Site command runs on, managing optional files of other sites needs ADMIN permission.
+fn command_site(ws: &mut ZeruWebsocket, address: Option<String>) -> Result<String, Error> {
+    match address {
+        Some(address) if address != ws.address.address => {
+            if !ws.is_admin_site()? {
+                return Err(Error {
+                    error: format!("You don't have permission to access site {address}"),
+                });
+            }
+            Ok(address)
+        }
+        _ => Ok(ws.address.address.clone()),
+    }
+}
+
+fn check_admin(ws: &mut ZeruWebsocket, command: &Command) -> Result<(), Error> {
+    if !ws.is_admin_site()? {
+        let cmd = serde_json::to_string(&command.cmd)?.replace('"', "");
+        return Err(Error {
+            error: format!("You don't have permission to run {cmd}"),
+        });
+    }
+    Ok(())
+}
+
+fn notify(ws: &mut ZeruWebsocket, body: String) {
+    let _ = ws.cmd("notification", json!(["done", body, 5000]), None, None);
+}
+
+fn default_order_by() -> String {
+    "time_downloaded DESC".into()
+}
+
+fn default_list_limit() -> usize {
+    10
+}
+
+fn default_filter() -> String {
+    "downloaded".into()
+}
+
+#[derive(Deserialize)]
+struct FileListParams {
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default = "default_order_by")]
+    orderby: String,
+    #[serde(default = "default_list_limit")]
+    limit: usize,
+    #[serde(default = "default_filter")]
+    filter: String,
+    #[serde(default)]
+    filter_inner_path: Option<String>,
+}
+
+pub fn handle_optional_file_list(
+    ws: &mut ZeruWebsocket,
+    command: &Command,
+) -> Result<Message, Error> {
+    trace!("Handling OptionalFileList request");
+    let params: FileListParams = parse_params(&command.params)?;
+    // Files of all sites are only listed for admin sites
+    let address = match params.address {
+        Some(address) if address == "all" => {
+            check_admin(ws, command)?;
+            None
+        }
+        address => Some(command_site(ws, address)?),
+    };
+    let files = block_on(ws.site_controller.send(OptionalFileListRequest {
+        address,
+        filter: params.filter,
+        filter_inner_path: params.filter_inner_path,
+        order_by: params.orderby,
+        limit: params.limit,
+    }))??;
+    command.respond(files)
+}
+
+#[derive(Deserialize)]
+struct FileInfoParams {
+    inner_path: String,
+}
+
+pub fn handle_optional_file_info(ws: &ZeruWebsocket, command: &Command) -> Result<Message, Error> {
+    trace!("Handling OptionalFileInfo request");
+    let params: FileInfoParams = parse_params(&command.params)?;
+    let info = block_on(ws.site_controller.send(OptionalFileInfoRequest {
+        address: ws.address.address.clone(),
+        inner_path: params.inner_path,
+    }))??;
+    command.respond(info)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InnerPaths {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct FilePinParams {
+    inner_path: InnerPaths,
+    #[serde(default)]
+    address: Option<String>,
+}
+
+pub fn handle_optional_file_pin(
+    ws: &mut ZeruWebsocket,
+    command: &Command,
+    pinned: bool,
+) -> Result<Message, Error> {
+    trace!("Handling OptionalFilePin request, pinned : {pinned}");
+    let params: FilePinParams = parse_params(&command.params)?;
+    let address = command_site(ws, params.address)?;
+    let inner_paths = match params.inner_path {
+        InnerPaths::Single(inner_path) => vec![inner_path],
+        InnerPaths::Multiple(inner_paths) => inner_paths,
+    };
+    block_on(ws.site_controller.send(OptionalFilePinRequest {
+        address,
+        inner_paths: inner_paths.clone(),
+        pinned,
+    }))??;
+    let action = if pinned { "Pinned" } else { "Removed pin from" };
+    let body = match inner_paths.as_slice() {
+        [inner_path] => {
+            let file_name = inner_path.rsplit('/').next().unwrap_or_default();
+            format!("{action} {}", html_escape::encode_text(file_name))
+        }
+        inner_paths => format!("{action} {} files", inner_paths.len()),
+    };
+    notify(ws, body);
+    command.respond("ok")
+}
+
+#[derive(Deserialize)]
+struct FileDeleteParams {
+    inner_path: String,
+    #[serde(default)]
+    address: Option<String>,
+}
+
+pub fn handle_optional_file_delete(
+    ws: &mut ZeruWebsocket,
+    command: &Command,
+) -> Result<Message, Error> {
+    trace!("Handling OptionalFileDelete request");
+    let params: FileDeleteParams = parse_params(&command.params)?;
+    let address = command_site(ws, params.address)?;
+    let deleted = block_on(ws.site_controller.send(OptionalFileDeleteRequest {
+        address,
+        inner_path: params.inner_path.clone(),
+    }))??;
+    if !deleted {
+        return Err(Error {
+            error: format!("File not downloaded : {}", params.inner_path),
+        });
+    }
+    command.respond("ok")
+}
+
+pub fn handle_optional_limit_stats(
+    ws: &mut ZeruWebsocket,
+    command: &Command,
+) -> Result<Message, Error> {
+    trace!("Handling OptionalLimitStats request");
+    check_admin(ws, command)?;
+    let stats = block_on(ws.site_controller.send(OptionalLimitStatsRequest))??;
+    command.respond(stats)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Limit {
+    Number(usize),
+    Text(String),
+}
+
+#[derive(Deserialize)]
+struct LimitSetParams {
+    limit: Limit,
+}
+
+pub fn handle_optional_limit_set(
+    ws: &mut ZeruWebsocket,
+    command: &Command,
+) -> Result<Message, Error> {
+    trace!("Handling OptionalLimitSet request");
+    check_admin(ws, command)?;
+    let params: LimitSetParams = parse_params(&command.params)?;
+    let limit = match params.limit {
+        Limit::Number(limit) => Some(limit),
+        Limit::Text(limit) => limit.trim().parse().ok(),
+    };
+    let Some(limit) = limit else {
+        return Err(Error {
+            error: "Invalid limit, expected size in GB".into(),
+        });
+    };
+    block_on(ws.site_controller.send(OptionalLimitSetRequest { limit }))??;
+    command.respond("ok")
+}
+
+#[derive(Deserialize)]
+struct HelpListParams {
+    #[serde(default)]
+    address: Option<String>,
+}
+
+pub fn handle_optional_help_list(
+    ws: &mut ZeruWebsocket,
+    command: &Command,
+) -> Result<Message, Error> {
+    trace!("Handling OptionalHelpList request");
+    let params: HelpListParams = parse_params(&command.params)?;
+    let address = command_site(ws, params.address)?;
+    let help = block_on(ws.site_controller.send(OptionalHelpListRequest { address }))??;
+    command.respond(help)
+}
+
+#[derive(Deserialize)]
+struct HelpParams {
+    directory: String,
+    title: String,
+    #[serde(default)]
+    address: Option<String>,
+}
+
+pub fn handle_optional_help(ws: &mut ZeruWebsocket, command: &Command) -> Result<Message, Error> {
+    trace!("Handling OptionalHelp request");
+    let params: HelpParams = parse_params(&command.params)?;
+    let address = command_site(ws, params.address)?;
+    let stats = block_on(ws.site_controller.send(OptionalHelpRequest {
+        address,
+        directory: params.directory.clone(),
+        title: params.title.clone(),
+    }))??;
+    notify(
+        ws,
+        format!(
+            "You started to help distribute <b>{}</b>.<br><small>Directory: {}</small>",
+            html_escape::encode_text(&params.title),
+            html_escape::encode_text(&params.directory)
+        ),
+    );
+    command.respond(stats)
+}
+
+#[derive(Deserialize)]
+struct HelpRemoveParams {
+    directory: String,
+    #[serde(default)]
+    address: Option<String>,
+}
+
+pub fn handle_optional_help_remove(
+    ws: &mut ZeruWebsocket,
+    command: &Command,
+) -> Result<Message, Error> {
+    trace!("Handling OptionalHelpRemove request");
+    let params: HelpRemoveParams = parse_params(&command.params)?;
+    let address = command_site(ws, params.address)?;
+    let removed = block_on(ws.site_controller.send(OptionalHelpRemoveRequest {
+        address,
+        directory: params.directory,
+    }))??;
+    if !removed {
+        return Err(Error {
+            error: "Not found".into(),
+        });
+    }
+    command.respond("ok")
+}
+
+#[derive(Deserialize)]
+struct HelpAllParams {
+    value: bool,
+    #[serde(default)]
+    address: Option<String>,
+}
+
+pub fn handle_optional_help_all(
+    ws: &mut ZeruWebsocket,
+    command: &Command,
+) -> Result<Message, Error> {
+    trace!("Handling OptionalHelpAll request");
+    let params: HelpAllParams = parse_params(&command.params)?;
+    let address = command_site(ws, params.address)?;
+    block_on(ws.site_controller.send(OptionalHelpAllRequest {
+        address,
+        value: params.value,
+    }))??;
+    command.respond(params.value)
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<Vec<Value>, CoreError>")]
+pub struct OptionalFileListRequest {
+    /// Site to list files of, all sites if None.
+    pub address: Option<String>,
+    pub filter: String,
+    pub filter_inner_path: Option<String>,
+    pub order_by: String,
+    pub limit: usize,
+}
+
+impl Handler<OptionalFileListRequest> for SitesController {
+    type Result = Result<Vec<Value>, CoreError>;
+
+    fn handle(&mut self, msg: OptionalFileListRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        self.optional_file_list(
+            msg.address.as_deref(),
+            &msg.filter,
+            msg.filter_inner_path.as_deref(),
+            &msg.order_by,
+            msg.limit,
+        )
+    }
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<Option<Value>, CoreError>")]
+pub struct OptionalFileInfoRequest {
+    pub address: String,
+    pub inner_path: String,
+}
+
+impl Handler<OptionalFileInfoRequest> for SitesController {
+    type Result = Result<Option<Value>, CoreError>;
+
+    fn handle(&mut self, msg: OptionalFileInfoRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        self.optional_file_info(&msg.address, &msg.inner_path)
+    }
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<usize, CoreError>")]
+pub struct OptionalFilePinRequest {
+    pub address: String,
+    pub inner_paths: Vec<String>,
+    pub pinned: bool,
+}
+
+impl Handler<OptionalFilePinRequest> for SitesController {
+    type Result = Result<usize, CoreError>;
+
+    fn handle(&mut self, msg: OptionalFilePinRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        self.set_optional_files_pinned(&msg.address, &msg.inner_paths, msg.pinned)
+    }
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<bool, CoreError>")]
+pub struct OptionalFileDeleteRequest {
+    pub address: String,
+    pub inner_path: String,
+}
+
+impl Handler<OptionalFileDeleteRequest> for SitesController {
+    type Result = Result<bool, CoreError>;
+
+    fn handle(&mut self, msg: OptionalFileDeleteRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        self.remove_optional_file(&msg.address, &msg.inner_path)
+    }
+}
+
+/// Optional limit in GB, with size of downloaded optional files and room left within limit.
+#[derive(Serialize)]
+pub struct OptionalLimitStats {
+    pub limit: String,
+    pub used: u64,
+    pub free: u64,
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<OptionalLimitStats, CoreError>")]
+pub struct OptionalLimitStatsRequest;
+
+impl Handler<OptionalLimitStatsRequest> for SitesController {
+    type Result = Result<OptionalLimitStats, CoreError>;
+
+    fn handle(&mut self, _: OptionalLimitStatsRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        let used = self.optional_used()?;
+        Ok(OptionalLimitStats {
+            limit: (self.optional_limit / GB).to_string(),
+            used,
+            free: self.optional_limit.saturating_sub(used),
+        })
+    }
+}
+
+/// Sets optional limit in GB, evicting files over it.
+#[derive(actix::Message)]
+#[rtype(result = "Result<(), CoreError>")]
+pub struct OptionalLimitSetRequest {
+    pub limit: usize,
+}
+
+impl Handler<OptionalLimitSetRequest> for SitesController {
+    type Result = Result<(), CoreError>;
+
+    fn handle(&mut self, msg: OptionalLimitSetRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        self.save_optional_limit(msg.limit)?;
+        self.check_optional_limit()?;
+        Ok(())
+    }
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<BTreeMap<String, String>, CoreError>")]
+pub struct OptionalHelpListRequest {
+    pub address: String,
+}
+
+impl Handler<OptionalHelpListRequest> for SitesController {
+    type Result = Result<BTreeMap<String, String>, CoreError>;
+
+    fn handle(&mut self, msg: OptionalHelpListRequest, _ctx: &mut Context<Self>) -> Self::Result {
+        let site = self
+            .sites
+            .get(&msg.address)
+            .ok_or(CoreError::SiteNotFound)?;
+        Ok(site.storage.settings.optional_help.clone())
+    }
+}
+
+/// Number and total size of optional files in helped directory.
+#[derive(Serialize, Default)]
+pub struct OptionalHelpStats {
+    pub num: usize,
+    pub size: usize,
+}
+
+impl SitesController {
+    /// Downloads optional files of site in `directory` we don't have yet,
+    /// resolves to stats of all optional files in it.
+    fn help_optional_files(
+        &self,
+        address: String,
+        directory: String,
+    ) -> ResponseActFuture<Self, Result<OptionalHelpStats, CoreError>> {
+        let Some(site) = self.sites.get(&address).cloned() else {
+            return Box::pin(actix::fut::ready(Err(CoreError::SiteNotFound)));
+        };
+        let files = async move {
+            let files = site.optional_files().await?;
+            let mut stats = OptionalHelpStats::default();
+            let mut missing = HashSet::new();
+            for (inner_path, file) in files {
+                if !inner_path.starts_with(&directory) {
+                    continue;
+                }
+                stats.num += 1;
+                stats.size += file.size;
+                if !site.has_file(&inner_path, &file) {
+                    missing.insert(inner_path);
+                }
+            }
+            Ok::<_, CoreError>((stats, missing))
+        };
+        let wrapped = actix::fut::wrap_future::<_, Self>(files).map(move |res, _act, ctx| {
+            let (stats, missing) = res?;
+            debug!(
+                "Downloading {} helped optional files of {address}",
+                missing.len()
+            );
+            for inner_path in missing {
+                ctx.address().do_send(OptionalFileNeedRequest {
+                    address: address.clone(),
+                    inner_path,
+                });
+            }
+            Ok(stats)
+        });
+        Box::pin(wrapped)
+    }
+}
+
+/// Starts helping distribute optional files of `directory`, titled `title`.
+#[derive(actix::Message)]
+#[rtype(result = "Result<OptionalHelpStats, CoreError>")]
+pub struct OptionalHelpRequest {
+    pub address: String,
+    pub directory: String,
+    pub title: String,
+}
+
+impl Handler<OptionalHelpRequest> for SitesController {
+    type Result = ResponseActFuture<Self, Result<OptionalHelpStats, CoreError>>;
+
+    fn handle(&mut self, msg: OptionalHelpRequest, ctx: &mut Context<Self>) -> Self::Result {
+        let Some(site) = self.sites.get_mut(&msg.address) else {
+            return Box::pin(actix::fut::ready(Err(CoreError::SiteNotFound)));
+        };
+        let help = &mut site.storage.settings.optional_help;
+        help.insert(msg.directory.clone(), msg.title);
+        self.save_site_storage(&msg.address, ctx);
+        self.help_optional_files(msg.address, msg.directory)
+    }
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "Result<bool, CoreError>")]
+pub struct OptionalHelpRemoveRequest {
+    pub address: String,
+    pub directory: String,
+}
+
+impl Handler<OptionalHelpRemoveRequest> for SitesController {
+    type Result = Result<bool, CoreError>;
+
+    fn handle(&mut self, msg: OptionalHelpRemoveRequest, ctx: &mut Context<Self>) -> Self::Result {
+        let site = self
+            .sites
+            .get_mut(&msg.address)
+            .ok_or(CoreError::SiteNotFound)?;
+        let help = &mut site.storage.settings.optional_help;
+        let removed = help.remove(&msg.directory).is_some();
+        if removed {
+            self.save_site_storage(&msg.address, ctx);
+        }
+        Ok(removed)
+    }
+}
+
+/// Sets whether every optional file of site is downloaded.
+#[derive(actix::Message)]
+#[rtype(result = "Result<(), CoreError>")]
+pub struct OptionalHelpAllRequest {
+    pub address: String,
+    pub value: bool,
+}
+
+impl Handler<OptionalHelpAllRequest> for SitesController {
+    type Result = ResponseActFuture<Self, Result<(), CoreError>>;
+
+    fn handle(&mut self, msg: OptionalHelpAllRequest, ctx: &mut Context<Self>) -> Self::Result {
+        let Some(site) = self.sites.get_mut(&msg.address) else {
+            return Box::pin(actix::fut::ready(Err(CoreError::SiteNotFound)));
+        };
+        site.storage.settings.autodownloadoptional = msg.value;
+        self.save_site_storage(&msg.address, ctx);
+        if !msg.value {
+            return Box::pin(actix::fut::ready(Ok(())));
+        }
+        let help = self.help_optional_files(msg.address, String::new());
+        Box::pin(help.map(|res, _, _| res.map(|_| ())))
+    }
+}
@@ -2,7 +2,7 @@ use actix::AsyncContext;
 use actix_web_actors::ws::WebsocketContext;
 use futures::executor::block_on;
 use log::*;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::{json, Value};
 
 use super::{
@@ -13,7 +13,7 @@ use crate::{
     core::site::models::SiteInfo,
     environment::SITE_PERMISSIONS_DETAILS,
     plugins::site_server::handlers::{
-        sites::{DBQueryRequest, SiteInfoListRequest, SiteInfoRequest},
+        sites::{DBQueryRequest, SiteInfoListRequest, SiteInfoRequest, SiteVerifyCertRequest},
         users::{UserCertAddRequest, UserCertDeleteRequest, UserSetSiteCertRequest, UserSiteData},
     },
     plugins::{
@@ -33,6 +33,23 @@ pub fn handle_cert_add(ws: &mut ZeruWebsocket, command: &Command) -> Result<Mess
     let domain = msg.domain.clone();
     msg.user_addr = String::from("current");
     msg.site_addr = ws.address.address.clone();
+    let Some(auth_address) = get_current_user(ws)?.get_auth_address(&msg.site_addr, false) else {
+        return Err(Error {
+            error: String::from("No auth address for site"),
+        });
+    };
+    let verified = block_on(ws.site_addr.send(SiteVerifyCertRequest {
+        auth_address,
+        auth_type: msg.auth_type.clone(),
+        cert_user_id: format!("{}@{}", msg.auth_user_name, domain),
+        cert_sign: msg.cert.clone(),
+    }))?;
+    if let Err(err) = verified {
+        warn!("Rejected cert of {}@{domain} : {err}", msg.auth_user_name);
+        return Err(Error {
+            error: format!("Invalid cert : {err}"),
+        });
+    }
     let res = block_on(ws.user_controller.send(msg.clone()))?;
     match res {
         Err(_) => command.respond("Not changed"),
@@ -577,25 +594,3 @@ pub fn handle_permission_details(cmd: &Command) -> Result<Message, Error> {
         });
     cmd.respond(details?)
 }
-
-#[derive(Serialize)]
-pub struct OptionalLimitStats {
-    pub limit: String,
-    pub used: isize,
-    pub free: isize,
-}
-
-pub fn _handle_optional_limit_stats(
-    _: &ZeruWebsocket,
-    _: &mut WebsocketContext<ZeruWebsocket>,
-    command: &Command,
-) -> Result<Message, Error> {
-    // TODO: replace dummy response with actual response
-    warn!("Handling OptionalLimitStats with dummy response");
-    let limit_stats = OptionalLimitStats {
-        limit: String::from("10%"),
-        used: 1000000,
-        free: 4000000,
-    };
-    command.respond(limit_stats)
-}
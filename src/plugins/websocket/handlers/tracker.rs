@@ -1,18 +1,21 @@
 use std::collections::HashMap;
 
-use actix::Handler;
+use actix::{Handler, MessageResult, Recipient};
 use actix_web_actors::ws::WebsocketContext;
 use futures::executor::block_on;
 use log::*;
-use serde::{Deserialize, Serialize};
 
 use super::super::{
-    error::Error, events::EventType::AnnouncerInfo, request::Command, response::Message,
+    error::Error,
+    events::{EventType, ServerEvent},
+    request::Command,
+    response::Message,
     ZeruWebsocket,
 };
+pub use crate::discovery::announcer::AnnouncerStats;
 use crate::{
-    controllers::sites::SitesController,
-    core::{address::Address, site::Site},
+    controllers::sites::SitesController, core::address::Address,
+    discovery::announcer::AnnouncerChanged,
 };
 
 pub fn handle_announcer_info(
@@ -20,71 +23,94 @@ pub fn handle_announcer_info(
     _ctx: &mut WebsocketContext<ZeruWebsocket>,
     command: &Command,
 ) -> Result<Message, Error> {
-    warn!("Handling AnnouncerStats request");
+    trace!("Handling AnnouncerInfo request");
     let address = ws.address.address.clone();
-    command.respond(AnnouncerInfo {
-        address,
-        stats: HashMap::new(),
-    })
+    let stats = block_on(ws.site_controller.send(AnnouncerStatsRequest {
+        address: Some(address.clone()),
+    }))?;
+    command.respond(EventType::AnnouncerInfo { address, stats })
 }
 
 pub fn handle_announcer_stats(
-    _ws: &ZeruWebsocket,
+    ws: &ZeruWebsocket,
     _ctx: &mut WebsocketContext<ZeruWebsocket>,
     command: &Command,
 ) -> Result<Message, Error> {
-    warn!("Handling AnnouncerStats request with dummy response");
-    // TODO: actually return announcer stats
-    let mut stats: HashMap<String, _> = HashMap::new();
-    stats.insert(
-        String::from("zero://boot3rdez4rzn36x.onion:15441"),
-        AnnouncerStats {
-            status: String::from("announced"),
-            num_request: 0,
-            num_success: 0,
-            num_error: 0,
-            time_request: 0.0,
-            time_last_error: 0.0,
-            time_status: 0.0,
-            last_error: String::from("Not implemented yet"),
-        },
-    );
+    trace!("Handling AnnouncerStats request");
+    let stats = block_on(
+        ws.site_controller
+            .send(AnnouncerStatsRequest { address: None }),
+    )?;
     command.respond(stats)
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct AnnouncerStats {
-    pub status: String,
-    pub num_request: usize,
-    pub num_success: usize,
-    pub num_error: usize,
-    pub time_request: f64,
-    pub time_last_error: f64,
-    pub time_status: f64,
-    pub last_error: String,
+/// Stats of trackers for given site, or combined over all sites.
+#[derive(actix::Message)]
+#[rtype(result = "HashMap<String, AnnouncerStats>")]
+pub struct AnnouncerStatsRequest {
+    pub address: Option<String>,
+}
+
+impl Handler<AnnouncerStatsRequest> for SitesController {
+    type Result = MessageResult<AnnouncerStatsRequest>;
+
+    fn handle(&mut self, msg: AnnouncerStatsRequest, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(match msg.address {
+            Some(address) => self.announcer.site_stats(&address),
+            None => self.announcer.stats(),
+        })
+    }
 }
 
 #[derive(actix::Message)]
 #[rtype(result = "()")]
-pub struct SiteAnnounce {
-    pub address: Address,
+pub struct SubscribeAnnouncer {
+    pub recipient: Recipient<AnnouncerChanged>,
 }
 
-impl Handler<SiteAnnounce> for Site {
+impl Handler<SubscribeAnnouncer> for SitesController {
     type Result = ();
 
-    fn handle(&mut self, _msg: SiteAnnounce, _ctx: &mut Self::Context) -> Self::Result {
-        warn!("Handling SiteAnnounce request with dummy response");
-        let peers = block_on(self.find_peers()).unwrap();
-        self.add_peers(peers);
+    fn handle(&mut self, msg: SubscribeAnnouncer, _ctx: &mut Self::Context) -> Self::Result {
+        self.announcer_listeners.push(msg.recipient);
     }
 }
 
+impl Handler<AnnouncerChanged> for ZeruWebsocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: AnnouncerChanged, ctx: &mut Self::Context) -> Self::Result {
+        if msg.address != self.address.address
+            || !self.channels.contains(&"announcerChanged".to_string())
+        {
+            return;
+        }
+        let event = ServerEvent::Event {
+            cmd: "setAnnouncerInfo".into(),
+            id: self.next_message_id,
+            params: EventType::AnnouncerInfo {
+                address: msg.address,
+                stats: msg.stats,
+            },
+        };
+        self.next_message_id += 1;
+        match serde_json::to_string(&event) {
+            Ok(text) => ctx.text(text),
+            Err(err) => error!("Failed to serialize announcer event, Error : {err:?}"),
+        }
+    }
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct SiteAnnounce {
+    pub address: Address,
+}
+
 impl Handler<SiteAnnounce> for SitesController {
     type Result = ();
 
-    fn handle(&mut self, msg: SiteAnnounce, _ctx: &mut Self::Context) -> Self::Result {
-        let (_, site) = self.get(&msg.address).unwrap();
-        site.do_send(msg)
+    fn handle(&mut self, msg: SiteAnnounce, ctx: &mut Self::Context) -> Self::Result {
+        self.announce_site(&msg.address.address, true, ctx);
     }
 }
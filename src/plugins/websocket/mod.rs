@@ -20,7 +20,7 @@ use serde::{Deserialize, Serialize};
 
 use self::{
     events::{EventType, ServerEvent, WebsocketController},
-    handlers::{files::*, sites::*, tracker::*, users::*},
+    handlers::{files::*, optional::*, sites::*, tracker::*, users::*},
     request::{CommandResponse, CommandType},
 };
 use crate::{
@@ -28,6 +28,7 @@ use crate::{
     core::{address::Address, site::Site},
     environment::{Environment, ENV},
     header_name,
+    net::tor::{tor_status, OnionMode},
     plugins::site_server::{
         handlers::sites::{Lookup, SiteInfoRequest},
         server::ZeroServer,
@@ -41,7 +42,7 @@ use crate::{
     },
 };
 use error::Error;
-use request::{AdminCommandType::*, Command, UiServerCommandType::*};
+use request::{AdminCommandType::*, Command, PluginCommands::*, UiServerCommandType::*};
 use response::Message;
 
 pub fn register_site_plugins<T: AppEntryImpl>(app: App<T>) -> App<T> {
@@ -111,6 +112,9 @@ pub async fn serve_websocket(
     let (addr, res) = WsResponseBuilder::new(websocket, &req, stream)
         .start_with_addr()
         .unwrap();
+    data.site_controller.do_send(SubscribeAnnouncer {
+        recipient: addr.clone().recipient(),
+    });
     controller_data.do_send(RegisterWSClient { addr });
     Ok(res)
 }
@@ -258,8 +262,8 @@ fn server_info(ws: &mut ZeruWebsocket) -> Result<ServerInfo, Error> {
         platform: env.dist,
         fileserver_ip: env.fileserver_ip,
         fileserver_port: env.fileserver_port,
-        tor_enabled: false,
-        tor_status: String::from("Disabled"),
+        tor_enabled: env.tor_onions != OnionMode::Disabled,
+        tor_status: tor_status(),
         tor_has_meek_bridges: false,
         ui_ip: env.ui_ip,
         ui_port: env.ui_port,
@@ -461,12 +465,27 @@ impl ZeruWebsocket {
                     });
                 }
             }
+        } else if let CommandType::Plugin(cmd) = &command.cmd {
+            match cmd {
+                OptionalFileList => handle_optional_file_list(self, command),
+                OptionalFileInfo => handle_optional_file_info(self, command),
+                OptionalFilePin => handle_optional_file_pin(self, command, true),
+                OptionalFileUnpin => handle_optional_file_pin(self, command, false),
+                OptionalFileDelete => handle_optional_file_delete(self, command),
+                OptionalLimitStats => handle_optional_limit_stats(self, command),
+                OptionalLimitSet => handle_optional_limit_set(self, command),
+                OptionalHelpList => handle_optional_help_list(self, command),
+                OptionalHelp => handle_optional_help(self, command),
+                OptionalHelpRemove => handle_optional_help_remove(self, command),
+                OptionalHelpAll => handle_optional_help_all(self, command),
+                _ => {
+                    debug!("Unhandled Plugin command: {:?}", command.cmd);
+                    command.respond("ok")
+                }
+            }
         } else {
-            debug!("Unhandled Plugin command: {:?}", command.cmd);
+            debug!("Unhandled command: {:?}", command.cmd);
             command.respond("ok")
-            // return Err(Error {
-            //     error: "Unhandled Plugin command".to_string(),
-            // });
         };
         let mut msg = response?;
         if msg.is_command() || msg.is_inject_script() {
@@ -506,15 +525,11 @@ impl ZeruWebsocket {
                 }
                 self.send_event(EventType::ServerInfo(server_info))?;
             } else if listener == "announcerChanged" {
-                let address: String = params[0].as_str().unwrap().to_string();
-                let announcer_info = EventType::AnnouncerInfo {
-                    address,
-                    stats: HashMap::new(),
-                };
-                if !params.is_empty() {
-                    //TODO!: Implement handling of announcerChanged params
-                }
-                self.send_event(announcer_info)?;
+                let address = self.address.address.clone();
+                let stats = block_on(self.site_controller.send(AnnouncerStatsRequest {
+                    address: Some(address.clone()),
+                }))?;
+                self.send_event(EventType::AnnouncerInfo { address, stats })?;
             }
         }
         Ok(())
@@ -1,5 +1,8 @@
 use serde_json::{json, Value};
 
+use crate::core::error::Error;
+
+/// Diff actions turning `old` into `new`, kept and removed lengths are counted in bytes.
 pub fn calc_diff(old: &str, new: &str) -> Vec<Value> {
     let res = diff::chars(old, new);
     let mut diff = Vec::new();
@@ -8,7 +11,7 @@ pub fn calc_diff(old: &str, new: &str) -> Vec<Value> {
     let mut added_str = String::new();
     for diff_res in res {
         match diff_res {
-            diff::Result::Left(_) => {
+            diff::Result::Left(c) => {
                 if prev != "-" {
                     if prev == "+" {
                         diff.push(Value::Array(vec![json!(prev), json!(added_str)]));
@@ -19,9 +22,9 @@ pub fn calc_diff(old: &str, new: &str) -> Vec<Value> {
                     count = 0;
                 }
                 prev = "-";
-                count += 1;
+                count += c.len_utf8() as i32;
             }
-            diff::Result::Both(_, _) => {
+            diff::Result::Both(c, _) => {
                 if prev != "=" {
                     if prev == "+" {
                         diff.push(Value::Array(vec![json!(prev), json!(added_str)]));
@@ -32,7 +35,7 @@ pub fn calc_diff(old: &str, new: &str) -> Vec<Value> {
                     count = 0;
                 }
                 prev = "=";
-                count += 1;
+                count += c.len_utf8() as i32;
             }
             diff::Result::Right(c) => {
                 if prev != "+" && count > 0 {
@@ -53,6 +56,38 @@ pub fn calc_diff(old: &str, new: &str) -> Vec<Value> {
     diff
 }
 
+/// Rebuilds new file from `old` file and diff actions created by [`calc_diff`].
+/// Kept and removed lengths are byte counts, added text may be split into lines.
+pub fn apply_diff(old: &[u8], diff: &[Value]) -> Result<Vec<u8>, Error> {
+    let mut pos = 0;
+    let mut new = Vec::with_capacity(old.len());
+    for action in diff {
+        match (action.get(0).and_then(Value::as_str), action.get(1)) {
+            (Some(op @ ("=" | "-")), Some(Value::Number(count))) => {
+                let count = count.as_u64().unwrap_or_default() as usize;
+                let Some(part) = old.get(pos..pos.saturating_add(count)) else {
+                    return Err(Error::Err("Diff exceeds old file length".into()));
+                };
+                if op == "=" {
+                    new.extend_from_slice(part);
+                }
+                pos += count;
+            }
+            (Some("+"), Some(Value::String(added))) => new.extend_from_slice(added.as_bytes()),
+            (Some("+"), Some(Value::Array(lines))) => {
+                for line in lines {
+                    let Some(line) = line.as_str() else {
+                        return Err(Error::Err(format!("Invalid diff action : {action}")));
+                    };
+                    new.extend_from_slice(line.as_bytes());
+                }
+            }
+            _ => return Err(Error::Err(format!("Invalid diff action : {action}"))),
+        }
+    }
+    Ok(new)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,4 +130,54 @@ mod tests {
             vec![Value::Array(vec![json!("-"), json![15]])]
         );
     }
+
+    #[test]
+    fn test_apply_diff() {
+        let cases = [
+            ("", "one, two, three"),
+            ("one, two, three", "one, two, three, four, five"),
+            ("one, two, three, six", "one, two, three, four, five, six"),
+            (
+                "one, two, three, hmm, six",
+                "one, two, three, four, five, six",
+            ),
+            ("one, two, three", ""),
+            ("{\"ünïcode\": 1}", "{\"ünïcode\": 2}"),
+        ];
+        for (old, new) in cases {
+            let diff = calc_diff(old, new);
+            assert_eq!(apply_diff(old.as_bytes(), &diff).unwrap(), new.as_bytes());
+        }
+        assert!(apply_diff(b"one", &[json!(["=", 5])]).is_err());
+        assert!(apply_diff(b"one", &[json!(["*", 1])]).is_err());
+    }
+
+    #[test]
+    fn test_apply_diff_byte_offsets() {
+        // "ü" and "ï" are two bytes each, so offsets are past their char counts
+        let old = "{\"ünïcode\": 1, \"x\": 2}";
+        let diff = calc_diff(old, "{\"ünïcode\": 3, \"x\": 2}");
+        assert_eq!(
+            diff,
+            vec![
+                json!(["=", 14]),
+                json!(["-", 1]),
+                json!(["+", "3"]),
+                json!(["=", 9]),
+            ]
+        );
+        let new = apply_diff(
+            old.as_bytes(),
+            &[
+                json!(["=", 14]),
+                json!(["-", 1]),
+                json!(["+", ["3, ", "\"y\": 4"]]),
+                json!(["=", 9]),
+            ],
+        );
+        assert_eq!(
+            new.unwrap(),
+            "{\"ünïcode\": 3, \"y\": 4, \"x\": 2}".as_bytes()
+        );
+    }
 }